@@ -10,6 +10,7 @@ use clap::{
     crate_version,
 };
 use log::debug;
+use actix_web::http::Uri;
 use std::net::SocketAddr;
 use std::path::Path;
 use std::str::FromStr;
@@ -36,16 +37,19 @@ fn is_valid_filesystem_path(s: String) -> Result<(), String> {
         return Err("output.file-path must not point at a directory".to_owned());
     }
 
-    // Node Exporter textfiles must end with .prom
+    // Node Exporter textfiles must end with .prom, while InfluxDB line
+    // protocol files conventionally end with .lp
+    let extension_error = "output.file-path must have .prom or .lp extension";
+
     if let Some(ext) = path.extension() {
-        // Got an extension, ensure that it's .prom
-        if ext != "prom" {
-            return Err("output.file-path must have .prom extension".to_owned());
+        // Got an extension, ensure that it's .prom or .lp
+        if ext != "prom" && ext != "lp" {
+            return Err(extension_error.to_owned());
         }
     }
     else {
         // Didn't find an extension at all
-        return Err("output.file-path must have .prom extension".to_owned());
+        return Err(extension_error.to_owned());
     }
 
     // Check that the directory exists
@@ -63,10 +67,250 @@ fn is_valid_filesystem_path(s: String) -> Result<(), String> {
     Ok(())
 }
 
+// Basic checks for valid output directory
+fn is_valid_directory_path(s: String) -> Result<(), String> {
+    debug!("Ensuring that output.directory is valid");
+
+    let path = Path::new(&s);
+
+    // We only take absolute paths
+    if !path.is_absolute() {
+        return Err("output.directory only accepts absolute paths".to_owned());
+    }
+
+    // The directory must already exist
+    if !path.is_dir() {
+        return Err("output.directory must be an existing directory".to_owned());
+    }
+
+    Ok(())
+}
+
+// Checks that the given file mode is a valid octal permission string.
+fn is_valid_file_mode(s: String) -> Result<(), String> {
+    debug!("Ensuring that file mode is valid");
+
+    match u32::from_str_radix(&s, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(()),
+        _ => Err(format!("'{}' is not a valid octal file mode", s)),
+    }
+}
+
+// Checks that the given owner is either a numeric UID or the name of an
+// existing user.
+fn is_valid_file_owner(s: String) -> Result<(), String> {
+    debug!("Ensuring that output.file-owner is valid");
+
+    if s.parse::<u32>().is_ok() || users::get_user_by_name(&s).is_some() {
+        return Ok(());
+    }
+
+    Err(format!("'{}' is not a valid user", s))
+}
+
+// Checks that the given group is either a numeric GID or the name of an
+// existing group.
+fn is_valid_file_group(s: String) -> Result<(), String> {
+    debug!("Ensuring that file group is valid");
+
+    if s.parse::<u32>().is_ok() || users::get_group_by_name(&s).is_some() {
+        return Ok(());
+    }
+
+    Err(format!("'{}' is not a valid group", s))
+}
+
+// Returns a validator checking that the named argument is a positive
+// integer, such as an interval in seconds or a queue size.
+fn is_positive_integer(arg: &'static str)
+-> impl Fn(String) -> Result<(), String> {
+    move |s| {
+        debug!("Ensuring that {} is valid", arg);
+
+        match s.parse::<u64>() {
+            Ok(n) if n > 0 => Ok(()),
+            _ => Err(format!("{} must be a positive integer", arg)),
+        }
+    }
+}
+
+// Checks that the Carbon address is in the form HOST:PORT.
+fn is_valid_graphite_address(s: String) -> Result<(), String> {
+    debug!("Ensuring that graphite.address is valid");
+
+    is_valid_host_port(&s)
+}
+
+// Checks that the Graphite prefix is a non-empty metric path. Dots are
+// allowed, as the prefix may span several path components.
+fn is_valid_graphite_prefix(s: String) -> Result<(), String> {
+    debug!("Ensuring that graphite.prefix is valid");
+
+    if s.is_empty() {
+        return Err("graphite.prefix must not be empty".to_owned());
+    }
+
+    if s.starts_with('.') || s.ends_with('.') || s.contains("..") {
+        return Err("graphite.prefix must not have empty components".to_owned());
+    }
+
+    if s.contains(char::is_whitespace) {
+        return Err("graphite.prefix must not contain whitespace".to_owned());
+    }
+
+    Ok(())
+}
+
+// Checks that the OTLP collector URL is a valid http URL.
+fn is_valid_otlp_url(s: String) -> Result<(), String> {
+    debug!("Ensuring that otlp.url is valid");
+
+    is_valid_http_url("otlp.url", &s)
+}
+
+// Parses the given argument as an http URL.
+fn parse_http_url(arg: &str, s: &str) -> Result<Uri, String> {
+    let uri = match Uri::from_str(s) {
+        Ok(uri) => uri,
+        Err(_)  => return Err(format!("'{}' is not a valid URL", s)),
+    };
+
+    if uri.scheme_str() != Some("http") || uri.host().is_none() {
+        return Err(format!("{} must be an http:// URL", arg));
+    }
+
+    Ok(uri)
+}
+
+// Checks that the given argument is a valid http URL without a query string.
+fn is_valid_http_url(arg: &str, s: &str) -> Result<(), String> {
+    let uri = parse_http_url(arg, s)?;
+
+    if uri.query().is_some() {
+        return Err(format!("{} must not have a query string", arg));
+    }
+
+    Ok(())
+}
+
+// Checks that the InfluxDB write URL is a valid http URL. Unlike the other
+// URLs, a query string is allowed as it carries the org and bucket.
+fn is_valid_influx_url(s: String) -> Result<(), String> {
+    debug!("Ensuring that influx.url is valid");
+
+    parse_http_url("influx.url", &s).map(|_| ())
+}
+
+// Checks that the Pushgateway URL is a valid http URL.
+fn is_valid_push_url(s: String) -> Result<(), String> {
+    debug!("Ensuring that push.url is valid");
+
+    is_valid_http_url("push.url", &s)
+}
+
+// Checks that the remote write URL is a valid http URL.
+fn is_valid_remote_write_url(s: String) -> Result<(), String> {
+    debug!("Ensuring that remote-write.url is valid");
+
+    is_valid_http_url("remote-write.url", &s)
+}
+
+// Checks that the given string is in the form HOST:PORT. The host is only
+// resolved when we start sending.
+fn is_valid_host_port(s: &str) -> Result<(), String> {
+    let valid = match s.rfind(':') {
+        Some(i) => i > 0 && s[i + 1..].parse::<u16>().is_ok(),
+        None    => false,
+    };
+
+    if valid {
+        Ok(())
+    }
+    else {
+        Err(format!("'{}' is not a valid HOST:PORT string", s))
+    }
+}
+
+// Checks that the StatsD address is in the form HOST:PORT.
+fn is_valid_statsd_address(s: String) -> Result<(), String> {
+    debug!("Ensuring that statsd.address is valid");
+
+    is_valid_host_port(&s)
+}
+
+// Checks that a StatsD tag is non-empty and doesn't contain characters used
+// as separators in the DogStatsD format.
+fn is_valid_statsd_tag(s: String) -> Result<(), String> {
+    debug!("Ensuring that statsd.tag is valid");
+
+    if s.is_empty() {
+        return Err("statsd.tag must not be empty".to_owned());
+    }
+
+    if s.contains(&[',', '|', '#'][..]) {
+        return Err(format!("'{}' must not contain ',', '|' or '#'", s));
+    }
+
+    Ok(())
+}
+
+// Checks that the given string is an IPv4 or IPv6 network in CIDR notation,
+// or a single address.
+fn is_valid_cidr(s: String) -> Result<(), String> {
+    debug!("Ensuring that CIDR is valid");
+
+    s.parse::<crate::httpd::Cidr>().map(|_| ())
+}
+
+// Checks that the history size is a number of samples. 0 disables history.
+fn is_valid_web_history_size(s: String) -> Result<(), String> {
+    debug!("Ensuring that web.history-size is valid");
+
+    match s.parse::<usize>() {
+        Ok(_)  => Ok(()),
+        Err(_) => {
+            Err("web.history-size must be a non-negative integer".to_owned())
+        },
+    }
+}
+
+// Checks that the scrape timeout offset is a non-negative number of
+// seconds, which may be fractional.
+fn is_valid_web_scrape_timeout_offset(s: String) -> Result<(), String> {
+    debug!("Ensuring that web.scrape-timeout-offset is valid");
+
+    match s.parse::<f64>() {
+        Ok(offset) if offset.is_finite() && offset >= 0.0 => Ok(()),
+        _ => {
+            Err("web.scrape-timeout-offset must be a non-negative number"
+                .to_owned())
+        },
+    }
+}
+
 // Used as a validator for the argument parsing.
+// Accepts either ADDR:PORT or unix:PATH, where PATH is an absolute path in an
+// existing directory.
 fn is_valid_socket_addr(s: String) -> Result<(), String> {
     debug!("Ensuring that web.listen-address is valid");
 
+    let mut parts = s.splitn(2, ':');
+
+    if let (Some("unix"), Some(path)) = (parts.next(), parts.next()) {
+        let path = Path::new(path);
+
+        if !path.is_absolute() {
+            return Err("web.listen-address socket path must be absolute"
+                .to_owned());
+        }
+
+        return match path.parent() {
+            Some(dir) if dir.is_dir() => Ok(()),
+            _ => Err("web.listen-address socket directory must exist"
+                .to_owned()),
+        };
+    }
+
     match SocketAddr::from_str(&s) {
         Ok(_)  => Ok(()),
         Err(_) => Err(format!("'{}' is not a valid ADDR:PORT string", s)),
@@ -97,6 +341,24 @@ fn is_valid_telemetry_path(s: String) -> Result<(), String> {
     Ok(())
 }
 
+// Checks that a TLS certificate, key or web config path is an absolute path
+// to an existing file.
+fn is_valid_file(s: String) -> Result<(), String> {
+    debug!("Ensuring that file {} is valid", s);
+
+    let path = Path::new(&s);
+
+    if !path.is_absolute() {
+        return Err(format!("'{}' must be an absolute path", s));
+    }
+
+    if !path.is_file() {
+        return Err(format!("'{}' is not a file", s));
+    }
+
+    Ok(())
+}
+
 // Create a clap app
 fn create_app<'a, 'b>() -> clap::App<'a, 'b> {
     debug!("Creating clap app");
@@ -106,6 +368,143 @@ fn create_app<'a, 'b>() -> clap::App<'a, 'b> {
         .author(crate_authors!())
         .about(crate_description!())
         .set_term_width(80)
+        .group(
+            // Only one output type may be given, the other output arguments
+            // require one of these.
+            clap::ArgGroup::with_name("OUTPUT")
+                .args(&["OUTPUT_DIRECTORY", "OUTPUT_FILE_PATH"])
+        )
+        .arg(
+            clap::Arg::with_name("GRAPHITE_ADDRESS")
+                .env("JAIL_EXPORTER_GRAPHITE_ADDRESS")
+                .hide_env_values(true)
+                .long("graphite.address")
+                .value_name("HOST:PORT")
+                .help("Carbon server to send metrics to using the Graphite \
+                       plaintext protocol instead of running an HTTPd.")
+                .takes_value(true)
+                .conflicts_with_all(&[
+                    "OUTPUT",
+                    "PUSH_URL",
+                    "REMOTE_WRITE_URL",
+                    "STATSD_ADDRESS",
+                ])
+                .validator(is_valid_graphite_address)
+        )
+        .arg(
+            clap::Arg::with_name("GRAPHITE_HOST")
+                .env("JAIL_EXPORTER_GRAPHITE_HOST")
+                .hide_env_values(true)
+                .long("graphite.host")
+                .value_name("HOST")
+                .help("Host component of Graphite metric paths. \
+                       [default: hostname]")
+                .takes_value(true)
+                .requires("GRAPHITE_ADDRESS")
+        )
+        .arg(
+            clap::Arg::with_name("GRAPHITE_INTERVAL")
+                .env("JAIL_EXPORTER_GRAPHITE_INTERVAL")
+                .hide_env_values(true)
+                .long("graphite.interval")
+                .value_name("SECONDS")
+                .help("Interval between sending metrics to Carbon. \
+                       [default: 15]")
+                .takes_value(true)
+                .requires("GRAPHITE_ADDRESS")
+                .validator(is_positive_integer("graphite.interval"))
+        )
+        .arg(
+            clap::Arg::with_name("GRAPHITE_PREFIX")
+                .env("JAIL_EXPORTER_GRAPHITE_PREFIX")
+                .hide_env_values(true)
+                .long("graphite.prefix")
+                .value_name("PREFIX")
+                .help("Prefix of Graphite metric paths. \
+                       [default: jail_exporter]")
+                .takes_value(true)
+                .requires("GRAPHITE_ADDRESS")
+                .validator(is_valid_graphite_prefix)
+        )
+        .arg(
+            clap::Arg::with_name("INFLUX_INTERVAL")
+                .env("JAIL_EXPORTER_INFLUX_INTERVAL")
+                .hide_env_values(true)
+                .long("influx.interval")
+                .value_name("SECONDS")
+                .help("Interval between writes to InfluxDB. [default: 15]")
+                .takes_value(true)
+                .requires("INFLUX_URL")
+                .validator(is_positive_integer("influx.interval"))
+        )
+        .arg(
+            clap::Arg::with_name("INFLUX_TOKEN")
+                .env("JAIL_EXPORTER_INFLUX_TOKEN")
+                .hide_env_values(true)
+                .long("influx.token")
+                .value_name("TOKEN")
+                .help("API token to authenticate InfluxDB writes with.")
+                .takes_value(true)
+                .requires("INFLUX_URL")
+        )
+        .arg(
+            clap::Arg::with_name("INFLUX_URL")
+                .env("JAIL_EXPORTER_INFLUX_URL")
+                .hide_env_values(true)
+                .long("influx.url")
+                .value_name("URL")
+                .help("InfluxDB /api/v2/write URL to write metrics to \
+                       instead of running an HTTPd.")
+                .takes_value(true)
+                .conflicts_with_all(&[
+                    "GRAPHITE_ADDRESS",
+                    "OUTPUT",
+                    "PUSH_URL",
+                    "REMOTE_WRITE_URL",
+                    "STATSD_ADDRESS",
+                ])
+                .validator(is_valid_influx_url)
+        )
+        .arg(
+            clap::Arg::with_name("OTLP_INTERVAL")
+                .env("JAIL_EXPORTER_OTLP_INTERVAL")
+                .hide_env_values(true)
+                .long("otlp.interval")
+                .value_name("SECONDS")
+                .help("Interval between OTLP exports. [default: 15]")
+                .takes_value(true)
+                .requires("OTLP_URL")
+                .validator(is_positive_integer("otlp.interval"))
+        )
+        .arg(
+            clap::Arg::with_name("OTLP_URL")
+                .env("JAIL_EXPORTER_OTLP_URL")
+                .hide_env_values(true)
+                .long("otlp.url")
+                .value_name("URL")
+                .help("OpenTelemetry collector OTLP/HTTP metrics URL to \
+                       export metrics to instead of running an HTTPd.")
+                .takes_value(true)
+                .conflicts_with_all(&[
+                    "GRAPHITE_ADDRESS",
+                    "INFLUX_URL",
+                    "OUTPUT",
+                    "PUSH_URL",
+                    "REMOTE_WRITE_URL",
+                    "STATSD_ADDRESS",
+                ])
+                .validator(is_valid_otlp_url)
+        )
+        .arg(
+            clap::Arg::with_name("OUTPUT_DIRECTORY")
+                .env("JAIL_EXPORTER_OUTPUT_DIRECTORY")
+                .hide_env_values(true)
+                .long("output.directory")
+                .value_name("DIR")
+                .help("Directory to output one metrics file per jail to.")
+                .takes_value(true)
+                .validator(is_valid_directory_path)
+        )
         .arg(
             clap::Arg::with_name("OUTPUT_FILE_PATH")
                 .env("JAIL_EXPORTER_OUTPUT_FILE_PATH")
@@ -116,219 +515,1317 @@ fn create_app<'a, 'b>() -> clap::App<'a, 'b> {
                 .takes_value(true)
                 .validator(is_valid_filesystem_path)
         )
+        .arg(
+            clap::Arg::with_name("OUTPUT_FORMAT")
+                .env("JAIL_EXPORTER_OUTPUT_FORMAT")
+                .hide_env_values(true)
+                .long("output.format")
+                .value_name("FORMAT")
+                .help("Format to write the output file in. \
+                       [default: prometheus]")
+                .takes_value(true)
+                .possible_values(&["influx", "prometheus"])
+                .requires("OUTPUT_FILE_PATH")
+        )
+        .arg(
+            clap::Arg::with_name("OUTPUT_FSYNC")
+                .env("JAIL_EXPORTER_OUTPUT_FSYNC")
+                .hide_env_values(true)
+                .long("output.fsync")
+                .value_name("BOOL")
+                .help("Sync the output file and its directory to disk.")
+                .takes_value(true)
+                .min_values(0)
+                .max_values(1)
+                .require_equals(true)
+                .possible_values(&["true", "false"])
+                .requires("OUTPUT")
+        )
+        .arg(
+            clap::Arg::with_name("OUTPUT_INTERVAL")
+                .env("JAIL_EXPORTER_OUTPUT_INTERVAL")
+                .hide_env_values(true)
+                .long("output.interval")
+                .value_name("SECONDS")
+                .help("Refresh the output every SECONDS while also running \
+                       the HTTPd.")
+                .takes_value(true)
+                .requires("OUTPUT")
+                .validator(is_positive_integer("output.interval"))
+        )
+        .arg(
+            clap::Arg::with_name("OUTPUT_FILE_MODE")
+                .env("JAIL_EXPORTER_OUTPUT_FILE_MODE")
+                .hide_env_values(true)
+                .long("output.file-mode")
+                .value_name("MODE")
+                .help("Octal permissions to set on the output file.")
+                .takes_value(true)
+                .requires("OUTPUT")
+                .validator(is_valid_file_mode)
+        )
+        .arg(
+            clap::Arg::with_name("OUTPUT_FILE_OWNER")
+                .env("JAIL_EXPORTER_OUTPUT_FILE_OWNER")
+                .hide_env_values(true)
+                .long("output.file-owner")
+                .value_name("USER")
+                .help("User name or UID to own the output file.")
+                .takes_value(true)
+                .requires("OUTPUT")
+                .validator(is_valid_file_owner)
+        )
+        .arg(
+            clap::Arg::with_name("OUTPUT_FILE_GROUP")
+                .env("JAIL_EXPORTER_OUTPUT_FILE_GROUP")
+                .hide_env_values(true)
+                .long("output.file-group")
+                .value_name("GROUP")
+                .help("Group name or GID to own the output file.")
+                .takes_value(true)
+                .requires("OUTPUT")
+                .validator(is_valid_file_group)
+        )
+        .arg(
+            clap::Arg::with_name("PUSH_DELETE_ON_SHUTDOWN")
+                .env("JAIL_EXPORTER_PUSH_DELETE_ON_SHUTDOWN")
+                .hide_env_values(true)
+                .long("push.delete-on-shutdown")
+                .value_name("BOOL")
+                .help("Delete pushed metrics from the Pushgateway on \
+                       shutdown.")
+                .takes_value(true)
+                .min_values(0)
+                .max_values(1)
+                .require_equals(true)
+                .possible_values(&["true", "false"])
+                .requires("PUSH_URL")
+        )
+        .arg(
+            clap::Arg::with_name("PUSH_INSTANCE")
+                .env("JAIL_EXPORTER_PUSH_INSTANCE")
+                .hide_env_values(true)
+                .long("push.instance")
+                .value_name("INSTANCE")
+                .help("Instance grouping key to push metrics with.")
+                .takes_value(true)
+                .requires("PUSH_URL")
+        )
+        .arg(
+            clap::Arg::with_name("PUSH_INTERVAL")
+                .env("JAIL_EXPORTER_PUSH_INTERVAL")
+                .hide_env_values(true)
+                .long("push.interval")
+                .value_name("SECONDS")
+                .help("Interval between pushes. [default: 15]")
+                .takes_value(true)
+                .requires("PUSH_URL")
+                .validator(is_positive_integer("push.interval"))
+        )
+        .arg(
+            clap::Arg::with_name("PUSH_JOB")
+                .env("JAIL_EXPORTER_PUSH_JOB")
+                .hide_env_values(true)
+                .long("push.job")
+                .value_name("JOB")
+                .help("Job grouping key to push metrics with. \
+                       [default: jail_exporter]")
+                .takes_value(true)
+                .requires("PUSH_URL")
+        )
+        .arg(
+            clap::Arg::with_name("PUSH_URL")
+                .env("JAIL_EXPORTER_PUSH_URL")
+                .hide_env_values(true)
+                .long("push.url")
+                .value_name("URL")
+                .help("Pushgateway URL to push metrics to instead of running \
+                       an HTTPd.")
+                .takes_value(true)
+                .conflicts_with("OUTPUT")
+                .validator(is_valid_push_url)
+        )
+        .arg(
+            clap::Arg::with_name("REMOTE_WRITE_INTERVAL")
+                .env("JAIL_EXPORTER_REMOTE_WRITE_INTERVAL")
+                .hide_env_values(true)
+                .long("remote-write.interval")
+                .value_name("SECONDS")
+                .help("Interval between remote writes. [default: 15]")
+                .takes_value(true)
+                .requires("REMOTE_WRITE_URL")
+                .validator(is_positive_integer("remote-write.interval"))
+        )
+        .arg(
+            clap::Arg::with_name("REMOTE_WRITE_QUEUE_SIZE")
+                .env("JAIL_EXPORTER_REMOTE_WRITE_QUEUE_SIZE")
+                .hide_env_values(true)
+                .long("remote-write.queue-size")
+                .value_name("WRITES")
+                .help("Number of unsent remote writes to queue before \
+                       dropping the oldest. [default: 100]")
+                .takes_value(true)
+                .requires("REMOTE_WRITE_URL")
+                .validator(is_positive_integer("remote-write.queue-size"))
+        )
+        .arg(
+            clap::Arg::with_name("REMOTE_WRITE_URL")
+                .env("JAIL_EXPORTER_REMOTE_WRITE_URL")
+                .hide_env_values(true)
+                .long("remote-write.url")
+                .value_name("URL")
+                .help("Prometheus remote write URL to send metrics to \
+                       instead of running an HTTPd.")
+                .takes_value(true)
+                .conflicts_with_all(&["OUTPUT", "PUSH_URL"])
+                .validator(is_valid_remote_write_url)
+        )
+        .arg(
+            clap::Arg::with_name("STATSD_ADDRESS")
+                .env("JAIL_EXPORTER_STATSD_ADDRESS")
+                .hide_env_values(true)
+                .long("statsd.address")
+                .value_name("HOST:PORT")
+                .help("StatsD server to send metrics to over UDP instead of \
+                       running an HTTPd.")
+                .takes_value(true)
+                .conflicts_with_all(&["OUTPUT", "PUSH_URL", "REMOTE_WRITE_URL"])
+                .validator(is_valid_statsd_address)
+        )
+        .arg(
+            clap::Arg::with_name("STATSD_INTERVAL")
+                .env("JAIL_EXPORTER_STATSD_INTERVAL")
+                .hide_env_values(true)
+                .long("statsd.interval")
+                .value_name("SECONDS")
+                .help("Interval between sending metrics to StatsD. \
+                       [default: 15]")
+                .takes_value(true)
+                .requires("STATSD_ADDRESS")
+                .validator(is_positive_integer("statsd.interval"))
+        )
+        .arg(
+            clap::Arg::with_name("STATSD_TAG")
+                .env("JAIL_EXPORTER_STATSD_TAG")
+                .hide_env_values(true)
+                .long("statsd.tag")
+                .value_name("TAG")
+                .help("Static DogStatsD tag to add to every metric. May be \
+                       given multiple times.")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .requires("STATSD_ADDRESS")
+                .validator(is_valid_statsd_tag)
+        )
+        .arg(
+            clap::Arg::with_name("WEB_ALLOW_CIDR")
+                .env("JAIL_EXPORTER_WEB_ALLOW_CIDR")
+                .hide_env_values(true)
+                .long("web.allow-cidr")
+                .value_name("CIDR")
+                .help("Network, in CIDR notation, that clients must connect \
+                       from. Other clients receive a 403. May be given \
+                       multiple times.")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .validator(is_valid_cidr)
+        )
+        .arg(
+            clap::Arg::with_name("WEB_CONFIG_FILE")
+                .env("JAIL_EXPORTER_WEB_CONFIG_FILE")
+                .hide_env_values(true)
+                .long("web.config.file")
+                .value_name("PATH")
+                .help("Web configuration file in the Prometheus \
+                       exporter-toolkit format, enabling TLS and basic \
+                       authentication.")
+                .takes_value(true)
+                .conflicts_with_all(&[
+                    "WEB_TLS_CERT",
+                    "WEB_TLS_CLIENT_ALLOWED_NAME",
+                    "WEB_TLS_CLIENT_CA",
+                    "WEB_TLS_KEY",
+                ])
+                .validator(is_valid_file)
+        )
+        .arg(
+            clap::Arg::with_name("WEB_HISTORY_INTERVAL")
+                .env("JAIL_EXPORTER_WEB_HISTORY_INTERVAL")
+                .hide_env_values(true)
+                .long("web.history-interval")
+                .value_name("SECONDS")
+                .help("Interval between the collections made to record the \
+                       history, whether or not we're being scraped.")
+                .takes_value(true)
+                .default_value("15")
+                .validator(is_positive_integer("web.history-interval"))
+        )
+        .arg(
+            clap::Arg::with_name("WEB_HISTORY_RETENTION")
+                .env("JAIL_EXPORTER_WEB_HISTORY_RETENTION")
+                .hide_env_values(true)
+                .long("web.history-retention")
+                .value_name("SECONDS")
+                .help("How long samples of jail resource usage are kept for \
+                       /api/v1/history.")
+                .takes_value(true)
+                .default_value("3600")
+                .validator(is_positive_integer("web.history-retention"))
+        )
+        .arg(
+            clap::Arg::with_name("WEB_HISTORY_SIZE")
+                .env("JAIL_EXPORTER_WEB_HISTORY_SIZE")
+                .hide_env_values(true)
+                .long("web.history-size")
+                .value_name("SAMPLES")
+                .help("Maximum number of samples of jail resource usage kept \
+                       for /api/v1/history. 0 disables the history.")
+                .takes_value(true)
+                .default_value("250000")
+                .validator(is_valid_web_history_size)
+        )
         .arg(
             clap::Arg::with_name("WEB_LISTEN_ADDRESS")
                 .env("JAIL_EXPORTER_WEB_LISTEN_ADDRESS")
                 .hide_env_values(true)
                 .long("web.listen-address")
-                .value_name("[ADDR:PORT]")
-                .help("Address on which to expose metrics and web interface.")
+                .value_name("[ADDR:PORT|unix:PATH]")
+                .help("Address on which to expose metrics and web interface. \
+                       Use unix:PATH to listen on a Unix socket. May be given \
+                       multiple times.")
                 .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
                 .default_value("127.0.0.1:9452")
                 .validator(is_valid_socket_addr)
         )
         .arg(
-            clap::Arg::with_name("WEB_TELEMETRY_PATH")
-                .env("JAIL_EXPORTER_WEB_TELEMETRY_PATH")
+            clap::Arg::with_name("WEB_SCRAPE_TIMEOUT_ACTION")
+                .env("JAIL_EXPORTER_WEB_SCRAPE_TIMEOUT_ACTION")
                 .hide_env_values(true)
-                .long("web.telemetry-path")
-                .value_name("PATH")
-                .help("Path under which to expose metrics.")
+                .long("web.scrape-timeout-action")
+                .value_name("ACTION")
+                .help("How to answer a scrape when collection is abandoned \
+                       before Prometheus' scrape timeout. partial serves \
+                       the jails collected in time, unavailable answers \
+                       with a 503.")
                 .takes_value(true)
-                .default_value("/metrics")
-                .validator(is_valid_telemetry_path)
+                .default_value("partial")
+                .possible_values(&["partial", "unavailable"])
         )
-}
+        .arg(
+            clap::Arg::with_name("WEB_SCRAPE_TIMEOUT_OFFSET")
+                .env("JAIL_EXPORTER_WEB_SCRAPE_TIMEOUT_OFFSET")
+                .hide_env_values(true)
+                .long("web.scrape-timeout-offset")
+                .value_name("SECONDS")
+                .help("Time to leave before Prometheus' scrape timeout when \
+                       abandoning collection.")
+                .takes_value(true)
+                .default_value("0.5")
+                .validator(is_valid_web_scrape_timeout_offset)
+        )
+        .arg(
+            clap::Arg::with_name("WEB_SOCKET_GROUP")
+                .env("JAIL_EXPORTER_WEB_SOCKET_GROUP")
+                .hide_env_values(true)
+                .long("web.socket-group")
+                .value_name("GROUP")
+                .help("Group name or GID to own the Unix socket.")
+                .takes_value(true)
+                .validator(is_valid_file_group)
+        )
+        .arg(
+            clap::Arg::with_name("WEB_SOCKET_MODE")
+                .env("JAIL_EXPORTER_WEB_SOCKET_MODE")
+                .hide_env_values(true)
+                .long("web.socket-mode")
+                .value_name("MODE")
+                .help("Octal permissions to set on the Unix socket.")
+                .takes_value(true)
+                .validator(is_valid_file_mode)
+        )
+        .arg(
+            clap::Arg::with_name("WEB_STREAM_INTERVAL")
+                .env("JAIL_EXPORTER_WEB_STREAM_INTERVAL")
+                .hide_env_values(true)
+                .long("web.stream-interval")
+                .value_name("SECONDS")
+                .help("Interval between jail snapshots sent to clients of \
+                       /api/v1/stream.")
+                .takes_value(true)
+                .default_value("5")
+                .validator(is_positive_integer("web.stream-interval"))
+        )
+        .arg(
+            clap::Arg::with_name("WEB_TELEMETRY_PATH")
+                .env("JAIL_EXPORTER_WEB_TELEMETRY_PATH")
+                .hide_env_values(true)
+                .long("web.telemetry-path")
+                .value_name("PATH")
+                .help("Path under which to expose metrics.")
+                .takes_value(true)
+                .default_value("/metrics")
+                .validator(is_valid_telemetry_path)
+        )
+        .arg(
+            clap::Arg::with_name("WEB_TLS_CERT")
+                .env("JAIL_EXPORTER_WEB_TLS_CERT")
+                .hide_env_values(true)
+                .long("web.tls-cert")
+                .value_name("PATH")
+                .help("PEM certificate chain used to serve the HTTPd over \
+                       TLS. Reloaded on SIGHUP or when it changes.")
+                .takes_value(true)
+                .requires("WEB_TLS_KEY")
+                .validator(is_valid_file)
+        )
+        .arg(
+            clap::Arg::with_name("WEB_TLS_CLIENT_ALLOWED_NAME")
+                .env("JAIL_EXPORTER_WEB_TLS_CLIENT_ALLOWED_NAME")
+                .hide_env_values(true)
+                .long("web.tls-client-allowed-name")
+                .value_name("NAME")
+                .help("Common name or DNS subject alternative name a client \
+                       certificate must have. May be given multiple times.")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .requires("WEB_TLS_CLIENT_CA")
+        )
+        .arg(
+            clap::Arg::with_name("WEB_TLS_CLIENT_CA")
+                .env("JAIL_EXPORTER_WEB_TLS_CLIENT_CA")
+                .hide_env_values(true)
+                .long("web.tls-client-ca")
+                .value_name("PATH")
+                .help("PEM CA certificates that client certificates must be \
+                       signed by.")
+                .takes_value(true)
+                .requires("WEB_TLS_CERT")
+                .validator(is_valid_file)
+        )
+        .arg(
+            clap::Arg::with_name("WEB_TLS_KEY")
+                .env("JAIL_EXPORTER_WEB_TLS_KEY")
+                .hide_env_values(true)
+                .long("web.tls-key")
+                .value_name("PATH")
+                .help("PEM private key for web.tls-cert.")
+                .takes_value(true)
+                .requires("WEB_TLS_CERT")
+                .validator(is_valid_file)
+        )
+        .arg(
+            clap::Arg::with_name("WEB_TRUSTED_PROXY")
+                .env("JAIL_EXPORTER_WEB_TRUSTED_PROXY")
+                .hide_env_values(true)
+                .long("web.trusted-proxy")
+                .value_name("CIDR")
+                .help("Proxy address or network, in CIDR notation, whose \
+                       X-Forwarded-For header is used to find the client \
+                       address for web.allow-cidr. May be given multiple \
+                       times.")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .requires("WEB_ALLOW_CIDR")
+                .validator(is_valid_cidr)
+        )
+}
+
+// Returns whether a boolean argument is enabled. These may be given without
+// a value, or set to true or false, which allows them to be set from the
+// environment.
+pub fn is_enabled(matches: &clap::ArgMatches, name: &str) -> bool {
+    matches.is_present(name) && matches.value_of(name) != Some("false")
+}
+
+// Parses the command line arguments and returns the matches.
+// Checks that the Unix socket options are only given along with a unix:
+// listen address, as they would otherwise be ignored.
+fn check_socket_options(matches: &clap::ArgMatches) -> Result<(), String> {
+    let has_socket = match matches.values_of("WEB_LISTEN_ADDRESS") {
+        Some(mut addresses) => {
+            addresses.any(|address| address.starts_with("unix:"))
+        },
+        None => false,
+    };
+
+    if has_socket {
+        return Ok(());
+    }
+
+    let options = [
+        ("WEB_SOCKET_GROUP", "web.socket-group"),
+        ("WEB_SOCKET_MODE", "web.socket-mode"),
+    ];
+
+    for (name, option) in options.iter() {
+        if matches.is_present(name) {
+            return Err(format!(
+                "{} requires a unix:PATH web.listen-address",
+                option,
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+pub fn parse_args<'a>() -> clap::ArgMatches<'a> {
+    debug!("Parsing command line arguments");
+
+    let matches = create_app().get_matches();
+
+    if let Err(e) = check_socket_options(&matches) {
+        clap::Error::with_description(&e, clap::ErrorKind::ArgumentConflict)
+            .exit();
+    }
+
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lazy_static::lazy_static;
+    use pretty_assertions::assert_eq;
+    use std::env;
+    use std::panic;
+    use std::sync::Mutex;
+
+    lazy_static! {
+        // Used during env_tests
+        static ref LOCK: Mutex<i8> = Mutex::new(0);
+    }
+
+    // Wraps setting and unsetting of environment variables
+    fn env_test<T>(key: &str, var: &str, test: T) -> ()
+    where T: FnOnce() -> () + panic::UnwindSafe {
+        // This ensures that only one test can be manipulating the environment
+        // at a time.
+        let _locked = LOCK.lock().unwrap();
+
+        env::set_var(key, var);
+
+        let result = panic::catch_unwind(|| {
+            test()
+        });
+
+        env::remove_var(key);
+
+        assert!(result.is_ok())
+    }
+
+    #[test]
+    fn default_web_listen_address() {
+        // Must lock since we're still testing env vars here even though we're
+        // not setting one.
+        let _locked = LOCK.lock().unwrap();
+
+        let argv = vec!["jail_exporter"];
+        let matches = create_app().get_matches_from(argv);
+        let listen_address = matches.value_of("WEB_LISTEN_ADDRESS");
+
+        assert_eq!(listen_address, Some("127.0.0.1:9452"));
+    }
+
+    #[test]
+    fn default_web_telemetry_path() {
+        // Must lock since we're still testing env vars here even though we're
+        // not setting one.
+        let _locked = LOCK.lock().unwrap();
+
+        let argv = vec!["jail_exporter"];
+        let matches = create_app().get_matches_from(argv);
+        let telemetry_path = matches.value_of("WEB_TELEMETRY_PATH");
+
+        assert_eq!(telemetry_path, Some("/metrics"));
+    }
+
+    #[test]
+    fn cli_set_web_listen_address() {
+        let argv = vec![
+            "jail_exporter",
+            "--web.listen-address=127.0.1.2:9452",
+        ];
+
+        let matches = create_app().get_matches_from(argv);
+        let listen_address = matches.value_of("WEB_LISTEN_ADDRESS");
+
+        assert_eq!(listen_address, Some("127.0.1.2:9452"));
+    }
+
+    #[test]
+    fn cli_override_env_web_listen_address() {
+        env_test("JAIL_EXPORTER_WEB_LISTEN_ADDRESS", "127.0.1.2:9452", || {
+            let argv = vec![
+                "jail_exporter",
+                "--web.listen-address=127.0.1.3:9452",
+            ];
+
+            let matches = create_app().get_matches_from(argv);
+            let listen_address = matches.value_of("WEB_LISTEN_ADDRESS");
+
+            assert_eq!(listen_address, Some("127.0.1.3:9452"));
+        });
+    }
+
+    #[test]
+    fn cli_override_env_web_telemetry_path() {
+        env_test("JAIL_EXPORTER_WEB_TELEMETRY_PATH", "/envvar", || {
+            let argv = vec![
+                "jail_exporter",
+                "--web.telemetry-path=/clioverride",
+            ];
+
+            let matches = create_app().get_matches_from(argv);
+            let listen_address = matches.value_of("WEB_TELEMETRY_PATH");
+
+            assert_eq!(listen_address, Some("/clioverride"));
+        });
+    }
+
+    #[test]
+    fn cli_set_web_telemetry_path() {
+        let argv = vec![
+            "jail_exporter",
+            "--web.telemetry-path=/test",
+        ];
+
+        let matches = create_app().get_matches_from(argv);
+        let telemetry_path = matches.value_of("WEB_TELEMETRY_PATH");
+
+        assert_eq!(telemetry_path, Some("/test"));
+    }
+
+    #[test]
+    fn env_set_web_listen_address() {
+        env_test("JAIL_EXPORTER_WEB_LISTEN_ADDRESS", "127.0.1.2:9452", || {
+            let argv = vec!["jail_exporter"];
+            let matches = create_app().get_matches_from(argv);
+            let listen_address = matches.value_of("WEB_LISTEN_ADDRESS");
+
+            assert_eq!(listen_address, Some("127.0.1.2:9452"));
+        });
+    }
+
+    #[test]
+    fn env_set_web_telemetry_path() {
+        env_test("JAIL_EXPORTER_WEB_TELEMETRY_PATH", "/test", || {
+            let argv = vec!["jail_exporter"];
+            let matches = create_app().get_matches_from(argv);
+            let telemetry_path = matches.value_of("WEB_TELEMETRY_PATH");
+
+            assert_eq!(telemetry_path, Some("/test"));
+        });
+    }
+
+    #[test]
+    fn is_valid_filesystem_path_absolute_path() {
+        let res = is_valid_filesystem_path("tmp/metrics.prom".into());
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn is_valid_filesystem_path_bad_extension() {
+        let res = is_valid_filesystem_path("/tmp/metrics.pram".into());
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn is_valid_filesystem_path_bad_parent_dir() {
+        let res = is_valid_filesystem_path("/tmp/nope/metrics.prom".into());
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn is_valid_filesystem_path_directory() {
+        let res = is_valid_filesystem_path("/tmp".into());
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn is_valid_filesystem_path_line_protocol() {
+        let res = is_valid_filesystem_path("/tmp/metrics.lp".into());
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn is_valid_filesystem_path_no_extension() {
+        let res = is_valid_filesystem_path("/tmp/metrics".into());
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn is_valid_filesystem_path_ok() {
+        let res = is_valid_filesystem_path("/tmp/metrics.prom".into());
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn is_valid_filesystem_path_root() {
+        let res = is_valid_filesystem_path("/".into());
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn is_valid_filesystem_path_stdout() {
+        let res = is_valid_filesystem_path("-".into());
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn cli_output_directory_conflicts_with_file_path() {
+        let argv = vec![
+            "jail_exporter",
+            "--output.directory=/tmp",
+            "--output.file-path=/tmp/metrics.prom",
+        ];
+
+        let matches = create_app().get_matches_from_safe(argv);
+
+        assert!(matches.is_err());
+    }
+
+    #[test]
+    fn cli_set_output_directory_fsync() {
+        let argv = vec![
+            "jail_exporter",
+            "--output.directory=/tmp",
+            "--output.fsync",
+        ];
+
+        let matches = create_app().get_matches_from(argv);
+
+        assert!(is_enabled(&matches, "OUTPUT_FSYNC"));
+    }
+
+    #[test]
+    fn cli_set_output_fsync() {
+        let argv = vec![
+            "jail_exporter",
+            "--output.file-path=/tmp/metrics.prom",
+            "--output.fsync",
+        ];
+
+        let matches = create_app().get_matches_from(argv);
+
+        assert!(is_enabled(&matches, "OUTPUT_FSYNC"));
+    }
+
+    #[test]
+    fn cli_set_output_fsync_false() {
+        let argv = vec![
+            "jail_exporter",
+            "--output.file-path=/tmp/metrics.prom",
+            "--output.fsync=false",
+        ];
+
+        let matches = create_app().get_matches_from(argv);
+
+        assert!(!is_enabled(&matches, "OUTPUT_FSYNC"));
+    }
+
+    #[test]
+    fn cli_override_env_output_fsync() {
+        env_test("JAIL_EXPORTER_OUTPUT_FSYNC", "true", || {
+            let argv = vec![
+                "jail_exporter",
+                "--output.file-path=/tmp/metrics.prom",
+            ];
+
+            let matches = create_app().get_matches_from(argv);
+
+            assert!(is_enabled(&matches, "OUTPUT_FSYNC"));
+        });
+
+        env_test("JAIL_EXPORTER_OUTPUT_FSYNC", "false", || {
+            let argv = vec![
+                "jail_exporter",
+                "--output.file-path=/tmp/metrics.prom",
+            ];
+
+            let matches = create_app().get_matches_from(argv);
+
+            assert!(!is_enabled(&matches, "OUTPUT_FSYNC"));
+        });
+    }
+
+    #[test]
+    fn is_positive_integer_ok() {
+        let res = is_positive_integer("output.interval")("15".into());
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn is_positive_integer_zero() {
+        let res = is_positive_integer("output.interval")("0".into());
+        assert_eq!(
+            res,
+            Err("output.interval must be a positive integer".to_owned()),
+        );
+    }
+
+    #[test]
+    fn is_positive_integer_not_number() {
+        let res = is_positive_integer("push.interval")("soon".into());
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn is_valid_directory_path_file() {
+        let res = is_valid_directory_path("/tmp/nope/metrics.prom".into());
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn is_valid_directory_path_ok() {
+        let res = is_valid_directory_path("/tmp".into());
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn is_valid_directory_path_relative() {
+        let res = is_valid_directory_path("tmp".into());
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn is_valid_file_mode_ok() {
+        let res = is_valid_file_mode("0644".into());
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn is_valid_file_mode_not_octal() {
+        let res = is_valid_file_mode("0699".into());
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn is_valid_file_mode_too_large() {
+        let res = is_valid_file_mode("17777".into());
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn is_valid_file_owner_name() {
+        let res = is_valid_file_owner("root".into());
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn is_valid_file_owner_numeric() {
+        let res = is_valid_file_owner("65534".into());
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn is_valid_file_owner_unknown() {
+        let res = is_valid_file_owner("no_such_user_here".into());
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn is_valid_file_group_numeric() {
+        let res = is_valid_file_group("0".into());
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn is_valid_file_group_unknown() {
+        let res = is_valid_file_group("no_such_group_here".into());
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn cli_file_mode_requires_file_path() {
+        let argv = vec![
+            "jail_exporter",
+            "--output.file-mode=0644",
+        ];
+
+        let matches = create_app().get_matches_from_safe(argv);
+
+        assert!(matches.is_err());
+    }
+
+    #[test]
+    fn cli_web_config_file_conflicts_with_tls_cert() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let path = file.path().display();
+        let config = format!("--web.config.file={}", path);
+        let cert = format!("--web.tls-cert={}", path);
+        let key = format!("--web.tls-key={}", path);
+        let argv = vec!["jail_exporter", &config, &cert, &key];
+
+        let matches = create_app().get_matches_from_safe(argv);
+
+        assert!(matches.is_err());
+    }
+
+    #[test]
+    fn cli_web_allow_cidr_multiple() {
+        let argv = vec![
+            "jail_exporter",
+            "--web.allow-cidr=192.0.2.0/24",
+            "--web.allow-cidr=2001:db8::/32",
+        ];
+
+        let matches = create_app().get_matches_from(argv);
+        let cidrs: Vec<&str> = matches
+            .values_of("WEB_ALLOW_CIDR")
+            .unwrap()
+            .collect();
+
+        assert_eq!(cidrs, vec!["192.0.2.0/24", "2001:db8::/32"]);
+    }
+
+    #[test]
+    fn cli_web_trusted_proxy_requires_allow_cidr() {
+        let argv = vec![
+            "jail_exporter",
+            "--web.trusted-proxy=10.0.0.1",
+        ];
+
+        let matches = create_app().get_matches_from_safe(argv);
+
+        assert!(matches.is_err());
+    }
+
+    #[test]
+    fn cli_web_listen_address_multiple() {
+        let argv = vec![
+            "jail_exporter",
+            "--web.listen-address=127.0.0.1:9452",
+            "--web.listen-address=[::1]:9452",
+        ];
+
+        let matches = create_app().get_matches_from_safe(argv).unwrap();
+        let addresses: Vec<&str> = matches
+            .values_of("WEB_LISTEN_ADDRESS")
+            .unwrap()
+            .collect();
+
+        assert_eq!(addresses, vec!["127.0.0.1:9452", "[::1]:9452"]);
+    }
+
+    #[test]
+    fn cli_web_tls_client_allowed_name_requires_client_ca() {
+        let argv = vec![
+            "jail_exporter",
+            "--web.tls-client-allowed-name=prometheus",
+        ];
+
+        let matches = create_app().get_matches_from_safe(argv);
+
+        assert!(matches.is_err());
+    }
+
+    #[test]
+    fn cli_web_tls_client_ca_requires_cert() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let arg = format!("--web.tls-client-ca={}", file.path().display());
+        let argv = vec!["jail_exporter", &arg];
+
+        let matches = create_app().get_matches_from_safe(argv);
+
+        assert!(matches.is_err());
+    }
+
+    #[test]
+    fn cli_web_tls_cert_requires_key() {
+        let argv = vec![
+            "jail_exporter",
+            "--web.tls-cert=/etc/ssl/cert.pem",
+        ];
+
+        let matches = create_app().get_matches_from_safe(argv);
+
+        assert!(matches.is_err());
+    }
+
+    #[test]
+    fn cli_web_socket_mode_unix() {
+        let argv = vec![
+            "jail_exporter",
+            "--web.listen-address=unix:/tmp/jail_exporter.sock",
+            "--web.socket-mode=0660",
+        ];
+
+        let matches = create_app().get_matches_from_safe(argv).unwrap();
+
+        assert_eq!(
+            matches.value_of("WEB_LISTEN_ADDRESS"),
+            Some("unix:/tmp/jail_exporter.sock"),
+        );
+        assert_eq!(matches.value_of("WEB_SOCKET_MODE"), Some("0660"));
+    }
+
+    #[test]
+    fn cli_web_history_interval_default() {
+        let argv = vec!["jail_exporter"];
+
+        let matches = create_app().get_matches_from_safe(argv).unwrap();
+
+        assert_eq!(matches.value_of("WEB_HISTORY_INTERVAL"), Some("15"));
+    }
+
+    #[test]
+    fn cli_web_history_interval_zero() {
+        let argv = vec![
+            "jail_exporter",
+            "--web.history-interval=0",
+        ];
 
-// Parses the command line arguments and returns the matches.
-pub fn parse_args<'a>() -> clap::ArgMatches<'a> {
-    debug!("Parsing command line arguments");
+        let matches = create_app().get_matches_from_safe(argv);
 
-    create_app().get_matches()
-}
+        assert!(matches.is_err());
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use lazy_static::lazy_static;
-    use pretty_assertions::assert_eq;
-    use std::env;
-    use std::panic;
-    use std::sync::Mutex;
+    #[test]
+    fn cli_web_history_size_default() {
+        let argv = vec!["jail_exporter"];
 
-    lazy_static! {
-        // Used during env_tests
-        static ref LOCK: Mutex<i8> = Mutex::new(0);
+        let matches = create_app().get_matches_from_safe(argv).unwrap();
+
+        assert_eq!(matches.value_of("WEB_HISTORY_SIZE"), Some("250000"));
     }
 
-    // Wraps setting and unsetting of environment variables
-    fn env_test<T>(key: &str, var: &str, test: T) -> ()
-    where T: FnOnce() -> () + panic::UnwindSafe {
-        // This ensures that only one test can be manipulating the environment
-        // at a time.
-        let _locked = LOCK.lock().unwrap();
+    #[test]
+    fn cli_web_history_retention_default() {
+        let argv = vec!["jail_exporter"];
 
-        env::set_var(key, var);
+        let matches = create_app().get_matches_from_safe(argv).unwrap();
 
-        let result = panic::catch_unwind(|| {
-            test()
-        });
+        assert_eq!(matches.value_of("WEB_HISTORY_RETENTION"), Some("3600"));
+    }
 
-        env::remove_var(key);
+    #[test]
+    fn cli_web_history_retention_zero() {
+        let argv = vec![
+            "jail_exporter",
+            "--web.history-retention=0",
+        ];
 
-        assert!(result.is_ok())
+        let matches = create_app().get_matches_from_safe(argv);
+
+        assert!(matches.is_err());
     }
 
     #[test]
-    fn default_web_listen_address() {
-        // Must lock since we're still testing env vars here even though we're
-        // not setting one.
-        let _locked = LOCK.lock().unwrap();
+    fn is_valid_web_history_size_negative() {
+        let res = is_valid_web_history_size("-1".into());
+        assert!(res.is_err());
+    }
 
-        let argv = vec!["jail_exporter"];
-        let matches = create_app().get_matches_from(argv);
-        let listen_address = matches.value_of("WEB_LISTEN_ADDRESS");
+    #[test]
+    fn cli_web_scrape_timeout_action_unknown() {
+        let argv = vec![
+            "jail_exporter",
+            "--web.scrape-timeout-action=ignore",
+        ];
 
-        assert_eq!(listen_address, Some("127.0.0.1:9452"));
+        let matches = create_app().get_matches_from_safe(argv);
+
+        assert!(matches.is_err());
     }
 
     #[test]
-    fn default_web_telemetry_path() {
-        // Must lock since we're still testing env vars here even though we're
-        // not setting one.
-        let _locked = LOCK.lock().unwrap();
+    fn is_valid_web_scrape_timeout_offset_ok() {
+        assert!(is_valid_web_scrape_timeout_offset("0".into()).is_ok());
+        assert!(is_valid_web_scrape_timeout_offset("0.5".into()).is_ok());
+        assert!(is_valid_web_scrape_timeout_offset("-1".into()).is_err());
+        assert!(is_valid_web_scrape_timeout_offset("inf".into()).is_err());
+    }
 
+    #[test]
+    fn cli_web_stream_interval_default() {
         let argv = vec!["jail_exporter"];
-        let matches = create_app().get_matches_from(argv);
-        let telemetry_path = matches.value_of("WEB_TELEMETRY_PATH");
 
-        assert_eq!(telemetry_path, Some("/metrics"));
+        let matches = create_app().get_matches_from_safe(argv).unwrap();
+
+        assert_eq!(matches.value_of("WEB_STREAM_INTERVAL"), Some("5"));
     }
 
     #[test]
-    fn cli_set_web_listen_address() {
+    fn cli_graphite_address_conflicts_with_statsd_address() {
         let argv = vec![
             "jail_exporter",
-            "--web.listen-address=127.0.1.2:9452",
+            "--graphite.address=127.0.0.1:2003",
+            "--statsd.address=127.0.0.1:8125",
+        ];
+
+        let matches = create_app().get_matches_from_safe(argv);
+
+        assert!(matches.is_err());
+    }
+
+    #[test]
+    fn is_valid_graphite_address_ok() {
+        let res = is_valid_graphite_address("carbon.example.com:2003".into());
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn is_valid_graphite_prefix_dots() {
+        let res = is_valid_graphite_prefix("servers.jails".into());
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn is_valid_graphite_prefix_empty_component() {
+        let res = is_valid_graphite_prefix("servers..jails".into());
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn is_valid_graphite_prefix_whitespace() {
+        let res = is_valid_graphite_prefix("jail exporter".into());
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn cli_output_format_influx() {
+        let argv = vec![
+            "jail_exporter",
+            "--output.file-path=/tmp/metrics.lp",
+            "--output.format=influx",
         ];
 
         let matches = create_app().get_matches_from(argv);
-        let listen_address = matches.value_of("WEB_LISTEN_ADDRESS");
+        let format = matches.value_of("OUTPUT_FORMAT");
 
-        assert_eq!(listen_address, Some("127.0.1.2:9452"));
+        assert_eq!(format, Some("influx"));
     }
 
     #[test]
-    fn cli_override_env_web_listen_address() {
-        env_test("JAIL_EXPORTER_WEB_LISTEN_ADDRESS", "127.0.1.2:9452", || {
-            let argv = vec![
-                "jail_exporter",
-                "--web.listen-address=127.0.1.3:9452",
-            ];
+    fn cli_output_format_requires_file_path() {
+        let argv = vec![
+            "jail_exporter",
+            "--output.directory=/tmp",
+            "--output.format=influx",
+        ];
 
-            let matches = create_app().get_matches_from(argv);
-            let listen_address = matches.value_of("WEB_LISTEN_ADDRESS");
+        let matches = create_app().get_matches_from_safe(argv);
 
-            assert_eq!(listen_address, Some("127.0.1.3:9452"));
-        });
+        assert!(matches.is_err());
     }
 
     #[test]
-    fn cli_override_env_web_telemetry_path() {
-        env_test("JAIL_EXPORTER_WEB_TELEMETRY_PATH", "/envvar", || {
+    fn cli_output_format_unknown() {
+        let argv = vec![
+            "jail_exporter",
+            "--output.file-path=/tmp/metrics.prom",
+            "--output.format=json",
+        ];
+
+        let matches = create_app().get_matches_from_safe(argv);
+
+        assert!(matches.is_err());
+    }
+
+    #[test]
+    fn is_valid_influx_url_query() {
+        let res = is_valid_influx_url(
+            "http://127.0.0.1:8086/api/v2/write?org=a&bucket=b".into(),
+        );
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn cli_otlp_url_conflicts_with_influx_url() {
+        let argv = vec![
+            "jail_exporter",
+            "--influx.url=http://127.0.0.1:8086/api/v2/write",
+            "--otlp.url=http://127.0.0.1:4318/v1/metrics",
+        ];
+
+        let matches = create_app().get_matches_from_safe(argv);
+
+        assert!(matches.is_err());
+    }
+
+    #[test]
+    fn cli_push_job_requires_push_url() {
+        let argv = vec![
+            "jail_exporter",
+            "--push.job=jails",
+        ];
+
+        let matches = create_app().get_matches_from_safe(argv);
+
+        assert!(matches.is_err());
+    }
+
+    #[test]
+    fn cli_override_env_push_delete_on_shutdown() {
+        env_test("JAIL_EXPORTER_PUSH_DELETE_ON_SHUTDOWN", "true", || {
             let argv = vec![
                 "jail_exporter",
-                "--web.telemetry-path=/clioverride",
+                "--push.url=http://127.0.0.1:9091",
             ];
 
             let matches = create_app().get_matches_from(argv);
-            let listen_address = matches.value_of("WEB_TELEMETRY_PATH");
 
-            assert_eq!(listen_address, Some("/clioverride"));
+            assert!(is_enabled(&matches, "PUSH_DELETE_ON_SHUTDOWN"));
         });
     }
 
     #[test]
-    fn cli_set_web_telemetry_path() {
+    fn cli_set_push_delete_on_shutdown() {
         let argv = vec![
             "jail_exporter",
-            "--web.telemetry-path=/test",
+            "--push.delete-on-shutdown",
+            "--push.url=http://127.0.0.1:9091",
         ];
 
         let matches = create_app().get_matches_from(argv);
-        let telemetry_path = matches.value_of("WEB_TELEMETRY_PATH");
 
-        assert_eq!(telemetry_path, Some("/test"));
+        assert!(is_enabled(&matches, "PUSH_DELETE_ON_SHUTDOWN"));
     }
 
     #[test]
-    fn env_set_web_listen_address() {
-        env_test("JAIL_EXPORTER_WEB_LISTEN_ADDRESS", "127.0.1.2:9452", || {
-            let argv = vec!["jail_exporter"];
-            let matches = create_app().get_matches_from(argv);
-            let listen_address = matches.value_of("WEB_LISTEN_ADDRESS");
+    fn cli_push_url_conflicts_with_output() {
+        let argv = vec![
+            "jail_exporter",
+            "--output.file-path=/tmp/metrics.prom",
+            "--push.url=http://127.0.0.1:9091",
+        ];
 
-            assert_eq!(listen_address, Some("127.0.1.2:9452"));
-        });
+        let matches = create_app().get_matches_from_safe(argv);
+
+        assert!(matches.is_err());
     }
 
     #[test]
-    fn env_set_web_telemetry_path() {
-        env_test("JAIL_EXPORTER_WEB_TELEMETRY_PATH", "/test", || {
-            let argv = vec!["jail_exporter"];
-            let matches = create_app().get_matches_from(argv);
-            let telemetry_path = matches.value_of("WEB_TELEMETRY_PATH");
-
-            assert_eq!(telemetry_path, Some("/test"));
-        });
+    fn is_valid_push_url_https() {
+        let res = is_valid_push_url("https://127.0.0.1:9091".into());
+        assert!(res.is_err());
     }
 
     #[test]
-    fn is_valid_filesystem_path_absolute_path() {
-        let res = is_valid_filesystem_path("tmp/metrics.prom".into());
+    fn is_valid_push_url_no_scheme() {
+        let res = is_valid_push_url("127.0.0.1:9091".into());
         assert!(res.is_err());
     }
 
     #[test]
-    fn is_valid_filesystem_path_bad_extension() {
-        let res = is_valid_filesystem_path("/tmp/metrics.pram".into());
-        assert!(res.is_err());
+    fn is_valid_push_url_ok() {
+        let res = is_valid_push_url("http://127.0.0.1:9091".into());
+        assert!(res.is_ok());
     }
 
     #[test]
-    fn is_valid_filesystem_path_bad_parent_dir() {
-        let res = is_valid_filesystem_path("/tmp/nope/metrics.prom".into());
-        assert!(res.is_err());
+    fn cli_remote_write_url_conflicts_with_push_url() {
+        let argv = vec![
+            "jail_exporter",
+            "--push.url=http://127.0.0.1:9091",
+            "--remote-write.url=http://127.0.0.1:9090/api/v1/write",
+        ];
+
+        let matches = create_app().get_matches_from_safe(argv);
+
+        assert!(matches.is_err());
     }
 
     #[test]
-    fn is_valid_filesystem_path_directory() {
-        let res = is_valid_filesystem_path("/tmp".into());
-        assert!(res.is_err());
+    fn cli_remote_write_queue_size_requires_remote_write_url() {
+        let argv = vec![
+            "jail_exporter",
+            "--remote-write.queue-size=10",
+        ];
+
+        let matches = create_app().get_matches_from_safe(argv);
+
+        assert!(matches.is_err());
     }
 
     #[test]
-    fn is_valid_filesystem_path_no_extension() {
-        let res = is_valid_filesystem_path("/tmp/metrics".into());
-        assert!(res.is_err());
+    fn is_valid_remote_write_url_ok() {
+        let res = is_valid_remote_write_url(
+            "http://127.0.0.1:9090/api/v1/write".into(),
+        );
+        assert!(res.is_ok());
     }
 
     #[test]
-    fn is_valid_filesystem_path_ok() {
-        let res = is_valid_filesystem_path("/tmp/metrics.prom".into());
+    fn cli_statsd_tag_multiple() {
+        let argv = vec![
+            "jail_exporter",
+            "--statsd.address=127.0.0.1:8125",
+            "--statsd.tag=env:prod",
+            "--statsd.tag=role:jails",
+        ];
+
+        let matches = create_app().get_matches_from(argv);
+        let tags: Vec<_> = matches.values_of("STATSD_TAG").unwrap().collect();
+
+        assert_eq!(tags, vec!["env:prod", "role:jails"]);
+    }
+
+    #[test]
+    fn cli_statsd_tag_requires_statsd_address() {
+        let argv = vec![
+            "jail_exporter",
+            "--statsd.tag=env:prod",
+        ];
+
+        let matches = create_app().get_matches_from_safe(argv);
+
+        assert!(matches.is_err());
+    }
+
+    #[test]
+    fn is_valid_statsd_address_hostname() {
+        let res = is_valid_statsd_address("localhost:8125".into());
         assert!(res.is_ok());
     }
 
     #[test]
-    fn is_valid_filesystem_path_root() {
-        let res = is_valid_filesystem_path("/".into());
+    fn is_valid_statsd_address_no_port() {
+        let res = is_valid_statsd_address("localhost".into());
         assert!(res.is_err());
     }
 
     #[test]
-    fn is_valid_filesystem_path_stdout() {
-        let res = is_valid_filesystem_path("-".into());
-        assert!(res.is_ok());
+    fn is_valid_statsd_tag_separator() {
+        let res = is_valid_statsd_tag("env:a,b".into());
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn check_socket_options_unix() {
+        let argv = vec![
+            "jail_exporter",
+            "--web.listen-address=127.0.0.1:9452",
+            "--web.listen-address=unix:/tmp/jail_exporter.sock",
+            "--web.socket-mode=0660",
+        ];
+
+        let matches = create_app().get_matches_from(argv);
+
+        assert!(check_socket_options(&matches).is_ok());
+    }
+
+    #[test]
+    fn check_socket_options_without_unix() {
+        let argv = vec![
+            "jail_exporter",
+            "--web.socket-mode=0660",
+        ];
+
+        let matches = create_app().get_matches_from(argv);
+
+        assert_eq!(
+            check_socket_options(&matches),
+            Err("web.socket-mode requires a unix:PATH web.listen-address"
+                .to_owned()),
+        );
     }
 
     #[test]
@@ -361,6 +1858,24 @@ mod tests {
         assert!(res.is_err());
     }
 
+    #[test]
+    fn is_valid_socket_addr_unix() {
+        let res = is_valid_socket_addr("unix:/tmp/jail_exporter.sock".into());
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn is_valid_socket_addr_unix_bad_parent_dir() {
+        let res = is_valid_socket_addr("unix:/nonexistent/a.sock".into());
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn is_valid_socket_addr_unix_relative() {
+        let res = is_valid_socket_addr("unix:jail_exporter.sock".into());
+        assert!(res.is_err());
+    }
+
     #[test]
     fn is_valid_telemetry_path_slash() {
         let res = is_valid_telemetry_path("/".into());
@@ -384,4 +1899,25 @@ mod tests {
         let res = is_valid_telemetry_path("/metrics".into());
         assert!(res.is_ok());
     }
+
+    #[test]
+    fn is_valid_file_directory() {
+        let res = is_valid_file("/tmp".into());
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn is_valid_file_relative() {
+        let res = is_valid_file("cert.pem".into());
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn is_valid_file_ok() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let path = file.path().to_str().unwrap().to_owned();
+
+        let res = is_valid_file(path);
+        assert!(res.is_ok());
+    }
 }
@@ -20,6 +20,11 @@ pub enum ExporterError {
     #[error("failed to bind to {0}")]
     BindAddress(String),
 
+    /// Raised when a network given to `web.allow-cidr` or `web.trusted-proxy`
+    /// is invalid. Should not be reachable.
+    #[error("invalid network: {0}")]
+    InvalidCidr(String),
+
     /// Raised if an io::Error occurs
     #[error("std::io::Error")]
     IoError(#[from] std::io::Error),
@@ -28,6 +33,10 @@ pub enum ExporterError {
     #[error("could not get jail name")]
     JailError(jail::JailError),
 
+    /// Raised if there are errors originating within the `nix` crate.
+    #[error("system call failed: {0}")]
+    NixError(#[from] nix::Error),
+
     /// Raised if the jail_exporter is not running as root.
     #[error("jail_exporter must be run as root")]
     NotRunningAsRoot,
@@ -36,10 +45,27 @@ pub enum ExporterError {
     #[error("error occurred while persisting metrics")]
     PersistError(#[from] tempfile::PersistError),
 
+    /// Raised if a fractional argument fails to parse.
+    #[error("failed to parse number")]
+    ParseFloatError(#[from] std::num::ParseFloatError),
+
+    /// Raised if a numeric argument fails to parse.
+    #[error("failed to parse integer")]
+    ParseIntError(#[from] std::num::ParseIntError),
+
+    /// Raised if sending metrics over HTTP to the Pushgateway, InfluxDB or
+    /// an OTLP collector fails, or deleting them from the Pushgateway does.
+    #[error("failed to push metrics: {0}")]
+    PushError(String),
+
     /// Raised if there are errors originating within the `prometheus` crate.
     #[error("error within Prometheus library")]
     PrometheusError(#[from] prometheus::Error),
 
+    /// Raised if metrics can't be encoded for the remote write endpoint.
+    #[error("failed to encode remote write request: {0}")]
+    RemoteWriteError(String),
+
     /// Raised if there are issues with RACCT/RCTL support.
     #[error("RACCT/RCTL: {0}")]
     RctlUnavailable(String),
@@ -48,6 +74,24 @@ pub enum ExporterError {
     #[error("Failed to render template")]
     RenderTemplate(#[from] askama::Error),
 
+    /// Raised if the TLS certificate or key given to `web.tls-cert` and
+    /// `web.tls-key` can't be loaded.
+    #[error("failed to load TLS certificate: {0}")]
+    TlsError(String),
+
+    /// Raised if a group given to `output.file-group` does not exist.
+    #[error("unknown group: {0}")]
+    UnknownGroup(String),
+
+    /// Raised if a user given to `output.file-owner` does not exist.
+    #[error("unknown user: {0}")]
+    UnknownUser(String),
+
+    /// Raised if the file given to `web.config.file` can't be read or is
+    /// invalid.
+    #[error("invalid web config file: {0}")]
+    WebConfigError(String),
+
     /// Raised if there's an issue converting from UTF-8 to String
     #[error("Failed to convert UTF-8 to String")]
     Utf8Error(#[from] std::string::FromUtf8Error),
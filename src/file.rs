@@ -1,26 +1,80 @@
 // File exporter
 #![forbid(unsafe_code)]
 #![forbid(missing_docs)]
-use crate::errors::ExporterError;
-use jail_exporter::Exporter;
-use log::debug;
+use crate::influx;
+use jail_exporter::{
+    encode,
+    jail_name,
+    Exporter,
+    ExporterError,
+};
+use actix_rt::time;
+use actix_web::error::BlockingError;
+use actix_web::web;
+use log::{
+    debug,
+    error,
+};
+use nix::unistd::{
+    chown,
+    Gid,
+    Uid,
+};
+use prometheus::proto::{
+    Metric,
+    MetricFamily,
+};
+use std::collections::{
+    BTreeMap,
+    HashSet,
+};
+use std::fs::{
+    self,
+    File,
+    Permissions,
+};
 use std::io::{
     self,
     Write,
 };
+use std::os::unix::fs::PermissionsExt;
 use std::path::{
     Path,
     PathBuf,
 };
+use std::sync::Arc;
+use std::time::Duration;
 use tempfile::NamedTempFile;
 
+// Name of the file holding metrics without a jail name label when writing to
+// an output directory.
+const GLOBAL_FILE_NAME: &str = "jail_exporter.prom";
+
+// Metric families belonging to each jail, keyed on jail name.
+type JailMetricFamilies = BTreeMap<String, Vec<MetricFamily>>;
+
 enum Output {
+    Directory(PathBuf),
     File(PathBuf),
     Stdout,
 }
 
+// Formats that metrics can be written in.
+#[derive(Debug)]
+pub enum Format {
+    Influx,
+    Prometheus,
+}
+
 pub struct FileExporter {
-    dest: Output,
+    dest:   Output,
+    format: Format,
+    fsync:  bool,
+    group:  Option<u32>,
+    mode:   Option<u32>,
+    owner:  Option<u32>,
+    // Syncs a file to disk when fsync is enabled, replaced in tests.
+    sync:   fn(&File) -> io::Result<()>,
 }
 
 impl FileExporter {
@@ -38,50 +92,530 @@ impl FileExporter {
             Output::File(path.into())
         };
 
+        Self::with_output(output)
+    }
+
+    // Returns a FileExporter writing one file per jail into the given
+    // directory.
+    pub fn new_directory(path: &str) -> Self {
+        debug!("New FileExporter outputting to directory {}", path);
+
+        let path = Path::new(&path);
+        Self::with_output(Output::Directory(path.into()))
+    }
+
+    fn with_output(output: Output) -> Self {
         Self {
-            dest: output,
+            dest:   output,
+            format: Format::Prometheus,
+            fsync:  false,
+            group:  None,
+            mode:   None,
+            owner:  None,
+            sync:   File::sync_all,
         }
     }
 
+    // Sets the format metrics are written in. Output directories are always
+    // written in the Prometheus format, as they're intended for the Node
+    // Exporter.
+    pub fn format(mut self, format: Format) -> Self {
+        debug!("Setting FileExporter format to: {:?}", format);
+
+        self.format = format;
+        self
+    }
+
+    // Sets whether the output file and its directory are synced to disk.
+    pub fn fsync(mut self, fsync: bool) -> Self {
+        debug!("Setting FileExporter fsync to: {}", fsync);
+
+        self.fsync = fsync;
+        self
+    }
+
+    // Sets the GID that will own the output file.
+    pub fn group(mut self, group: u32) -> Self {
+        debug!("Setting FileExporter group to: {}", group);
+
+        self.group = Some(group);
+        self
+    }
+
+    // Sets the permissions of the output file.
+    pub fn mode(mut self, mode: u32) -> Self {
+        debug!("Setting FileExporter mode to: {:o}", mode);
+
+        self.mode = Some(mode);
+        self
+    }
+
+    // Sets the UID that will own the output file.
+    pub fn owner(mut self, owner: u32) -> Self {
+        debug!("Setting FileExporter owner to: {}", owner);
+
+        self.owner = Some(owner);
+        self
+    }
+
+    // Applies the configured mode and ownership to the temporary file before
+    // it is persisted, so the final file never appears with the wrong
+    // permissions.
+    fn set_permissions(&self, file: &NamedTempFile)
+    -> Result<(), ExporterError> {
+        if let Some(mode) = self.mode {
+            debug!("Setting mode {:o} on {:?}", mode, file.path());
+
+            let permissions = Permissions::from_mode(mode);
+            file.as_file().set_permissions(permissions)?;
+        }
+
+        if self.owner.is_some() || self.group.is_some() {
+            debug!(
+                "Setting owner {:?} and group {:?} on {:?}",
+                self.owner,
+                self.group,
+                file.path(),
+            );
+
+            let owner = self.owner.map(Uid::from_raw);
+            let group = self.group.map(Gid::from_raw);
+            chown(file.path(), owner, group)?;
+        }
+
+        Ok(())
+    }
+
+    // Writes metrics to the given path. The metrics are first written to a
+    // temporary file which is then renamed into place, so readers never see
+    // a partially written file.
+    fn persist(&self, path: &Path, metrics: &[u8])
+    -> Result<(), ExporterError> {
+        debug!("Writing metrics to {:?}", path);
+
+        // We already vetted the parent in the CLI validator, so unwrap
+        // here should be fine.
+        let parent = path.parent().unwrap();
+
+        // We do this since we need the temporary file to be on the
+        // same filesystem as the final persisted file.
+        let mut file = NamedTempFile::new_in(&parent)?;
+        file.write_all(metrics)?;
+        self.set_permissions(&file)?;
+
+        // Ensure the data is on disk before the rename, otherwise a
+        // crash could leave us with an empty file.
+        if self.fsync {
+            debug!("Syncing {:?}", file.path());
+            (self.sync)(file.as_file())?;
+        }
+
+        file.persist(&path)?;
+
+        // Ensure the rename itself is on disk.
+        if self.fsync {
+            debug!("Syncing {:?}", parent);
+            (self.sync)(&File::open(&parent)?)?;
+        }
+
+        Ok(())
+    }
+
+    // Encodes metrics in the configured format.
+    fn encode(&self, metric_families: &[MetricFamily])
+    -> Result<Vec<u8>, ExporterError> {
+        match self.format {
+            Format::Influx => {
+                Ok(influx::encode(metric_families, influx::timestamp()))
+            },
+            Format::Prometheus => encode(metric_families),
+        }
+    }
+
+    // Writes the global metrics and the metrics of each jail to their own
+    // files within dir, then removes the files of jails that have gone away.
+    fn write_directory(&self, dir: &Path, metric_families: &[MetricFamily])
+    -> Result<(), ExporterError> {
+        debug!("Writing metrics to directory {:?}", dir);
+
+        let (global, jails) = split_by_jail(metric_families);
+
+        let metrics = encode(&global)?;
+        self.persist(&dir.join(GLOBAL_FILE_NAME), &metrics)?;
+
+        let mut written = HashSet::new();
+
+        for (name, metric_families) in jails {
+            let file_name = jail_file_name(&name);
+            let metrics = encode(&metric_families)?;
+
+            self.persist(&dir.join(&file_name), &metrics)?;
+            written.insert(file_name);
+        }
+
+        reap_files(dir, &written)
+    }
+
     // Handles choosing the correct output type based on path
-    fn write(&self, metrics: Vec<u8>) -> Result<(), ExporterError> {
+    fn write(&self, metric_families: &[MetricFamily])
+    -> Result<(), ExporterError> {
         match &self.dest {
+            Output::Directory(path) => {
+                self.write_directory(path, metric_families)?;
+            },
+            Output::File(path) => {
+                let metrics = self.encode(metric_families)?;
+                self.persist(path, &metrics)?;
+            },
             Output::Stdout => {
                 debug!("Writing metrics to stdout");
 
+                let metrics = self.encode(metric_families)?;
                 io::stdout().write_all(&metrics)?;
             },
-            Output::File(path) => {
-                debug!("Writing metrics to {:?}", path);
-
-                // We already vetted the parent in the CLI validator, so unwrap
-                // here should be fine.
-                let parent = path.parent().unwrap();
-
-                // We do this since we need the temporary file to be on the
-                // same filesystem as the final persisted file.
-                let mut file = NamedTempFile::new_in(&parent)?;
-                let metrics = String::from_utf8(metrics)?;
-                write!(file, "{}", metrics)?;
-                file.persist(&path)?;
-            },
         }
 
         Ok(())
     }
 
-    pub fn export(self) -> Result<(), ExporterError> {
+    // Collects metrics with the given exporter and writes them out.
+    pub fn export(&self, exporter: &Exporter) -> Result<(), ExporterError> {
         debug!("Exporting metrics to file");
 
-        // Get an exporter and export the metrics.
-        let exporter = Exporter::new();
-
-        // TODO: Fix this unwrap
-        let metrics = exporter.export().unwrap();
+        let metric_families = exporter.gather()?;
 
         // Write metrics
-        self.write(metrics)?;
+        self.write(&metric_families)?;
 
         Ok(())
     }
+
+    // Exports metrics every interval, forever. Errors are logged rather than
+    // returned, so a transient failure doesn't stop future refreshes.
+    pub async fn export_interval(self, exporter: Exporter, interval: Duration) {
+        debug!("Exporting metrics to file every {:?}", interval);
+
+        let file_exporter = Arc::new(self);
+        let mut interval = time::interval(interval);
+
+        loop {
+            interval.tick().await;
+
+            // Collecting and writing are blocking, so they're kept off the
+            // runtime's thread.
+            let export = {
+                let exporter = exporter.clone();
+                let file_exporter = Arc::clone(&file_exporter);
+                web::block(move || file_exporter.export(&exporter))
+            };
+
+            match export.await {
+                Ok(())                       => {},
+                Err(BlockingError::Error(e)) => {
+                    error!("Failed to export metrics to file: {}", e);
+                },
+                Err(BlockingError::Canceled) => {
+                    error!("Failed to export metrics to file: canceled");
+                },
+            }
+        }
+    }
+}
+
+// Splits metric families into the global metrics, which have no name label,
+// and the metrics belonging to each jail.
+fn split_by_jail(metric_families: &[MetricFamily])
+-> (Vec<MetricFamily>, JailMetricFamilies) {
+    let mut global = Vec::new();
+    let mut jails = JailMetricFamilies::new();
+
+    for family in metric_families {
+        let mut global_metrics = Vec::new();
+        let mut jail_metrics: BTreeMap<&str, Vec<Metric>> = BTreeMap::new();
+
+        for metric in family.get_metric() {
+            match jail_name(metric) {
+                Some(name) => {
+                    jail_metrics
+                        .entry(name)
+                        .or_default()
+                        .push(metric.clone());
+                },
+                None => global_metrics.push(metric.clone()),
+            }
+        }
+
+        // The text encoder refuses families without metrics, so we only keep
+        // the non-empty ones.
+        if !global_metrics.is_empty() {
+            let mut global_family = family.clone();
+            global_family.set_metric(global_metrics);
+            global.push(global_family);
+        }
+
+        for (name, metrics) in jail_metrics {
+            let mut jail_family = family.clone();
+            jail_family.set_metric(metrics);
+
+            jails
+                .entry(name.to_owned())
+                .or_default()
+                .push(jail_family);
+        }
+    }
+
+    (global, jails)
+}
+
+// Returns the file name used for the metrics of the named jail.
+// Jail names are percent encoded, keeping only characters that are safe in
+// file names. Dots are kept, since nested jails are named parent.child, and
+// the leading "jail_" prefix means a name can never become "." or "..".
+// A jail named "exporter" has its first character encoded so it can't clash
+// with the global metrics file.
+fn jail_file_name(name: &str) -> String {
+    let mut file_name = String::from("jail_");
+
+    for (i, byte) in name.bytes().enumerate() {
+        let is_safe = byte.is_ascii_alphanumeric() || b"-._".contains(&byte);
+
+        if is_safe && !(i == 0 && name == "exporter") {
+            file_name.push(byte as char);
+        }
+        else {
+            file_name.push_str(&format!("%{:02X}", byte));
+        }
+    }
+
+    file_name.push_str(".prom");
+    file_name
+}
+
+// Removes jail metric files in dir that weren't written during this run,
+// these belong to jails that are no longer running.
+fn reap_files(dir: &Path, written: &HashSet<String>)
+-> Result<(), ExporterError> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+
+        // We never write non-UTF-8 file names, so they can't be ours.
+        let file_name = match file_name.to_str() {
+            Some(file_name) => file_name,
+            None            => continue,
+        };
+
+        let is_jail_file = file_name.starts_with("jail_")
+            && file_name.ends_with(".prom")
+            && file_name != GLOBAL_FILE_NAME;
+
+        if is_jail_file && !written.contains(file_name) {
+            debug!("Removing metrics for dead jail: {:?}", entry.path());
+
+            fs::remove_file(entry.path())?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::libc;
+    use pretty_assertions::assert_eq;
+    use prometheus::{
+        IntCounter,
+        IntGauge,
+        IntGaugeVec,
+        Opts,
+        Registry,
+    };
+    use tempfile::TempDir;
+
+    // Returns metric families containing a global metric and one metric for
+    // each of the given jails.
+    fn metric_families(names: &[&str]) -> Vec<MetricFamily> {
+        let registry = Registry::new();
+
+        let num = IntGauge::new("jail_num", "help").unwrap();
+        num.set(names.len() as i64);
+        registry.register(Box::new(num)).unwrap();
+
+        let opts = Opts::new("jail_id", "help");
+        let id = IntGaugeVec::new(opts, &["name"]).unwrap();
+        for (i, name) in names.iter().enumerate() {
+            id.with_label_values(&[name]).set(i as i64);
+        }
+        registry.register(Box::new(id)).unwrap();
+
+        registry.gather()
+    }
+
+    #[test]
+    fn export_shares_exporter() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("metrics.prom");
+        let file_exporter = FileExporter::new(path.to_str().unwrap());
+        let exporter = Exporter::new();
+
+        // A counter registered on a clone is written when exporting the
+        // original, so both use the same registry.
+        let clone = exporter.clone();
+        let counter = IntCounter::new("shared_total", "help").unwrap();
+        counter.inc_by(7);
+        clone.registry().register(Box::new(counter)).unwrap();
+
+        file_exporter.export(&exporter).unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.contains("\nshared_total 7\n"));
+    }
+
+    #[test]
+    fn jail_file_name_dots() {
+        let file_name = jail_file_name("parent.child");
+        assert_eq!(file_name, "jail_parent.child.prom");
+    }
+
+    #[test]
+    fn jail_file_name_exporter() {
+        let file_name = jail_file_name("exporter");
+        assert_eq!(file_name, "jail_%65xporter.prom");
+    }
+
+    #[test]
+    fn jail_file_name_unsafe() {
+        let file_name = jail_file_name("../a b%");
+        assert_eq!(file_name, "jail_..%2Fa%20b%25.prom");
+    }
+
+    #[test]
+    fn split_by_jail_ok() {
+        let families = metric_families(&["a", "b.c"]);
+        let (global, jails) = split_by_jail(&families);
+
+        assert_eq!(global.len(), 1);
+        assert_eq!(global[0].get_name(), "jail_num");
+
+        let names: Vec<&str> = jails.keys().map(|k| k.as_str()).collect();
+        assert_eq!(names, vec!["a", "b.c"]);
+
+        for families in jails.values() {
+            assert_eq!(families.len(), 1);
+            assert_eq!(families[0].get_metric().len(), 1);
+        }
+    }
+
+    #[test]
+    fn write_directory_ok() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let exporter = FileExporter::new_directory(path);
+
+        exporter.write(&metric_families(&["a", "b.c"])).unwrap();
+
+        let global = fs::read_to_string(dir.path().join("jail_exporter.prom"))
+            .unwrap();
+        assert!(global.contains("jail_num 2"));
+
+        let jail = fs::read_to_string(dir.path().join("jail_b.c.prom"))
+            .unwrap();
+        assert!(jail.contains("jail_id{name=\"b.c\"} 1"));
+        assert!(!jail.contains("name=\"a\""));
+    }
+
+    #[test]
+    fn write_directory_reaps_dead_jails() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let exporter = FileExporter::new_directory(path);
+
+        // Files that aren't ours must survive the reaping.
+        let other = dir.path().join("other.prom");
+        fs::write(&other, "other 1\n").unwrap();
+
+        exporter.write(&metric_families(&["a", "b"])).unwrap();
+        assert!(dir.path().join("jail_b.prom").exists());
+
+        exporter.write(&metric_families(&["a"])).unwrap();
+        assert!(dir.path().join("jail_a.prom").exists());
+        assert!(dir.path().join("jail_exporter.prom").exists());
+        assert!(!dir.path().join("jail_b.prom").exists());
+        assert!(other.exists());
+    }
+
+    #[test]
+    fn write_influx_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("metrics.lp");
+        let file_exporter = FileExporter::new(path.to_str().unwrap())
+            .format(Format::Influx);
+
+        file_exporter.write(&metric_families(&["a"])).unwrap();
+
+        let metrics = fs::read_to_string(&path).unwrap();
+        assert!(metrics.contains("jail_id,jid=0,name=a value=0i "));
+    }
+
+    #[test]
+    fn write_fsync_ok() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("metrics.prom");
+        let exporter = FileExporter::new(path.to_str().unwrap()).fsync(true);
+
+        exporter.write(&metric_families(&["a"])).unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.contains("jail_num 1"));
+    }
+
+    #[test]
+    fn write_fsync_error() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("metrics.prom");
+        let mut exporter = FileExporter::new(path.to_str().unwrap())
+            .fsync(true);
+
+        // EIO is how fsync reports a failed write back to disk.
+        exporter.sync = |_| Err(io::Error::from_raw_os_error(libc::EIO));
+
+        let res = exporter.write(&metric_families(&["a"]));
+
+        match res {
+            Err(ExporterError::IoError(e)) => {
+                assert_eq!(e.raw_os_error(), Some(libc::EIO));
+            },
+            _ => panic!("expected an IoError, got {:?}", res),
+        }
+
+        // The sync failed before the rename, so nothing was written and the
+        // temporary file was cleaned up.
+        assert!(!path.exists());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn write_sets_file_mode() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("metrics.prom");
+        let exporter = FileExporter::new(path.to_str().unwrap()).mode(0o644);
+
+        exporter.write(&metric_families(&["a"])).unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, 0o644);
+    }
+
+    #[test]
+    fn write_default_file_mode() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("metrics.prom");
+        let exporter = FileExporter::new(path.to_str().unwrap());
+
+        exporter.write(&metric_families(&["a"])).unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, 0o600);
+    }
 }
@@ -0,0 +1,365 @@
+// Graphite exporter
+#![forbid(unsafe_code)]
+#![forbid(missing_docs)]
+use crate::signal::run_every;
+use futures::future::ready;
+use jail_exporter::{
+    Exporter,
+    ExporterError,
+};
+use log::{
+    debug,
+    info,
+    warn,
+};
+use prometheus::proto::{
+    MetricFamily,
+    MetricType,
+};
+use std::collections::{
+    HashMap,
+    HashSet,
+};
+use std::io::{
+    self,
+    Write,
+};
+use std::net::{
+    TcpStream,
+    ToSocketAddrs,
+};
+use std::time::{
+    Duration,
+    SystemTime,
+    UNIX_EPOCH,
+};
+
+// Timeout for connecting and writing to Carbon.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Used for the Graphite builder
+pub struct Graphite {
+    address:  String,
+    host:     String,
+    interval: Duration,
+    prefix:   String,
+}
+
+impl Graphite {
+    // Returns a new Graphite sending to Carbon at the given HOST:PORT. The
+    // host path component defaults to our hostname.
+    pub fn new(address: &str) -> Self {
+        debug!("New Graphite sending to {}", address);
+
+        let host = hostname::get()
+            .map(|host| host.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| "localhost".into());
+
+        Self {
+            address:  address.into(),
+            host,
+            interval: Duration::from_secs(15),
+            prefix:   "jail_exporter".into(),
+        }
+    }
+
+    // Sets the host path component.
+    pub fn host(mut self, host: String) -> Self {
+        debug!("Setting Graphite host to: {}", host);
+
+        self.host = host;
+        self
+    }
+
+    // Sets how often metrics are sent.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        debug!("Setting Graphite interval to: {:?}", interval);
+
+        self.interval = interval;
+        self
+    }
+
+    // Sets the prefix of every metric path.
+    pub fn prefix(mut self, prefix: String) -> Self {
+        debug!("Setting Graphite prefix to: {}", prefix);
+
+        self.prefix = prefix;
+        self
+    }
+
+    // Returns a connection to Carbon, trying each address the host resolves
+    // to in turn.
+    fn connect(&self) -> Result<TcpStream, ExporterError> {
+        let mut last_error = io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            format!("no addresses found for {}", self.address),
+        );
+
+        for address in self.address.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&address, WRITE_TIMEOUT) {
+                Ok(stream) => {
+                    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+                    return Ok(stream);
+                },
+                Err(e) => last_error = e,
+            }
+        }
+
+        Err(last_error.into())
+    }
+
+    // Collects metrics with the given exporter and sends them to Carbon.
+    fn send(&self, exporter: &Exporter) -> Result<(), ExporterError> {
+        let metric_families = exporter.gather()?;
+
+        self.send_metrics(&metric_families)
+    }
+
+    // Sends the given metrics to Carbon.
+    fn send_metrics(&self, metric_families: &[MetricFamily])
+    -> Result<(), ExporterError> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+
+        let lines = lines(
+            metric_families,
+            &self.prefix,
+            &self.host,
+            timestamp,
+        );
+
+        let mut stream = self.connect()?;
+        stream.write_all(lines.concat().as_bytes())?;
+
+        Ok(())
+    }
+
+    // Sends metrics every interval until we receive SIGINT or SIGTERM.
+    pub async fn run(self, exporter: &Exporter) -> Result<(), ExporterError> {
+        info!(
+            "Sending Graphite metrics to {} every {:?}",
+            self.address,
+            self.interval,
+        );
+
+        run_every(self.interval, "send Graphite metrics", || {
+            ready(self.send(exporter))
+        }).await
+    }
+}
+
+// Escapes a single component of a Graphite metric path.
+// Graphite uses dots to separate path components and whitespace to separate
+// the fields of a line, so both are replaced with underscores. Other
+// characters outside of [A-Za-z0-9_:-] are also replaced, as Graphite may
+// fail to query them. This means a nested jail named "parent.child" becomes
+// "parent_child", so lines has to check escaped jail names for collisions.
+fn escape_component(component: &str) -> String {
+    component
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "_:-".contains(c) {
+                c
+            }
+            else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+// Converts gathered metric families into Carbon plaintext lines.
+// Metrics with a name label are sent as prefix.<host>.<jail>.<metric>, with
+// the "jail_" prefix removed from the metric name as the jail is already in
+// the path. Metrics without labels are sent as prefix.<host>.<metric>.
+// Metrics with any other labels, such as the build info, can't be
+// represented in Graphite and are skipped.
+// Escaping can map different jail names, such as "a.b" and "a_b", to the
+// same path. Only the first of those jails is sent, as they would otherwise
+// overwrite each other's metrics.
+fn lines(
+    metric_families: &[MetricFamily],
+    prefix: &str,
+    host: &str,
+    timestamp: u64,
+) -> Vec<String> {
+    let host = escape_component(host);
+    let mut lines = Vec::new();
+
+    // The jail name each escaped jail path component was taken by.
+    let mut jails: HashMap<String, &str> = HashMap::new();
+    let mut collided = HashSet::new();
+
+    for family in metric_families {
+        let name = family.get_name();
+
+        for metric in family.get_metric() {
+            let value = match family.get_field_type() {
+                MetricType::COUNTER => metric.get_counter().get_value(),
+                MetricType::GAUGE   => metric.get_gauge().get_value(),
+                _                   => continue,
+            };
+
+            let path = match metric.get_label() {
+                [] => {
+                    format!("{}.{}.{}", prefix, host, escape_component(name))
+                },
+                [label] if label.get_name() == "name" => {
+                    let jail = label.get_value();
+                    let component = escape_component(jail);
+
+                    let owner = *jails.entry(component.clone()).or_insert(jail);
+                    if owner != jail {
+                        if collided.insert(jail) {
+                            warn!(
+                                "Not sending jail {} to Graphite, its path \
+                                 collides with jail {}",
+                                jail,
+                                owner,
+                            );
+                        }
+
+                        continue;
+                    }
+
+                    let metric = name.trim_start_matches("jail_");
+
+                    format!(
+                        "{}.{}.{}.{}",
+                        prefix,
+                        host,
+                        component,
+                        escape_component(metric),
+                    )
+                },
+                _ => continue,
+            };
+
+            lines.push(format!("{} {} {}\n", path, value, timestamp));
+        }
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use prometheus::{
+        IntCounterVec,
+        IntGauge,
+        IntGaugeVec,
+        Opts,
+        Registry,
+    };
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    #[test]
+    fn escape_component_dots() {
+        let component = escape_component("parent.child.grandchild");
+        assert_eq!(component, "parent_child_grandchild");
+    }
+
+    #[test]
+    fn escape_component_ok() {
+        let component = escape_component("web-01_a:b");
+        assert_eq!(component, "web-01_a:b");
+    }
+
+    #[test]
+    fn escape_component_whitespace() {
+        let component = escape_component("a b\tc\nd");
+        assert_eq!(component, "a_b_c_d");
+    }
+
+    #[test]
+    fn escape_component_unicode() {
+        let component = escape_component("jäil/1");
+        assert_eq!(component, "j_il_1");
+    }
+
+    #[test]
+    fn lines_ok() {
+        let registry = Registry::new();
+
+        let jail_num = IntGauge::new("jail_num", "number of jails").unwrap();
+        jail_num.set(1);
+        registry.register(Box::new(jail_num)).unwrap();
+
+        let opts = Opts::new("jail_cputime_seconds_total", "CPU time");
+        let cputime = IntCounterVec::new(opts, &["name"]).unwrap();
+        cputime.with_label_values(&["www.example"]).inc_by(5);
+        registry.register(Box::new(cputime)).unwrap();
+
+        let opts = Opts::new("jail_exporter_build_info", "build info");
+        let build_info = IntGaugeVec::new(opts, &["version"]).unwrap();
+        build_info.with_label_values(&["0.12.0"]).set(1);
+        registry.register(Box::new(build_info)).unwrap();
+
+        let lines = lines(
+            &registry.gather(),
+            "servers.jails",
+            "host.example.com",
+            1000,
+        );
+
+        let ok = vec![
+            "servers.jails.host_example_com.www_example.cputime_seconds_total \
+             5 1000\n".to_owned(),
+            "servers.jails.host_example_com.jail_num 1 1000\n".to_owned(),
+        ];
+
+        assert_eq!(lines, ok);
+    }
+
+    #[test]
+    fn lines_colliding_jails() {
+        let registry = Registry::new();
+
+        let opts = Opts::new("jail_memoryuse_bytes", "memory use");
+        let memoryuse = IntGaugeVec::new(opts, &["name"]).unwrap();
+        memoryuse.with_label_values(&["a_b"]).set(1);
+        memoryuse.with_label_values(&["a.b"]).set(2);
+        memoryuse.with_label_values(&["c"]).set(3);
+        registry.register(Box::new(memoryuse)).unwrap();
+
+        let lines = lines(&registry.gather(), "p", "h", 1000);
+
+        // Labels are sorted, so "a.b" claims the path before "a_b".
+        let ok = vec![
+            "p.h.a_b.memoryuse_bytes 2 1000\n".to_owned(),
+            "p.h.c.memoryuse_bytes 3 1000\n".to_owned(),
+        ];
+
+        assert_eq!(lines, ok);
+    }
+
+    #[test]
+    fn send_ok() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        let graphite = Graphite::new(&address).host("host1".into());
+
+        let registry = Registry::new();
+        let jail_num = IntGauge::new("jail_num", "number of jails").unwrap();
+        jail_num.set(2);
+        registry.register(Box::new(jail_num)).unwrap();
+
+        graphite.send_metrics(&registry.gather()).unwrap();
+
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut received = String::new();
+        stream.read_to_string(&mut received).unwrap();
+
+        let fields: Vec<_> = received.trim_end().split(' ').collect();
+
+        assert_eq!(fields.len(), 3);
+        assert_eq!(fields[0], "jail_exporter.host1.jail_num");
+        assert_eq!(fields[1], "2");
+    }
+}
@@ -0,0 +1,329 @@
+//
+// jail_exporter
+//
+// This module implements the history of jail resource usage kept across
+// collections.
+//
+#![forbid(unsafe_code)]
+#![forbid(missing_docs)]
+use crate::Rusage;
+use std::collections::{
+    HashMap,
+    VecDeque,
+};
+
+/// The resource usage of each jail seen by a single collection.
+#[derive(Debug)]
+pub(crate) struct HistoryEntry {
+    timestamp: u64,
+    jails:     HashMap<String, Vec<(rctl::Resource, usize)>>,
+}
+
+impl HistoryEntry {
+    /// Returns a new entry for a collection made at the given timestamp.
+    pub(crate) fn new(timestamp: u64) -> Self {
+        Self {
+            timestamp,
+            jails: HashMap::new(),
+        }
+    }
+
+    /// Records the resource usage of the named jail.
+    pub(crate) fn insert(&mut self, name: &str, rusage: &Rusage) {
+        let usage = rusage
+            .iter()
+            .map(|(resource, value)| (*resource, *value))
+            .collect();
+
+        self.jails.insert(name.to_owned(), usage);
+    }
+
+    /// Returns the number of samples held by the entry.
+    fn samples(&self) -> usize {
+        self.jails.values().map(|usage| usage.len()).sum()
+    }
+}
+
+/// Resource usage of each jail over recent collections. Collections older
+/// than the retention, in seconds, are dropped. The oldest collections are
+/// also dropped once more than max_samples samples, or more than max_samples
+/// collections, are held, so the memory used is bounded however many jails
+/// are running, including none.
+#[derive(Debug, Default)]
+pub(crate) struct History {
+    entries:     VecDeque<HistoryEntry>,
+    max_samples: usize,
+    retention:   Option<u64>,
+    samples:     usize,
+}
+
+impl History {
+    /// Returns the maximum number of samples held. History is disabled when
+    /// this is 0.
+    pub(crate) fn max_samples(&self) -> usize {
+        self.max_samples
+    }
+
+    /// Returns how long collections are kept for, in seconds. Collections
+    /// are only bounded by max_samples when this is None.
+    pub(crate) fn retention(&self) -> Option<u64> {
+        self.retention
+    }
+
+    /// Returns the number of collections held.
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Sets the maximum number of samples held, dropping the oldest
+    /// collections if there are now too many.
+    pub(crate) fn set_max_samples(&mut self, max_samples: usize) {
+        self.max_samples = max_samples;
+        self.evict();
+    }
+
+    /// Sets how long collections are kept for, in seconds, dropping those
+    /// that are now too old.
+    pub(crate) fn set_retention(&mut self, retention: Option<u64>) {
+        self.retention = retention;
+        self.evict();
+    }
+
+    /// Adds the entry for the latest collection.
+    pub(crate) fn push(&mut self, entry: HistoryEntry) {
+        if self.max_samples == 0 {
+            return;
+        }
+
+        self.samples += entry.samples();
+        self.entries.push_back(entry);
+        self.evict();
+    }
+
+    /// Drops the oldest entries until none are older than the retention
+    /// before the newest, and no more than max_samples samples and entries
+    /// are held. Entries of collections that saw no jails hold no samples, so
+    /// they're bounded by their own number.
+    fn evict(&mut self) {
+        let cutoff = match (self.retention, self.entries.back()) {
+            (Some(retention), Some(newest)) => {
+                newest.timestamp.saturating_sub(retention)
+            },
+            _ => 0,
+        };
+
+        while let Some(oldest) = self.entries.front() {
+            let expired = oldest.timestamp < cutoff;
+
+            if !expired
+                && self.samples <= self.max_samples
+                && self.entries.len() <= self.max_samples
+            {
+                break;
+            }
+
+            if let Some(entry) = self.entries.pop_front() {
+                self.samples -= entry.samples();
+            }
+        }
+    }
+
+    /// Returns the (timestamp, value) pairs of the resource for the named
+    /// jail, oldest first, from collections made at or after since. Returns
+    /// None if no held collection saw the jail.
+    pub(crate) fn values(
+        &self,
+        name: &str,
+        resource: rctl::Resource,
+        since: u64,
+    ) -> Option<Vec<(u64, usize)>> {
+        let mut seen = false;
+        let mut values = Vec::new();
+
+        for entry in &self.entries {
+            let usage = match entry.jails.get(name) {
+                Some(usage) => usage,
+                None        => continue,
+            };
+
+            seen = true;
+
+            if entry.timestamp < since {
+                continue;
+            }
+
+            let value = usage
+                .iter()
+                .find(|(r, _)| *r == resource)
+                .map(|(_, value)| *value);
+
+            if let Some(value) = value {
+                values.push((entry.timestamp, value));
+            }
+        }
+
+        if seen {
+            Some(values)
+        }
+        else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    // Returns an entry holding the memoryuse and openfiles of the given
+    // jails.
+    fn entry(timestamp: u64, jails: &[(&str, usize)]) -> HistoryEntry {
+        let mut entry = HistoryEntry::new(timestamp);
+
+        for (name, memoryuse) in jails {
+            let mut rusage = Rusage::new();
+            rusage.insert(rctl::Resource::MemoryUse, *memoryuse);
+            rusage.insert(rctl::Resource::OpenFiles, 1);
+
+            entry.insert(name, &rusage);
+        }
+
+        entry
+    }
+
+    fn history(max_samples: usize) -> History {
+        let mut history = History::default();
+        history.set_max_samples(max_samples);
+        history
+    }
+
+    #[test]
+    fn values_ok() {
+        let mut history = history(100);
+        history.push(entry(10, &[("a", 1), ("b", 2)]));
+        history.push(entry(20, &[("a", 3)]));
+        history.push(entry(30, &[("a", 5), ("b", 6)]));
+
+        let memoryuse = rctl::Resource::MemoryUse;
+
+        assert_eq!(
+            history.values("a", memoryuse, 0),
+            Some(vec![(10, 1), (20, 3), (30, 5)]),
+        );
+        assert_eq!(
+            history.values("b", memoryuse, 0),
+            Some(vec![(10, 2), (30, 6)]),
+        );
+        assert_eq!(history.values("c", memoryuse, 0), None);
+    }
+
+    #[test]
+    fn values_since() {
+        let mut history = history(100);
+        history.push(entry(10, &[("a", 1)]));
+        history.push(entry(20, &[("a", 3)]));
+
+        let memoryuse = rctl::Resource::MemoryUse;
+
+        assert_eq!(history.values("a", memoryuse, 20), Some(vec![(20, 3)]));
+        assert_eq!(history.values("a", memoryuse, 30), Some(vec![]));
+    }
+
+    #[test]
+    fn values_unrecorded_resource() {
+        let mut history = history(100);
+        history.push(entry(10, &[("a", 1)]));
+
+        let values = history.values("a", rctl::Resource::NThreads, 0);
+        assert_eq!(values, Some(vec![]));
+    }
+
+    #[test]
+    fn push_evicts_oldest() {
+        // Each entry holds 2 samples per jail.
+        let mut history = history(8);
+        history.push(entry(10, &[("a", 1), ("b", 1)]));
+        history.push(entry(20, &[("a", 2), ("b", 2)]));
+        assert_eq!(history.samples, 8);
+
+        history.push(entry(30, &[("a", 3)]));
+        assert_eq!(history.samples, 6);
+
+        let memoryuse = rctl::Resource::MemoryUse;
+        assert_eq!(
+            history.values("a", memoryuse, 0),
+            Some(vec![(20, 2), (30, 3)]),
+        );
+
+        // b was only seen by collections that have been dropped.
+        history.push(entry(40, &[("a", 4)]));
+        history.push(entry(50, &[("a", 5)]));
+        assert_eq!(history.values("b", memoryuse, 0), None);
+        assert_eq!(history.samples, 6);
+    }
+
+    #[test]
+    fn push_evicts_empty() {
+        let mut history = history(2);
+
+        for timestamp in 0..5 {
+            history.push(entry(timestamp, &[]));
+        }
+
+        assert_eq!(history.len(), 2);
+        assert_eq!(history.entries[0].timestamp, 3);
+    }
+
+    #[test]
+    fn push_evicts_expired() {
+        let mut history = history(100);
+        history.set_retention(Some(20));
+        history.push(entry(10, &[("a", 1)]));
+        history.push(entry(20, &[("a", 2)]));
+        history.push(entry(30, &[("a", 3)]));
+        assert_eq!(history.len(), 3);
+
+        history.push(entry(40, &[("a", 4)]));
+        assert_eq!(history.len(), 3);
+        assert_eq!(history.samples, 6);
+
+        let memoryuse = rctl::Resource::MemoryUse;
+        assert_eq!(
+            history.values("a", memoryuse, 0),
+            Some(vec![(20, 2), (30, 3), (40, 4)]),
+        );
+    }
+
+    #[test]
+    fn set_retention_evicts() {
+        let mut history = history(100);
+        history.push(entry(10, &[("a", 1)]));
+        history.push(entry(20, &[("a", 2)]));
+        history.push(entry(30, &[("a", 3)]));
+
+        history.set_retention(Some(5));
+        assert_eq!(history.len(), 1);
+        assert_eq!(history.samples, 2);
+    }
+
+    #[test]
+    fn set_max_samples_evicts() {
+        let mut history = history(100);
+        history.push(entry(10, &[("a", 1)]));
+        history.push(entry(20, &[("a", 2)]));
+
+        history.set_max_samples(2);
+        assert_eq!(history.entries.len(), 1);
+        assert_eq!(history.samples, 2);
+    }
+
+    #[test]
+    fn push_disabled() {
+        let mut history = history(0);
+        history.push(entry(10, &[("a", 1)]));
+
+        assert!(history.entries.is_empty());
+        assert_eq!(history.samples, 0);
+    }
+}
@@ -5,44 +5,105 @@
 //
 #![forbid(unsafe_code)]
 #![deny(missing_docs)]
-use crate::errors::ExporterError;
+use jail_exporter::ExporterError;
 use actix_web::{
     web,
     HttpServer,
 };
-use actix_web::middleware::Logger;
+use actix_web::middleware::{
+    Condition,
+    Logger,
+};
 use log::{
     debug,
     info,
 };
+use std::io;
+use std::time::{
+    Duration,
+    Instant,
+};
 
+mod allow;
+use allow::AllowList;
+mod auth;
+use auth::{
+    BasicAuth,
+    Users,
+};
+mod cidr;
+pub use cidr::Cidr;
 mod handlers;
 use handlers::{
+    events,
+    healthy,
+    history,
     index,
+    jail_metrics,
     metrics,
+    ready,
+    stream,
 };
+mod query;
+mod sampler;
+mod sse;
+use sse::Broadcaster;
 mod templates;
-use templates::render_index_page;
+mod timeout;
+use timeout::ScrapeTimeout;
+pub use timeout::TimeoutAction;
+mod tls;
+pub use tls::TlsConfig;
+mod unix;
+mod web_config;
+pub use web_config::WebConfig;
 
-// This AppState is used to pass the rendered index template to the index
-// function.
+// Returns a BindAddress error naming the address that failed to bind.
+fn bind_error(bind_address: &str, e: io::Error) -> ExporterError {
+    ExporterError::BindAddress(format!("{}: {}", bind_address, e))
+}
+
+// This AppState is used to pass the exporter and the details shown on the
+// index page and the snapshot stream to the request handlers.
 pub(self) struct AppState {
-    exporter:   jail_exporter::Exporter,
-    index_page: String,
+    broadcaster:    Broadcaster,
+    exporter:       jail_exporter::Exporter,
+    scrape_timeout: ScrapeTimeout,
+    started:        Instant,
+    telemetry_path: String,
 }
 
 // Used for the httpd builder
-#[derive(Debug)]
 pub struct Server {
-    bind_address:   String,
-    telemetry_path: String,
+    allow_cidrs:      Vec<Cidr>,
+    basic_auth:       BasicAuth,
+    bind_addresses:   Vec<String>,
+    exporter:         jail_exporter::Exporter,
+    history_interval: Duration,
+    scrape_timeout:   ScrapeTimeout,
+    socket_group:     Option<u32>,
+    socket_mode:      Option<u32>,
+    stream_interval:  Duration,
+    telemetry_path:   String,
+    tls:              Option<TlsConfig>,
+    trusted_proxies:  Vec<Cidr>,
 }
 
 impl Default for Server {
     fn default() -> Self {
         Self {
-            bind_address:   "127.0.0.1:9452".into(),
-            telemetry_path: "/metrics".into(),
+            allow_cidrs:      Vec::new(),
+            basic_auth:       BasicAuth::default(),
+            bind_addresses:   vec!["127.0.0.1:9452".into()],
+            exporter:         jail_exporter::Exporter::new(),
+            history_interval: Duration::from_secs(15),
+            scrape_timeout:   ScrapeTimeout::default(),
+            socket_group:     None,
+            socket_mode:      None,
+            stream_interval:  Duration::from_secs(5),
+            telemetry_path:   "/metrics".into(),
+            tls:              None,
+            trusted_proxies:  Vec::new(),
         }
     }
 }
@@ -54,11 +115,88 @@ impl Server {
         Default::default()
     }
 
-    // Sets the bind_address of the server.
-    pub fn bind_address(mut self, bind_address: String) -> Self {
-        debug!("Setting server bind_address to: {}", bind_address);
+    // Sets the networks clients must connect from. All clients are allowed
+    // if no networks are given.
+    pub fn allow_cidrs(mut self, allow_cidrs: Vec<Cidr>) -> Self {
+        debug!("Setting server allow_cidrs to: {:?}", allow_cidrs);
+
+        self.allow_cidrs = allow_cidrs;
+        self
+    }
+
+    // Sets the users required to authenticate with basic auth. No
+    // authentication is required if no users are given.
+    pub fn basic_auth(mut self, users: Users) -> Self {
+        debug!("Setting server basic_auth users");
 
-        self.bind_address = bind_address;
+        self.basic_auth = BasicAuth::new(users);
+        self
+    }
+
+    // Sets the addresses the server binds to.
+    pub fn bind_addresses(mut self, bind_addresses: Vec<String>) -> Self {
+        debug!("Setting server bind_addresses to: {:?}", bind_addresses);
+
+        self.bind_addresses = bind_addresses;
+        self
+    }
+
+    // Sets the exporter used to collect metrics. This allows the exporter to
+    // be shared with other outputs.
+    pub fn exporter(mut self, exporter: jail_exporter::Exporter) -> Self {
+        debug!("Setting server exporter");
+
+        self.exporter = exporter;
+        self
+    }
+
+    // Sets how scrapes are answered when collection is abandoned before
+    // Prometheus' scrape timeout.
+    pub fn scrape_timeout_action(mut self, action: TimeoutAction) -> Self {
+        debug!("Setting server scrape_timeout_action to: {:?}", action);
+
+        self.scrape_timeout.action = action;
+        self
+    }
+
+    // Sets how long before Prometheus' scrape timeout collection is
+    // abandoned, leaving time for the response to reach Prometheus.
+    pub fn scrape_timeout_offset(mut self, offset: Duration) -> Self {
+        debug!("Setting server scrape_timeout_offset to: {:?}", offset);
+
+        self.scrape_timeout.offset = offset;
+        self
+    }
+
+    // Sets the group of the Unix socket when listening on a unix: address.
+    pub fn socket_group(mut self, socket_group: u32) -> Self {
+        debug!("Setting server socket_group to: {}", socket_group);
+
+        self.socket_group = Some(socket_group);
+        self
+    }
+
+    // Sets the mode of the Unix socket when listening on a unix: address.
+    pub fn socket_mode(mut self, socket_mode: u32) -> Self {
+        debug!("Setting server socket_mode to: {:o}", socket_mode);
+
+        self.socket_mode = Some(socket_mode);
+        self
+    }
+
+    // Sets how often the history sampler collects metrics.
+    pub fn history_interval(mut self, history_interval: Duration) -> Self {
+        debug!("Setting server history_interval to: {:?}", history_interval);
+
+        self.history_interval = history_interval;
+        self
+    }
+
+    // Sets how often snapshots are sent to clients of the stream.
+    pub fn stream_interval(mut self, stream_interval: Duration) -> Self {
+        debug!("Setting server stream_interval to: {:?}", stream_interval);
+
+        self.stream_interval = stream_interval;
         self
     }
 
@@ -70,12 +208,64 @@ impl Server {
         self
     }
 
+    // Sets the proxies whose X-Forwarded-For headers are trusted when
+    // checking client addresses against allow_cidrs.
+    pub fn trusted_proxies(mut self, trusted_proxies: Vec<Cidr>) -> Self {
+        debug!("Setting server trusted_proxies to: {:?}", trusted_proxies);
+
+        self.trusted_proxies = trusted_proxies;
+        self
+    }
+
+    // Sets the TLS configuration used to serve TCP addresses over TLS.
+    pub fn tls(mut self, tls: TlsConfig) -> Self {
+        debug!("Setting server tls");
+
+        self.tls = Some(tls);
+        self
+    }
+
     // Run the HTTP server.
     pub async fn run(self) -> Result<(), ExporterError> {
-        let bind_address   = self.bind_address;
-        let exporter       = jail_exporter::Exporter::new();
-        let index_page     = render_index_page(&self.telemetry_path)?;
+        let allow_list     = AllowList::new(
+            self.allow_cidrs,
+            self.trusted_proxies,
+        );
+        let basic_auth     = self.basic_auth;
+        let bind_addresses = self.bind_addresses;
+        let broadcaster    = Broadcaster::new();
+        let exporter       = self.exporter;
+        let scrape_timeout = self.scrape_timeout;
+        let socket_group   = self.socket_group;
+        let socket_mode    = self.socket_mode;
+        let started        = Instant::now();
         let telemetry_path = self.telemetry_path.clone();
+        let jail_path      = format!("{}/jail/{{name}}", telemetry_path);
+
+        // Load the certificates up front, so a bad certificate stops us
+        // before we bind anything.
+        let (resolver, tls_config) = match &self.tls {
+            Some(tls) => {
+                let (resolver, config) = tls.load(exporter.registry())?;
+                (Some(resolver), Some(config))
+            },
+            None => (None, None),
+        };
+
+        // The snapshot publisher only starts once we're serving, but needs
+        // its own handles as the app takes ownership of ours.
+        let publisher = broadcaster
+            .clone()
+            .run(exporter.clone(), self.stream_interval);
+
+        // Likewise for the history sampler, which is only needed if we keep
+        // a history.
+        let history_sampler = if exporter.history_size() > 0 {
+            Some(sampler::run(exporter.clone(), self.history_interval))
+        }
+        else {
+            None
+        };
 
         // Route handlers
         debug!("Registering HTTP app routes");
@@ -83,12 +273,26 @@ impl Server {
             // This state is shared between threads and allows us to pass
             // arbitrary items to request handlers.
             let state = AppState {
-                exporter:   exporter.clone(),
-                index_page: index_page.clone(),
+                broadcaster:    broadcaster.clone(),
+                exporter:       exporter.clone(),
+                scrape_timeout,
+                started,
+                telemetry_path: telemetry_path.clone(),
             };
 
             actix_web::App::new()
                 .data(state)
+                // Require authentication if any users are configured.
+                .wrap(Condition::new(
+                    basic_auth.is_enabled(),
+                    basic_auth.clone(),
+                ))
+                // Reject clients outside of the allowed networks before
+                // asking them to authenticate.
+                .wrap(Condition::new(
+                    allow_list.is_enabled(),
+                    allow_list.clone(),
+                ))
                 // Enable request logging
                 .wrap(Logger::default())
                 // Root of HTTP server. Provides a basic index page and
@@ -96,20 +300,110 @@ impl Server {
                 .route("/", web::get().to(index))
                 // Path serving up the metrics.
                 .route(&telemetry_path, web::get().to(metrics))
+                // Path serving up the metrics of a single jail.
+                .route(&jail_path, web::get().to(jail_metrics))
+                // Health and readiness checks, which never collect metrics.
+                .route("/-/healthy", web::get().to(healthy))
+                .route("/-/ready", web::get().to(ready))
+                // Jail events seen by recent collections.
+                .route("/api/v1/events", web::get().to(events))
+                // Recent values of a resource for a jail.
+                .route("/api/v1/history", web::get().to(history))
+                // Snapshots of every jail, sent as Server-Sent Events.
+                .route("/api/v1/stream", web::get().to(stream))
         };
 
-        // Create the server
-        debug!("Attempting to bind to: {}", bind_address);
-        let server = HttpServer::new(app)
-            .bind(&bind_address)
-            .map_err(|e| {
-                ExporterError::BindAddress(format!("{}: {}", bind_address, e))
-            })?;
+        // Create the server, binding every address before we start serving.
+        let mut server = HttpServer::new(app);
+        let mut socket_paths = Vec::new();
+
+        for bind_address in &bind_addresses {
+            debug!("Attempting to bind to: {}", bind_address);
+
+            let bound = match unix::socket_path(bind_address) {
+                Some(path) => {
+                    unix::bind(path, socket_mode, socket_group)
+                        .and_then(|listener| {
+                            socket_paths.push(path);
+
+                            server
+                                .listen_uds(listener)
+                                .map_err(|e| bind_error(bind_address, e))
+                        })
+                },
+                None => {
+                    let bound = match &tls_config {
+                        Some(config) => {
+                            server.bind_rustls(bind_address, config.clone())
+                        },
+                        None => server.bind(bind_address),
+                    };
+
+                    bound.map_err(|e| bind_error(bind_address, e))
+                },
+            };
+
+            server = match bound {
+                Ok(server) => server,
+                Err(e) => {
+                    // Sockets we've already created would otherwise be left
+                    // behind.
+                    for path in &socket_paths {
+                        unix::remove_socket(path);
+                    }
+
+                    return Err(e);
+                },
+            };
+        }
+
+        // Pick up renewed certificates without restarting the listeners.
+        if let Some(resolver) = resolver {
+            actix_rt::spawn(tls::watch(resolver));
+        }
+
+        // Send snapshots to stream clients. Collection happens here rather
+        // than per client, so slow clients can't hold it up.
+        actix_rt::spawn(publisher);
+
+        // Keep the history filling up while nothing scrapes us.
+        if let Some(history_sampler) = history_sampler {
+            actix_rt::spawn(history_sampler);
+        }
 
         // Run it!
-        info!("Starting HTTP server on {}", bind_address);
-        server.run().await?;
+        info!("Starting HTTP server on {}", bind_addresses.join(", "));
+        let result = server.run().await;
+
+        // Don't leave our sockets behind for the next run to clean up.
+        for path in &socket_paths {
+            unix::remove_socket(path);
+        }
+
+        result?;
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[actix_rt::test]
+    async fn run_bind_address_in_use() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let in_use = listener.local_addr().unwrap().to_string();
+
+        let server = Server::new()
+            .bind_addresses(vec!["127.0.0.1:0".into(), in_use.clone()]);
+
+        match server.run().await {
+            Err(ExporterError::BindAddress(e)) => {
+                assert!(e.starts_with(&format!("{}: ", in_use)));
+            },
+            _ => panic!("expected a BindAddress error"),
+        }
+    }
+}
@@ -0,0 +1,347 @@
+//
+// jail_exporter
+//
+// This module deals with restricting access to the HTTPd by client address.
+//
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use super::cidr::Cidr;
+use actix_web::dev::{
+    Service,
+    ServiceRequest,
+    ServiceResponse,
+    Transform,
+};
+use actix_web::{
+    Error,
+    HttpResponse,
+};
+use futures::future::{
+    ok,
+    Either,
+    Ready,
+};
+use log::{
+    debug,
+    warn,
+};
+use std::net::{
+    IpAddr,
+    SocketAddr,
+};
+use std::sync::Arc;
+use std::task::{
+    Context,
+    Poll,
+};
+
+// Header set by reverse proxies to record the addresses a request was
+// forwarded for.
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+// Parses an X-Forwarded-For entry. Some proxies include the client port.
+fn parse_forwarded(entry: &str) -> Option<IpAddr> {
+    let entry = entry.trim();
+
+    entry.parse::<IpAddr>()
+        .or_else(|_| entry.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .ok()
+}
+
+// Returns whether the address is within any of the networks.
+fn is_listed(networks: &[Cidr], address: &IpAddr) -> bool {
+    networks.iter().any(|network| network.contains(address))
+}
+
+// Returns the address of the client that made the request. X-Forwarded-For
+// is only believed when the peer is a trusted proxy, in which case the list
+// is walked from the right, skipping over further trusted proxies, to find
+// the first address that no trusted proxy vouches for. Returns None if the
+// header contains an entry that isn't an address, as we can't tell who the
+// client is.
+fn client_address(
+    peer: IpAddr,
+    forwarded_for: &[&str],
+    trusted_proxies: &[Cidr],
+) -> Option<IpAddr> {
+    if !is_listed(trusted_proxies, &peer) {
+        return Some(peer);
+    }
+
+    let mut client = peer;
+
+    let entries = forwarded_for.iter().flat_map(|value| value.split(','));
+    for entry in entries.rev() {
+        client = parse_forwarded(entry)?;
+
+        if !is_listed(trusted_proxies, &client) {
+            break;
+        }
+    }
+
+    Some(client)
+}
+
+// Used to wrap the App, rejecting requests from clients outside of the
+// allowed networks with a 403.
+#[derive(Clone, Default)]
+pub struct AllowList {
+    allowed:         Arc<Vec<Cidr>>,
+    trusted_proxies: Arc<Vec<Cidr>>,
+}
+
+impl AllowList {
+    // Returns a new AllowList allowing the given networks, trusting
+    // X-Forwarded-For from the given proxies.
+    pub fn new(allowed: Vec<Cidr>, trusted_proxies: Vec<Cidr>) -> Self {
+        Self {
+            allowed:         Arc::new(allowed),
+            trusted_proxies: Arc::new(trusted_proxies),
+        }
+    }
+
+    // Returns whether any networks are configured. Without networks, all
+    // clients are allowed.
+    pub fn is_enabled(&self) -> bool {
+        !self.allowed.is_empty()
+    }
+
+    // Returns whether the request should be allowed.
+    fn is_allowed(&self, req: &ServiceRequest) -> bool {
+        // Requests on Unix sockets have no peer address. Access to those is
+        // controlled by the socket permissions instead.
+        let peer = match req.peer_addr() {
+            Some(peer) => peer.ip(),
+            None       => return true,
+        };
+
+        let forwarded_for: Vec<&str> = req.headers()
+            .get_all(X_FORWARDED_FOR)
+            .filter_map(|value| value.to_str().ok())
+            .collect();
+
+        let client = client_address(
+            peer,
+            &forwarded_for,
+            &self.trusted_proxies,
+        );
+
+        match client {
+            Some(client) if is_listed(&self.allowed, &client) => true,
+            Some(client) => {
+                debug!("Rejecting request from {} via {}", client, peer);
+                false
+            },
+            None => {
+                warn!("Invalid X-Forwarded-For from proxy {}", peer);
+                false
+            },
+        }
+    }
+}
+
+impl<S, B> Transform<S> for AllowList
+where
+    S: Service<
+        Request  = ServiceRequest,
+        Response = ServiceResponse<B>,
+        Error    = Error,
+    >,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request   = ServiceRequest;
+    type Response  = ServiceResponse<B>;
+    type Error     = Error;
+    type InitError = ();
+    type Transform = AllowListMiddleware<S>;
+    type Future    = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(AllowListMiddleware {
+            allow_list: self.clone(),
+            service,
+        })
+    }
+}
+
+// The middleware created by AllowList for each worker.
+pub struct AllowListMiddleware<S> {
+    allow_list: AllowList,
+    service:    S,
+}
+
+impl<S, B> Service for AllowListMiddleware<S>
+where
+    S: Service<
+        Request  = ServiceRequest,
+        Response = ServiceResponse<B>,
+        Error    = Error,
+    >,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request  = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error    = Error;
+    type Future   = Either<S::Future, Ready<Result<Self::Response, Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        if self.allow_list.is_allowed(&req) {
+            return Either::Left(self.service.call(req));
+        }
+
+        let response = HttpResponse::Forbidden()
+            .body("Forbidden")
+            .map_body(|_, body| body.into_body());
+
+        Either::Right(ok(req.into_response(response)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{
+        test,
+        web,
+        App,
+    };
+    use pretty_assertions::assert_eq;
+
+    fn cidrs(networks: &[&str]) -> Vec<Cidr> {
+        networks.iter().map(|network| network.parse().unwrap()).collect()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn client_address_untrusted_peer() {
+        let proxies = cidrs(&["10.0.0.1"]);
+        let client = client_address(ip("192.0.2.1"), &["10.1.1.1"], &proxies);
+
+        assert_eq!(client, Some(ip("192.0.2.1")));
+    }
+
+    #[test]
+    fn client_address_trusted_peer_without_header() {
+        let proxies = cidrs(&["10.0.0.1"]);
+        let client = client_address(ip("10.0.0.1"), &[], &proxies);
+
+        assert_eq!(client, Some(ip("10.0.0.1")));
+    }
+
+    #[test]
+    fn client_address_trusted_peer() {
+        let proxies = cidrs(&["10.0.0.0/24"]);
+        let forwarded = ["203.0.113.9, 192.0.2.1", "10.0.0.2"];
+        let client = client_address(ip("10.0.0.1"), &forwarded, &proxies);
+
+        // 203.0.113.9 was added by the client itself, and can't be trusted.
+        assert_eq!(client, Some(ip("192.0.2.1")));
+    }
+
+    #[test]
+    fn client_address_all_trusted() {
+        let proxies = cidrs(&["10.0.0.0/24"]);
+        let forwarded = ["10.0.0.3, 10.0.0.2"];
+        let client = client_address(ip("10.0.0.1"), &forwarded, &proxies);
+
+        assert_eq!(client, Some(ip("10.0.0.3")));
+    }
+
+    #[test]
+    fn client_address_with_port() {
+        let proxies = cidrs(&["10.0.0.1"]);
+        let forwarded = ["[2001:db8::1]:1234"];
+        let client = client_address(ip("10.0.0.1"), &forwarded, &proxies);
+
+        assert_eq!(client, Some(ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn client_address_invalid_entry() {
+        let proxies = cidrs(&["10.0.0.1"]);
+        let forwarded = ["unknown"];
+        let client = client_address(ip("10.0.0.1"), &forwarded, &proxies);
+
+        assert_eq!(client, None);
+    }
+
+    // Returns the status of a request from the given peer.
+    async fn status(
+        allow_list: AllowList,
+        peer: &str,
+        forwarded_for: Option<&str>,
+    ) -> u16 {
+        let mut server = test::init_service(
+            App::new()
+                .wrap(allow_list)
+                .route("/", web::get().to(HttpResponse::Ok))
+        ).await;
+
+        let mut request = test::TestRequest::get()
+            .uri("/")
+            .peer_addr(peer.parse().unwrap());
+
+        if let Some(forwarded_for) = forwarded_for {
+            request = request.header(X_FORWARDED_FOR, forwarded_for);
+        }
+
+        let response = test::call_service(
+            &mut server,
+            request.to_request(),
+        ).await;
+
+        response.status().as_u16()
+    }
+
+    #[actix_rt::test]
+    async fn middleware_allowed() {
+        let allow_list = AllowList::new(cidrs(&["192.0.2.0/24"]), vec![]);
+        assert_eq!(status(allow_list, "192.0.2.1:1234", None).await, 200);
+    }
+
+    #[actix_rt::test]
+    async fn middleware_forbidden() {
+        let allow_list = AllowList::new(cidrs(&["192.0.2.0/24"]), vec![]);
+        assert_eq!(status(allow_list, "203.0.113.1:1234", None).await, 403);
+    }
+
+    #[actix_rt::test]
+    async fn middleware_ignores_untrusted_forwarded_for() {
+        let allow_list = AllowList::new(cidrs(&["192.0.2.0/24"]), vec![]);
+        let forwarded = Some("192.0.2.1");
+
+        let status = status(allow_list, "203.0.113.1:1234", forwarded).await;
+        assert_eq!(status, 403);
+    }
+
+    #[actix_rt::test]
+    async fn middleware_trusted_proxy() {
+        let allow_list = AllowList::new(
+            cidrs(&["192.0.2.0/24"]),
+            cidrs(&["10.0.0.1"]),
+        );
+
+        let allowed = status(
+            allow_list.clone(),
+            "10.0.0.1:1234",
+            Some("192.0.2.1"),
+        ).await;
+        assert_eq!(allowed, 200);
+
+        let forbidden = status(
+            allow_list,
+            "10.0.0.1:1234",
+            Some("203.0.113.1"),
+        ).await;
+        assert_eq!(forbidden, 403);
+    }
+}
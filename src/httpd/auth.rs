@@ -0,0 +1,390 @@
+//
+// jail_exporter
+//
+// This module deals with HTTP basic authentication.
+//
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use actix_web::dev::{
+    Service,
+    ServiceRequest,
+    ServiceResponse,
+    Transform,
+};
+use actix_web::http::header::{
+    HeaderValue,
+    AUTHORIZATION,
+    WWW_AUTHENTICATE,
+};
+use actix_web::{
+    Error,
+    HttpResponse,
+};
+use bcrypt::HashParts;
+use futures::future::{
+    ok,
+    Either,
+    Ready,
+};
+use log::{
+    debug,
+    error,
+};
+use std::collections::HashMap;
+use std::sync::{
+    Arc,
+    Mutex,
+};
+use std::task::{
+    Context,
+    Poll,
+};
+
+// Realm sent to clients in the WWW-Authenticate header.
+const REALM: &str = "jail_exporter";
+
+// Map of user names to bcrypt password hashes.
+pub type Users = HashMap<String, String>;
+
+// Returns the user name and password from a basic Authorization header.
+fn credentials(header: &HeaderValue) -> Option<(String, String)> {
+    let mut parts = header.to_str().ok()?.splitn(2, ' ');
+
+    let scheme = parts.next()?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+
+    let decoded = base64::decode(parts.next()?.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+
+    // Passwords may contain colons, user names may not.
+    let colon = decoded.find(':')?;
+    let user = &decoded[..colon];
+    let password = &decoded[colon + 1..];
+
+    Some((user.to_owned(), password.to_owned()))
+}
+
+// Compares a and b in a time that depends only on their lengths, so timing
+// doesn't reveal how much of a cached password a client guessed.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// The users allowed in and the passwords they last authenticated with.
+// bcrypt is deliberately slow, so verifying every scrape would cost tens of
+// milliseconds of CPU each time.
+#[derive(Debug, Default)]
+struct Authenticator {
+    // Verified against for unknown users, so that they take as long to
+    // reject as known users with a wrong password.
+    dummy_hash: Option<String>,
+    users:      Users,
+    verified:   Mutex<HashMap<String, String>>,
+}
+
+impl Authenticator {
+    fn new(users: Users) -> Self {
+        // Use the highest cost in the configuration, so that no user is
+        // distinguishable by how long they take to reject.
+        let cost = users
+            .values()
+            .filter_map(|hash| hash.parse::<HashParts>().ok())
+            .map(|parts| parts.get_cost())
+            .max();
+
+        let dummy_hash = cost.and_then(|cost| {
+            match bcrypt::hash("", cost) {
+                Ok(hash) => Some(hash),
+                Err(e)   => {
+                    error!("Failed to create dummy password hash: {}", e);
+                    None
+                },
+            }
+        });
+
+        Self {
+            dummy_hash,
+            users,
+            verified: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Checks the credentials in the given Authorization header against the
+    // users' bcrypt hashes, skipping the hashing for credentials that were
+    // recently verified.
+    fn is_authorised(&self, header: Option<&HeaderValue>) -> bool {
+        let (user, password) = match header.and_then(credentials) {
+            Some(credentials) => credentials,
+            None              => return false,
+        };
+
+        let hash = match self.users.get(&user) {
+            Some(hash) => hash,
+            None       => {
+                debug!("Unknown basic auth user: {}", user);
+
+                if let Some(dummy_hash) = &self.dummy_hash {
+                    let _ = bcrypt::verify(password, dummy_hash);
+                }
+
+                return false;
+            },
+        };
+
+        if let Some(cached) = self.verified.lock().unwrap().get(&user) {
+            if constant_time_eq(cached.as_bytes(), password.as_bytes()) {
+                return true;
+            }
+        }
+
+        match bcrypt::verify(&password, hash) {
+            Ok(true) => {
+                // Only one password per user is held, so the cache can't
+                // grow beyond the configured users.
+                self.verified.lock().unwrap().insert(user, password);
+                true
+            },
+            Ok(false) => false,
+            Err(e)    => {
+                error!("Failed to verify password for user {}: {}", user, e);
+                false
+            },
+        }
+    }
+}
+
+// Used to wrap the App, requiring every request to authenticate as one of
+// the given users. Requests that fail are answered with a 401.
+#[derive(Clone, Default)]
+pub struct BasicAuth {
+    authenticator: Arc<Authenticator>,
+}
+
+impl BasicAuth {
+    // Returns a new BasicAuth accepting the given users.
+    pub fn new(users: Users) -> Self {
+        Self {
+            authenticator: Arc::new(Authenticator::new(users)),
+        }
+    }
+
+    // Returns whether any users are configured. Without users, basic
+    // authentication is disabled.
+    pub fn is_enabled(&self) -> bool {
+        !self.authenticator.users.is_empty()
+    }
+}
+
+impl<S, B> Transform<S> for BasicAuth
+where
+    S: Service<
+        Request  = ServiceRequest,
+        Response = ServiceResponse<B>,
+        Error    = Error,
+    >,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request   = ServiceRequest;
+    type Response  = ServiceResponse<B>;
+    type Error     = Error;
+    type InitError = ();
+    type Transform = BasicAuthMiddleware<S>;
+    type Future    = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(BasicAuthMiddleware {
+            service,
+            authenticator: self.authenticator.clone(),
+        })
+    }
+}
+
+// The middleware created by BasicAuth for each worker.
+pub struct BasicAuthMiddleware<S> {
+    service:       S,
+    authenticator: Arc<Authenticator>,
+}
+
+impl<S, B> Service for BasicAuthMiddleware<S>
+where
+    S: Service<
+        Request  = ServiceRequest,
+        Response = ServiceResponse<B>,
+        Error    = Error,
+    >,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request  = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error    = Error;
+    type Future   = Either<S::Future, Ready<Result<Self::Response, Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let header = req.headers().get(AUTHORIZATION);
+
+        if self.authenticator.is_authorised(header) {
+            return Either::Left(self.service.call(req));
+        }
+
+        debug!("Unauthorised request for {}", req.path());
+
+        let response = HttpResponse::Unauthorized()
+            .header(WWW_AUTHENTICATE, format!("Basic realm=\"{}\"", REALM))
+            .body("Unauthorized")
+            .map_body(|_, body| body.into_body());
+
+        Either::Right(ok(req.into_response(response)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{
+        test,
+        web,
+        App,
+    };
+    use pretty_assertions::assert_eq;
+
+    // Returns users with a single user "prometheus" with the password
+    // "secret". A low cost keeps the tests quick.
+    fn users() -> Users {
+        let mut users = Users::new();
+        let hash = bcrypt::hash("secret", 4).unwrap();
+        users.insert("prometheus".into(), hash);
+
+        users
+    }
+
+    fn authenticator() -> Authenticator {
+        Authenticator::new(users())
+    }
+
+    // Returns a basic Authorization header for the given credentials.
+    fn header(credentials: &str) -> HeaderValue {
+        let value = format!("Basic {}", base64::encode(credentials));
+        HeaderValue::from_str(&value).unwrap()
+    }
+
+    #[test]
+    fn credentials_ok() {
+        let res = credentials(&header("user:pass:word"));
+        assert_eq!(res, Some(("user".into(), "pass:word".into())));
+    }
+
+    #[test]
+    fn credentials_not_basic() {
+        let res = credentials(&HeaderValue::from_static("Bearer abc"));
+        assert_eq!(res, None);
+    }
+
+    #[test]
+    fn credentials_bad_base64() {
+        let res = credentials(&HeaderValue::from_static("Basic !!!"));
+        assert_eq!(res, None);
+    }
+
+    #[test]
+    fn is_authorised_ok() {
+        let header = header("prometheus:secret");
+        assert!(authenticator().is_authorised(Some(&header)));
+    }
+
+    #[test]
+    fn is_authorised_wrong_password() {
+        let header = header("prometheus:wrong");
+        assert!(!authenticator().is_authorised(Some(&header)));
+    }
+
+    #[test]
+    fn is_authorised_unknown_user() {
+        let header = header("grafana:secret");
+        assert!(!authenticator().is_authorised(Some(&header)));
+    }
+
+    #[test]
+    fn is_authorised_missing_header() {
+        assert!(!authenticator().is_authorised(None));
+    }
+
+    #[test]
+    fn is_authorised_cached() {
+        let mut authenticator = authenticator();
+        let header = header("prometheus:secret");
+        assert!(authenticator.is_authorised(Some(&header)));
+
+        // Once verified, the password is accepted without hashing, which
+        // would now fail.
+        authenticator.users.insert("prometheus".into(), "invalid".into());
+        assert!(authenticator.is_authorised(Some(&header)));
+
+        let header = self::header("prometheus:wrong");
+        assert!(!authenticator.is_authorised(Some(&header)));
+    }
+
+    #[test]
+    fn authenticator_dummy_hash() {
+        let authenticator = authenticator();
+        let dummy_hash = authenticator.dummy_hash.unwrap();
+        let parts = dummy_hash.parse::<HashParts>().unwrap();
+        assert_eq!(parts.get_cost(), 4);
+
+        let authenticator = Authenticator::new(Users::new());
+        assert_eq!(authenticator.dummy_hash, None);
+    }
+
+    #[test]
+    fn constant_time_eq_ok() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secrets"));
+    }
+
+    #[actix_rt::test]
+    async fn middleware_unauthorised() {
+        let mut server = test::init_service(
+            App::new()
+                .wrap(BasicAuth::new(users()))
+                .route("/", web::get().to(HttpResponse::Ok))
+        ).await;
+
+        let request = test::TestRequest::get().uri("/").to_request();
+        let response = test::call_service(&mut server, request).await;
+
+        assert_eq!(response.status(), 401);
+        assert_eq!(
+            response.headers().get(WWW_AUTHENTICATE).unwrap(),
+            "Basic realm=\"jail_exporter\"",
+        );
+    }
+
+    #[actix_rt::test]
+    async fn middleware_authorised() {
+        let mut server = test::init_service(
+            App::new()
+                .wrap(BasicAuth::new(users()))
+                .route("/", web::get().to(HttpResponse::Ok))
+        ).await;
+
+        let request = test::TestRequest::get()
+            .uri("/")
+            .header(AUTHORIZATION, header("prometheus:secret"))
+            .to_request();
+        let response = test::call_service(&mut server, request).await;
+
+        assert_eq!(response.status(), 200);
+    }
+}
@@ -0,0 +1,194 @@
+//
+// jail_exporter
+//
+// This module deals with matching client addresses against IPv4 and IPv6
+// networks given in CIDR notation.
+//
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use std::fmt;
+use std::net::{
+    IpAddr,
+    Ipv4Addr,
+};
+use std::str::FromStr;
+
+// Returns the IPv4 address embedded in an IPv4-mapped IPv6 address, as seen
+// for IPv4 clients of a dual stack listener, or the address unchanged.
+fn unmap(address: IpAddr) -> IpAddr {
+    match address {
+        IpAddr::V6(v6) => {
+            match v6.segments() {
+                [0, 0, 0, 0, 0, 0xffff, high, low] => {
+                    let v4 = (u32::from(high) << 16) | u32::from(low);
+                    IpAddr::V4(Ipv4Addr::from(v4))
+                },
+                _ => address,
+            }
+        },
+        IpAddr::V4(_) => address,
+    }
+}
+
+// Returns the address as a 128 bit integer along with its length in bits,
+// so both address families can be masked the same way.
+fn bits(address: IpAddr) -> (u128, u8) {
+    match address {
+        IpAddr::V4(v4) => (u128::from(u32::from(v4)), 32),
+        IpAddr::V6(v6) => (u128::from(v6), 128),
+    }
+}
+
+// An IPv4 or IPv6 network, such as 192.0.2.0/24 or 2001:db8::/32.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cidr {
+    address: IpAddr,
+    prefix:  u8,
+}
+
+impl Cidr {
+    // Returns whether the given address is within this network. IPv4-mapped
+    // IPv6 addresses are matched against IPv4 networks.
+    pub fn contains(&self, address: &IpAddr) -> bool {
+        let (network, length) = bits(self.address);
+        let (address, address_length) = bits(unmap(*address));
+
+        if length != address_length {
+            return false;
+        }
+
+        // A /0 would overflow the shift below, and matches everything in
+        // the family anyway.
+        if self.prefix == 0 {
+            return true;
+        }
+
+        let shift = u32::from(length - self.prefix);
+        (network >> shift) == (address >> shift)
+    }
+}
+
+// Parses ADDRESS/PREFIX, or a bare ADDRESS which is taken to be a single
+// host. Host bits set in the address are rejected, as they usually indicate
+// a typo.
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '/');
+
+        let address = parts.next().unwrap_or_default();
+        let address = match address.parse::<IpAddr>() {
+            Ok(address) => unmap(address),
+            Err(_)      => {
+                return Err(format!("'{}' is not a valid IP address", s));
+            },
+        };
+
+        let (network, length) = bits(address);
+
+        let prefix = match parts.next() {
+            Some(prefix) => {
+                match prefix.parse::<u8>() {
+                    Ok(prefix) if prefix <= length => prefix,
+                    _ => return Err(format!("'{}' has an invalid prefix", s)),
+                }
+            },
+            None => length,
+        };
+
+        let host_bits = u32::from(length - prefix);
+        if host_bits > 0 && network.trailing_zeros() < host_bits {
+            return Err(format!("'{}' has host bits set", s));
+        }
+
+        Ok(Self {
+            address,
+            prefix,
+        })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn cidr(s: &str) -> Cidr {
+        s.parse().unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn from_str_bare_address() {
+        assert_eq!(cidr("192.0.2.1").to_string(), "192.0.2.1/32");
+        assert_eq!(cidr("2001:db8::1").to_string(), "2001:db8::1/128");
+    }
+
+    #[test]
+    fn from_str_invalid() {
+        assert!("".parse::<Cidr>().is_err());
+        assert!("192.0.2.0/".parse::<Cidr>().is_err());
+        assert!("192.0.2.0/33".parse::<Cidr>().is_err());
+        assert!("2001:db8::/129".parse::<Cidr>().is_err());
+        assert!("example.com/24".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn from_str_host_bits_set() {
+        assert!("192.0.2.1/24".parse::<Cidr>().is_err());
+        assert!("2001:db8::1/32".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn contains_ipv4() {
+        let network = cidr("192.0.2.0/24");
+
+        assert!(network.contains(&ip("192.0.2.0")));
+        assert!(network.contains(&ip("192.0.2.255")));
+        assert!(!network.contains(&ip("192.0.3.0")));
+        assert!(!network.contains(&ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn contains_ipv6() {
+        let network = cidr("2001:db8::/32");
+
+        assert!(network.contains(&ip("2001:db8::1")));
+        assert!(network.contains(&ip("2001:db8:ffff::1")));
+        assert!(!network.contains(&ip("2001:db9::1")));
+        assert!(!network.contains(&ip("192.0.2.1")));
+    }
+
+    #[test]
+    fn contains_ipv4_mapped() {
+        let network = cidr("192.0.2.0/24");
+
+        assert!(network.contains(&ip("::ffff:192.0.2.1")));
+        assert!(!network.contains(&ip("::ffff:192.0.3.1")));
+    }
+
+    #[test]
+    fn contains_zero_prefix() {
+        assert!(cidr("0.0.0.0/0").contains(&ip("203.0.113.1")));
+        assert!(!cidr("0.0.0.0/0").contains(&ip("2001:db8::1")));
+        assert!(cidr("::/0").contains(&ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn contains_host() {
+        let host = cidr("192.0.2.1");
+
+        assert!(host.contains(&ip("192.0.2.1")));
+        assert!(!host.contains(&ip("192.0.2.2")));
+    }
+}
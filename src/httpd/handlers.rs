@@ -5,44 +5,290 @@
 //
 #![forbid(unsafe_code)]
 #![deny(missing_docs)]
-use actix_web::HttpResponse;
-use actix_web::http::header::CONTENT_TYPE;
-use actix_web::web::Data;
+use actix_web::{
+    HttpRequest,
+    HttpResponse,
+};
+use actix_web::http::header::{
+    CACHE_CONTROL,
+    CONTENT_TYPE,
+};
+use actix_web::web::{
+    Data,
+    Path,
+    Query,
+};
+use crate::jails::jail_ids;
+use jail_exporter::{
+    ExporterError,
+    JailEvent,
+};
 use log::debug;
 use mime::{
+    TEXT_EVENT_STREAM,
     TEXT_HTML_UTF_8,
     TEXT_PLAIN_UTF_8,
 };
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use std::time::Instant;
 
+use super::query::MetricsQuery;
+use super::timeout::{
+    timed_out_marker,
+    TimeoutAction,
+};
+use super::templates::{
+    render_index_page,
+    Sort,
+    Status as IndexStatus,
+};
 use super::AppState;
 
-// Displays the index page. This is a page which simply links to the actual
-// telemetry path.
-pub(in crate::httpd) fn index(data: Data<AppState>) -> HttpResponse {
+// Body of the health and readiness responses. The reason is only given when
+// the exporter isn't ready.
+#[derive(Debug, PartialEq, Serialize)]
+struct Status {
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
+// Returns the reason the exporter isn't ready to serve metrics, if any.
+fn not_ready_reason(exporter: &jail_exporter::Exporter) -> Option<String> {
+    if let Err(e) = crate::is_racct_rctl_available() {
+        return Some(e.to_string());
+    }
+
+    exporter
+        .last_collection_error()
+        .map(|e| format!("last collection failed: {}", e))
+}
+
+// Reports that the process is alive. This never collects metrics.
+pub(in crate::httpd) fn healthy() -> HttpResponse {
+    debug!("Processing health check");
+
+    HttpResponse::Ok().json(Status {
+        status: "healthy",
+        reason: None,
+    })
+}
+
+// Reports whether RACCT/RCTL is still available and the last collection
+// succeeded. This never collects metrics, so it is cheap enough for load
+// balancers to call frequently.
+pub(in crate::httpd) fn ready(data: Data<AppState>) -> HttpResponse {
+    debug!("Processing readiness check");
+
+    match not_ready_reason(&data.exporter) {
+        None => {
+            HttpResponse::Ok().json(Status {
+                status: "ready",
+                reason: None,
+            })
+        },
+        Some(reason) => {
+            debug!("Not ready: {}", reason);
+
+            HttpResponse::ServiceUnavailable().json(Status {
+                status: "not ready",
+                reason: Some(reason),
+            })
+        },
+    }
+}
+
+// Body of the events response.
+#[derive(Debug, Serialize)]
+struct Events {
+    events: Vec<JailEvent>,
+}
+
+// Lists the jail events seen by recent collections, oldest first. This never
+// collects metrics.
+pub(in crate::httpd) fn events(data: Data<AppState>) -> HttpResponse {
+    debug!("Listing jail events");
+
+    HttpResponse::Ok().json(Events {
+        events: data.exporter.events(),
+    })
+}
+
+// Query parameters accepted by the history route.
+#[derive(Debug, Deserialize)]
+pub(in crate::httpd) struct HistoryQuery {
+    jail:     String,
+    resource: String,
+    since:    Option<u64>,
+}
+
+// Body of the history response. Values are (timestamp, value) pairs.
+#[derive(Debug, Serialize)]
+struct History<'a> {
+    jail:     &'a str,
+    resource: &'a str,
+    values:   Vec<(u64, usize)>,
+}
+
+// Returns the recent values of a resource for a jail, as kept by the
+// exporter between collections. This never collects metrics.
+pub(in crate::httpd) fn history(
+    data: Data<AppState>,
+    query: Query<HistoryQuery>,
+) -> HttpResponse {
+    debug!("Processing history request for jail {}", query.jail);
+
+    if data.exporter.history_size() == 0 {
+        return HttpResponse::NotFound()
+            .header(CONTENT_TYPE, TEXT_PLAIN_UTF_8)
+            .body("history is disabled");
+    }
+
+    let resource = match query.resource.parse::<rctl::Resource>() {
+        Ok(resource) => resource,
+        Err(_)       => {
+            return HttpResponse::BadRequest()
+                .header(CONTENT_TYPE, TEXT_PLAIN_UTF_8)
+                .body(format!("unknown resource: {}", query.resource));
+        },
+    };
+
+    let since = query.since.unwrap_or_default();
+
+    match data.exporter.history(&query.jail, resource, since) {
+        Some(values) => {
+            HttpResponse::Ok().json(History {
+                jail:     &query.jail,
+                resource: &query.resource,
+                values,
+            })
+        },
+        None => {
+            HttpResponse::NotFound()
+                .header(CONTENT_TYPE, TEXT_PLAIN_UTF_8)
+                .body(format!("jail not found: {}", query.jail))
+        },
+    }
+}
+
+// Query parameters accepted by the index page, selecting how the jail table
+// is sorted.
+#[derive(Debug, Deserialize)]
+pub(in crate::httpd) struct IndexQuery {
+    order: Option<String>,
+    sort:  Option<String>,
+}
+
+// Displays the index page. This links to the actual telemetry path, and
+// shows the jails as of the last collection. Rendering the page never
+// collects metrics itself.
+pub(in crate::httpd) fn index(
+    data: Data<AppState>,
+    query: Query<IndexQuery>,
+) -> HttpResponse {
     debug!("Displaying index page");
 
-    let body = &data.index_page;
+    let sort = Sort::from_params(query.sort.as_ref(), query.order.as_ref());
 
-    HttpResponse::Ok()
-        .header(CONTENT_TYPE, TEXT_HTML_UTF_8)
-        .body(body)
+    let metric_families = data.exporter.snapshot();
+    let status = IndexStatus {
+        metric_families: &metric_families,
+        scrape_duration: data.exporter.last_collection_duration(),
+        uptime:          data.started.elapsed(),
+    };
+
+    match render_index_page(&data.telemetry_path, &status, sort) {
+        Ok(body) => {
+            HttpResponse::Ok()
+                .header(CONTENT_TYPE, TEXT_HTML_UTF_8)
+                .body(body)
+        },
+        Err(e) => {
+            HttpResponse::InternalServerError()
+                .header(CONTENT_TYPE, TEXT_PLAIN_UTF_8)
+                .body(format!("{}", e))
+        },
+    }
 }
 
-// Returns a HttpResponse containing the Prometheus Exporter output, or an
-// InternalServerError if things fail for some reason.
-pub(in crate::httpd) fn metrics(data: Data<AppState>) -> HttpResponse {
-    debug!("Processing metrics request");
+// The outcome of collecting the metrics for a request.
+enum Export {
+    // The encoded metrics.
+    Metrics(Vec<u8>),
+    // The query names a jail that isn't running.
+    JailNotFound,
+    // Collection was abandoned, and the timeout action asks for a 503.
+    TimedOut,
+}
 
-    // Get the exporter from the state
-    let exporter = &(data.exporter);
+// Collects the metrics and encodes them, keeping only those requested by
+// the query. Given a deadline, collection is abandoned once it passes and
+// the output is marked as timed out, unless the action asks for a 503. A
+// single collection serves both the full and the restricted output.
+fn export(
+    exporter: &jail_exporter::Exporter,
+    query: &MetricsQuery,
+    deadline: Option<Instant>,
+    action: TimeoutAction,
+) -> Result<Export, ExporterError> {
+    let (mut metric_families, timed_out) = match deadline {
+        Some(deadline) => exporter.gather_until(deadline)?,
+        None           => (exporter.gather()?, false),
+    };
 
-    // Exporter could fail.
-    match exporter.export() {
-        Ok(o) => {
+    if timed_out {
+        debug!("Abandoned collection before the scrape timed out");
+
+        if action == TimeoutAction::Unavailable {
+            return Ok(Export::TimedOut);
+        }
+    }
+    else if let Some(jail) = query.jail() {
+        // Only a finished collection tells us the jail isn't running.
+        if !jail_ids(&metric_families).contains_key(jail.as_str()) {
+            return Ok(Export::JailNotFound);
+        }
+    }
+
+    query.retain(exporter, &mut metric_families);
+
+    // Added after the query is applied, so the marker is always served.
+    if timed_out {
+        metric_families.extend(timed_out_marker());
+    }
+
+    jail_exporter::encode(&metric_families).map(Export::Metrics)
+}
+
+// Returns a HttpResponse containing the Prometheus Exporter output, a
+// NotFound if the requested jail isn't running, a ServiceUnavailable if
+// collection was abandoned before the scrape timed out, or an
+// InternalServerError if things fail for some reason.
+fn export_response(
+    query: &MetricsQuery,
+    exported: Result<Export, ExporterError>,
+) -> HttpResponse {
+    match exported {
+        Ok(Export::Metrics(o)) => {
             HttpResponse::Ok()
                 .header(CONTENT_TYPE, TEXT_PLAIN_UTF_8)
                 .body(o)
         },
+        Ok(Export::JailNotFound) => {
+            let jail = query.jail().cloned().unwrap_or_default();
+
+            HttpResponse::NotFound()
+                .header(CONTENT_TYPE, TEXT_PLAIN_UTF_8)
+                .body(format!("jail not found: {}", jail))
+        },
+        Ok(Export::TimedOut) => {
+            HttpResponse::ServiceUnavailable()
+                .header(CONTENT_TYPE, TEXT_PLAIN_UTF_8)
+                .body("collection abandoned before the scrape timed out")
+        },
         Err(e) => {
             HttpResponse::InternalServerError()
                 .header(CONTENT_TYPE, TEXT_PLAIN_UTF_8)
@@ -51,6 +297,71 @@ pub(in crate::httpd) fn metrics(data: Data<AppState>) -> HttpResponse {
     }
 }
 
+// Returns the response to a metrics request, or a BadRequest if the query
+// is invalid.
+fn metrics_response(
+    data: &AppState,
+    req: &HttpRequest,
+    query: Result<MetricsQuery, String>,
+) -> HttpResponse {
+    let query = match query {
+        Ok(query) => query,
+        Err(e)    => {
+            return HttpResponse::BadRequest()
+                .header(CONTENT_TYPE, TEXT_PLAIN_UTF_8)
+                .body(e);
+        },
+    };
+
+    // Get the exporter from the state
+    let exporter = &(data.exporter);
+
+    // Prometheus tells us how long it will wait for us.
+    let deadline = data.scrape_timeout.deadline(req);
+    let action = data.scrape_timeout.action;
+
+    // Exporter could fail.
+    export_response(&query, export(exporter, &query, deadline, action))
+}
+
+// Serves the metrics, restricted by the query parameters.
+pub(in crate::httpd) fn metrics(
+    req: HttpRequest,
+    data: Data<AppState>,
+    query: Query<Vec<(String, String)>>,
+) -> HttpResponse {
+    debug!("Processing metrics request");
+
+    metrics_response(&data, &req, MetricsQuery::from_pairs(&query))
+}
+
+// Serves the metrics of the jail named in the path, further restricted by
+// the query parameters.
+pub(in crate::httpd) fn jail_metrics(
+    req: HttpRequest,
+    data: Data<AppState>,
+    jail: Path<String>,
+    query: Query<Vec<(String, String)>>,
+) -> HttpResponse {
+    debug!("Processing metrics request for jail {}", jail);
+
+    let query = MetricsQuery::from_pairs(&query)
+        .map(|query| query.with_jail(jail.into_inner()));
+
+    metrics_response(&data, &req, query)
+}
+
+// Streams snapshots of every jail to the client as Server-Sent Events. The
+// snapshots are collected once for all clients by the Broadcaster.
+pub(in crate::httpd) fn stream(data: Data<AppState>) -> HttpResponse {
+    debug!("Starting jail snapshot stream");
+
+    HttpResponse::Ok()
+        .header(CACHE_CONTROL, "no-cache")
+        .header(CONTENT_TYPE, TEXT_EVENT_STREAM)
+        .streaming(Box::pin(data.broadcaster.subscribe()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -62,18 +373,255 @@ mod tests {
     };
     use pretty_assertions::assert_eq;
     use std::str;
+    use super::super::sse::Broadcaster;
+    use super::super::timeout::ScrapeTimeout;
+    use std::time::Instant;
+
+    fn state() -> AppState {
+        AppState {
+            broadcaster:    Broadcaster::new(),
+            exporter:       jail_exporter::Exporter::new(),
+            scrape_timeout: ScrapeTimeout::default(),
+            started:        Instant::now(),
+            telemetry_path: "/metrics".into(),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn healthy_ok() {
+        let mut server = test::init_service(
+            App::new()
+                .service(web::resource("/-/healthy").to(healthy))
+        ).await;
+
+        let request = test::TestRequest::get().uri("/-/healthy").to_request();
+        let bytes = test::read_response(&mut server, request).await;
+        let body = str::from_utf8(&bytes).unwrap();
+        assert_eq!(body, r#"{"status":"healthy"}"#);
+    }
+
+    #[actix_rt::test]
+    async fn events_ok() {
+        let mut server = test::init_service(
+            App::new()
+                .data(state())
+                .service(web::resource("/api/v1/events").to(events))
+        ).await;
+
+        let request = test::TestRequest::get()
+            .uri("/api/v1/events")
+            .to_request();
+        let bytes = test::read_response(&mut server, request).await;
+        let body = str::from_utf8(&bytes).unwrap();
+        assert_eq!(body, r#"{"events":[]}"#);
+    }
+
+    // Returns the status and body of a history request.
+    async fn get_history(state: AppState, uri: &str) -> (u16, String) {
+        let mut server = test::init_service(
+            App::new()
+                .data(state)
+                .service(web::resource("/api/v1/history").to(history))
+        ).await;
+
+        let request = test::TestRequest::get().uri(uri).to_request();
+        let response = server.call(request).await.unwrap();
+        let status = response.status().as_u16();
+
+        let bytes = test::read_body(response).await;
+        let body = str::from_utf8(&bytes).unwrap().to_owned();
+
+        (status, body)
+    }
+
+    #[actix_rt::test]
+    async fn history_disabled() {
+        let uri = "/api/v1/history?jail=www&resource=memoryuse";
+        let (status, body) = get_history(state(), uri).await;
+
+        assert_eq!(status, 404);
+        assert_eq!(body, "history is disabled");
+    }
+
+    #[actix_rt::test]
+    async fn history_unknown_jail() {
+        let state = state();
+        state.exporter.set_history_size(100);
+
+        let uri = "/api/v1/history?jail=www&resource=memoryuse&since=10";
+        let (status, body) = get_history(state, uri).await;
+
+        assert_eq!(status, 404);
+        assert_eq!(body, "jail not found: www");
+    }
+
+    #[actix_rt::test]
+    async fn history_unknown_resource() {
+        let state = state();
+        state.exporter.set_history_size(100);
+
+        let uri = "/api/v1/history?jail=www&resource=disk";
+        let (status, body) = get_history(state, uri).await;
+
+        assert_eq!(status, 400);
+        assert_eq!(body, "unknown resource: disk");
+    }
+
+    #[actix_rt::test]
+    async fn history_missing_jail() {
+        let uri = "/api/v1/history?resource=memoryuse";
+        let (status, _) = get_history(state(), uri).await;
+
+        assert_eq!(status, 400);
+    }
 
     #[actix_rt::test]
+    async fn ready_ok() {
+        let state = state();
+
+        let mut server = test::init_service(
+            App::new()
+                .data(state)
+                .service(web::resource("/-/ready").to(ready))
+        ).await;
+
+        let request = test::TestRequest::get().uri("/-/ready").to_request();
+        let response = server.call(request).await.unwrap();
+        assert_eq!(response.status(), 200);
+
+        let request = test::TestRequest::get().uri("/-/ready").to_request();
+        let bytes = test::read_response(&mut server, request).await;
+        let body = str::from_utf8(&bytes).unwrap();
+        assert_eq!(body, r#"{"status":"ready"}"#);
+    }
+
     #[test]
-    async fn index_ok() {
+    fn status_not_ready_serialises_reason() {
+        let status = Status {
+            status: "not ready",
+            reason: Some("last collection failed: oops".into()),
+        };
+
+        let json = serde_json::to_string(&status).unwrap();
+        assert_eq!(
+            json,
+            r#"{"status":"not ready","reason":"last collection failed: oops"}"#,
+        );
+    }
+
+    // Requests the given URI from an app serving the metrics routes,
+    // returning the status and body.
+    async fn get_metrics(uri: &str) -> (u16, String) {
+        let state = state();
+
+        let mut server = test::init_service(
+            App::new()
+                .data(state)
+                .route("/metrics", web::get().to(metrics))
+                .route("/metrics/jail/{name}", web::get().to(jail_metrics))
+        ).await;
+
+        let request  = test::TestRequest::get().uri(uri).to_request();
+        let response = server.call(request).await.unwrap();
+        let status   = response.status().as_u16();
+        let bytes    = test::read_body(response).await;
+
+        (status, str::from_utf8(&bytes).unwrap().to_owned())
+    }
+
+    #[actix_rt::test]
+    async fn metrics_ok() {
+        let (status, body) = get_metrics("/metrics").await;
+
+        assert_eq!(status, 200);
+        assert!(body.contains("jail_exporter_build_info"));
+    }
+
+    #[actix_rt::test]
+    async fn metrics_scrape_timeout() {
+        let mut server = test::init_service(
+            App::new()
+                .data(state())
+                .route("/metrics", web::get().to(metrics))
+        ).await;
+
+        let request = test::TestRequest::get()
+            .uri("/metrics")
+            .header("X-Prometheus-Scrape-Timeout-Seconds", "10")
+            .to_request();
+        let bytes = test::read_response(&mut server, request).await;
+        let body = str::from_utf8(&bytes).unwrap();
+
+        // Collection finished well within the timeout.
+        assert!(body.contains("jail_exporter_build_info"));
+        assert!(!body.contains("jail_exporter_scrape_timed_out"));
+    }
+
+    // Returns the response to a scrape whose deadline has already passed,
+    // so collection is always abandoned.
+    async fn timed_out_response(action: TimeoutAction) -> (u16, String) {
         let exporter = jail_exporter::Exporter::new();
+        let query = MetricsQuery::default();
+        let exported = export(&exporter, &query, Some(Instant::now()), action);
 
-        let state = AppState {
-            exporter: exporter.clone(),
-            index_page: "Test Body".into(),
-        };
+        let response = test::TestRequest::default()
+            .to_srv_response(export_response(&query, exported));
+        let status   = response.status().as_u16();
+        let bytes    = test::read_body(response).await;
+
+        (status, str::from_utf8(&bytes).unwrap().to_owned())
+    }
+
+    #[actix_rt::test]
+    async fn metrics_scrape_timed_out_partial() {
+        let (status, body) = timed_out_response(TimeoutAction::Partial).await;
+
+        // Global metrics are still served, marked as missing jails.
+        assert_eq!(status, 200);
+        assert!(body.contains("\njail_exporter_build_info{"));
+        assert!(body.contains("\njail_exporter_scrape_timed_out 1\n"));
+    }
+
+    #[actix_rt::test]
+    async fn metrics_scrape_timed_out_unavailable() {
+        let action = TimeoutAction::Unavailable;
+        let (status, body) = timed_out_response(action).await;
+
+        assert_eq!(status, 503);
+        assert_eq!(body, "collection abandoned before the scrape timed out");
+    }
+
+    #[actix_rt::test]
+    async fn jail_metrics_unknown_jail() {
+        let (status, body) = get_metrics("/metrics/jail/missing").await;
+        assert_eq!(status, 404);
+        assert_eq!(body, "jail not found: missing");
 
-        let data = Data::new(state);
+        let (status, _) = get_metrics("/metrics?jail=missing").await;
+        assert_eq!(status, 404);
+    }
+
+    #[actix_rt::test]
+    async fn metrics_collect() {
+        let uri = "/metrics?collect[]=cpu&exclude%5B%5D=memory";
+        let (status, body) = get_metrics(uri).await;
+
+        assert_eq!(status, 200);
+        assert!(body.contains("jail_exporter_build_info"));
+    }
+
+    #[actix_rt::test]
+    async fn metrics_collect_unknown_group() {
+        let (status, body) = get_metrics("/metrics?collect[]=disk").await;
+
+        assert_eq!(status, 400);
+        assert_eq!(body, "unknown resource group: disk");
+    }
+
+    #[actix_rt::test]
+    #[test]
+    async fn index_ok() {
+        let data = Data::new(state());
 
         let mut server = test::init_service(
             App::new()
@@ -96,6 +644,31 @@ mod tests {
         let request = test::TestRequest::get().uri("/").to_request();
         let bytes = test::read_response(&mut server, request).await;
         let body = str::from_utf8(&bytes).unwrap();
-        assert_eq!(body, "Test Body");
+        assert!(body.contains(r#"<a href="&#x2f;metrics">Metrics</a>"#));
+    }
+
+    #[actix_rt::test]
+    async fn stream_ok() {
+        let data = Data::new(state());
+
+        let mut server = test::init_service(
+            App::new()
+                .app_data(data)
+                .service(web::resource("/api/v1/stream").to(stream))
+        ).await;
+
+        let request = test::TestRequest::get()
+            .uri("/api/v1/stream")
+            .to_request();
+        let response = server.call(request).await.unwrap();
+        assert!(response.status().is_success());
+
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .unwrap()
+            .to_str()
+            .unwrap();
+        assert_eq!(content_type, TEXT_EVENT_STREAM);
     }
 }
@@ -0,0 +1,206 @@
+//
+// jail_exporter
+//
+// This module deals with the query parameters accepted by the metrics route.
+//
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use crate::jails::{
+    retain_jail,
+    retain_shard,
+};
+use jail_exporter::{
+    Exporter,
+    ResourceGroup,
+};
+use prometheus::proto::MetricFamily;
+
+// Restrictions on the metrics served by the metrics route.
+#[derive(Debug, Default, PartialEq)]
+pub struct MetricsQuery {
+    collect: Vec<ResourceGroup>,
+    exclude: Vec<ResourceGroup>,
+    jail:    Option<String>,
+    shard:   Option<(u64, u64)>,
+}
+
+// Parses the shard parameters, which must be given together.
+fn parse_shard(shard: Option<&String>, shards: Option<&String>)
+-> Result<Option<(u64, u64)>, String> {
+    let (shard, shards) = match (shard, shards) {
+        (Some(shard), Some(shards)) => (shard, shards),
+        (None, None) => return Ok(None),
+        _ => return Err("shard and shards must be given together".to_owned()),
+    };
+
+    let shards = match shards.parse::<u64>() {
+        Ok(shards) if shards > 0 => shards,
+        _ => return Err("shards must be a positive integer".to_owned()),
+    };
+
+    match shard.parse::<u64>() {
+        Ok(shard) if shard < shards => Ok(Some((shard, shards))),
+        _ => Err(format!("shard must be an integer below {}", shards)),
+    }
+}
+
+impl MetricsQuery {
+    // Builds the query from the given query string pairs. Parameters we
+    // don't know about are ignored, but unknown resource groups are an
+    // error, as the output would silently differ from what was asked for.
+    pub fn from_pairs(pairs: &[(String, String)]) -> Result<Self, String> {
+        let mut query = Self::default();
+        let mut shard = None;
+        let mut shards = None;
+
+        for (key, value) in pairs {
+            match key.as_str() {
+                "collect[]" => query.collect.push(value.parse()?),
+                "exclude[]" => query.exclude.push(value.parse()?),
+                "jail"      => query.jail = Some(value.to_owned()),
+                "shard"     => shard = Some(value),
+                "shards"    => shards = Some(value),
+                _           => {},
+            }
+        }
+
+        query.shard = parse_shard(shard, shards)?;
+
+        Ok(query)
+    }
+
+    // Restricts the query to the given jail, as requested by the path.
+    pub fn with_jail(mut self, jail: String) -> Self {
+        self.jail = Some(jail);
+        self
+    }
+
+    // Returns the jail the query is restricted to, if any.
+    pub fn jail(&self) -> Option<&String> {
+        self.jail.as_ref()
+    }
+
+    // Returns whether metric families of the given resource group should be
+    // served. Families that don't belong to a resource group, such as
+    // jail_num, are always served.
+    fn includes(&self, group: Option<ResourceGroup>) -> bool {
+        let group = match group {
+            Some(group) => group,
+            None        => return true,
+        };
+
+        if !self.collect.is_empty() && !self.collect.contains(&group) {
+            return false;
+        }
+
+        !self.exclude.contains(&group)
+    }
+
+    // Removes the metric families and series not requested by the query.
+    // The exporter provides the resource group of each family.
+    pub fn retain(
+        &self,
+        exporter: &Exporter,
+        metric_families: &mut Vec<MetricFamily>,
+    ) {
+        metric_families.retain(|family| {
+            self.includes(exporter.resource_group(family.get_name()))
+        });
+
+        if let Some(jail) = &self.jail {
+            retain_jail(metric_families, jail);
+        }
+
+        if let Some((shard, shards)) = self.shard {
+            retain_shard(metric_families, shard, shards);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn from_pairs_ok() {
+        let query = MetricsQuery::from_pairs(&pairs(&[
+            ("collect[]", "cpu"),
+            ("collect[]", "memory"),
+            ("exclude[]", "io"),
+            ("jail", "www"),
+            ("shard", "1"),
+            ("shards", "3"),
+            ("unknown", "ignored"),
+        ])).unwrap();
+
+        assert_eq!(query, MetricsQuery {
+            collect: vec![ResourceGroup::Cpu, ResourceGroup::Memory],
+            exclude: vec![ResourceGroup::Io],
+            jail:    Some("www".into()),
+            shard:   Some((1, 3)),
+        });
+    }
+
+    #[test]
+    fn from_pairs_invalid_shard() {
+        let invalid = [
+            vec![("shard", "0")],
+            vec![("shards", "2")],
+            vec![("shard", "0"), ("shards", "0")],
+            vec![("shard", "2"), ("shards", "2")],
+            vec![("shard", "-1"), ("shards", "2")],
+        ];
+
+        for params in invalid.iter() {
+            let query = MetricsQuery::from_pairs(&pairs(params));
+            assert!(query.is_err(), "{:?} should be invalid", params);
+        }
+    }
+
+    #[test]
+    fn from_pairs_unknown_group() {
+        let query = MetricsQuery::from_pairs(&pairs(&[("collect[]", "disk")]));
+        assert!(query.is_err());
+    }
+
+    // Returns the resource group of the named metric family.
+    fn group(name: &str) -> Option<ResourceGroup> {
+        Exporter::new().resource_group(name)
+    }
+
+    #[test]
+    fn includes_everything_by_default() {
+        let query = MetricsQuery::default();
+
+        assert!(query.includes(group("jail_cputime_seconds_total")));
+        assert!(query.includes(group("jail_num")));
+    }
+
+    #[test]
+    fn includes_collect() {
+        let query = MetricsQuery::from_pairs(&pairs(&[("collect[]", "cpu")]))
+            .unwrap();
+
+        assert!(query.includes(group("jail_pcpu_used")));
+        assert!(query.includes(group("jail_num")));
+        assert!(!query.includes(group("jail_memoryuse_bytes")));
+    }
+
+    #[test]
+    fn includes_exclude() {
+        let query = MetricsQuery::from_pairs(&pairs(&[("exclude[]", "ipc")]))
+            .unwrap();
+
+        assert!(query.includes(group("jail_pcpu_used")));
+        assert!(query.includes(group("jail_num")));
+        assert!(!query.includes(group("jail_nsem")));
+    }
+}
@@ -0,0 +1,107 @@
+//
+// jail_exporter
+//
+// This module deals with sampling jail resource usage for the history
+// independently of scrapes.
+//
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use actix_rt::time;
+use actix_web::error::BlockingError;
+use actix_web::web;
+use jail_exporter::Exporter;
+use log::{
+    debug,
+    error,
+};
+use std::time::{
+    Duration,
+    Instant,
+};
+
+// Returns true if a collection other than our own started within the
+// interval, in which case it has already recorded a sample for the history.
+fn collected_recently(
+    exporter: &Exporter,
+    sampled: Option<Instant>,
+    interval: Duration,
+) -> bool {
+    let started = exporter.last_collection_started();
+
+    if started == sampled {
+        return false;
+    }
+
+    match started {
+        Some(started) => started.elapsed() < interval,
+        None          => false,
+    }
+}
+
+// Collects metrics every interval, recording a sample of each jail in the
+// history. This keeps the history served by /api/v1/history filling up while
+// nothing scrapes us, which is when it's needed most. Ticks are skipped
+// while something else collects often enough, so we don't hold up scrapes
+// or add samples for nothing.
+pub async fn run(exporter: Exporter, interval: Duration) {
+    let period = interval;
+    let mut interval = time::interval(interval);
+
+    // When the last collection we made started.
+    let mut sampled = None;
+
+    loop {
+        interval.tick().await;
+
+        if collected_recently(&exporter, sampled, period) {
+            debug!("Skipping history sample, collected recently");
+            continue;
+        }
+
+        // Collection is blocking, so it's kept off the HTTPd's threads.
+        let gather = {
+            let exporter = exporter.clone();
+            web::block(move || exporter.gather())
+        };
+
+        match gather.await {
+            Ok(_)                        => {},
+            Err(BlockingError::Error(e)) => {
+                error!("Failed to collect metrics for history: {}", e);
+            },
+            Err(BlockingError::Canceled) => {
+                error!("Failed to collect metrics for history: canceled");
+            },
+        }
+
+        sampled = exporter.last_collection_started();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_rt::test]
+    async fn run_samples_without_scrapes() {
+        let exporter = Exporter::new();
+        exporter.set_history_size(100);
+
+        let sampler = run(exporter.clone(), Duration::from_millis(10));
+        let _ = time::timeout(Duration::from_millis(100), sampler).await;
+
+        assert!(exporter.history_len() >= 2);
+    }
+
+    #[actix_rt::test]
+    async fn run_skips_after_collection() {
+        let exporter = Exporter::new();
+        exporter.set_history_size(100);
+        exporter.gather().unwrap();
+
+        let sampler = run(exporter.clone(), Duration::from_secs(60));
+        let _ = time::timeout(Duration::from_millis(100), sampler).await;
+
+        assert_eq!(exporter.history_len(), 1);
+    }
+}
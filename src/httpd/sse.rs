@@ -0,0 +1,240 @@
+//
+// jail_exporter
+//
+// This module deals with streaming jail snapshots to clients as Server-Sent
+// Events.
+//
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use crate::jails::jail_values;
+use actix_rt::time;
+use actix_web::error::BlockingError;
+use actix_web::web::{
+    self,
+    Bytes,
+};
+use futures::stream::{
+    self,
+    Stream,
+    StreamExt,
+};
+use jail_exporter::{
+    unix_time,
+    Exporter,
+};
+use log::{
+    debug,
+    error,
+};
+use prometheus::proto::MetricFamily;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{
+    Arc,
+    Mutex,
+};
+use std::time::Duration;
+use tokio::sync::broadcast::{
+    self,
+    RecvError,
+};
+
+// How many snapshots may be waiting for a slow client before the oldest are
+// dropped.
+const CHANNEL_CAPACITY: usize = 16;
+
+// The values of every jail at a point in time, as sent to stream clients.
+#[derive(Debug, Serialize)]
+struct Snapshot<'a> {
+    timestamp: u64,
+    jails:     BTreeMap<&'a str, BTreeMap<&'a str, i64>>,
+}
+
+// Returns the snapshot event for the given metric families.
+fn event(metric_families: &[MetricFamily], timestamp: u64)
+-> Result<Bytes, serde_json::Error> {
+    let snapshot = Snapshot {
+        timestamp,
+        jails: jail_values(metric_families),
+    };
+
+    let data = serde_json::to_string(&snapshot)?;
+
+    Ok(Bytes::from(format!("event: snapshot\ndata: {}\n\n", data)))
+}
+
+// Sends snapshot events to every connected stream client. Clients that fall
+// behind by more than CHANNEL_CAPACITY events miss the oldest ones rather
+// than holding up collection.
+#[derive(Clone)]
+pub struct Broadcaster {
+    latest: Arc<Mutex<Option<Bytes>>>,
+    sender: broadcast::Sender<Bytes>,
+}
+
+impl Default for Broadcaster {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+
+        Self {
+            latest: Arc::new(Mutex::new(None)),
+            sender,
+        }
+    }
+}
+
+impl Broadcaster {
+    // Returns a new Broadcaster without any clients.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    // Sends the event to every client, remembering it for clients that
+    // connect before the next one.
+    fn publish(&self, event: Bytes) {
+        *self.latest.lock().unwrap() = Some(event.clone());
+
+        // This only fails when there are no clients left to send to.
+        let _ = self.sender.send(event);
+    }
+
+    // Returns the events for a new client, starting with the latest event if
+    // there is one.
+    pub fn subscribe(&self)
+    -> impl Stream<Item = Result<Bytes, actix_web::Error>> {
+        let receiver = self.sender.subscribe();
+        let latest = self.latest.lock().unwrap().clone();
+
+        let events = stream::unfold(receiver, |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => return Some((event, receiver)),
+                    Err(RecvError::Lagged(skipped)) => {
+                        debug!("Stream client skipped {} snapshots", skipped);
+                    },
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        });
+
+        stream::iter(latest).chain(events).map(Ok)
+    }
+
+    // Collects metrics every interval and sends a snapshot to the connected
+    // clients. Nothing is collected while there are no clients.
+    pub async fn run(self, exporter: Exporter, interval: Duration) {
+        let mut interval = time::interval(interval);
+
+        loop {
+            interval.tick().await;
+
+            if self.sender.receiver_count() == 0 {
+                // Don't greet the next client with an old snapshot.
+                *self.latest.lock().unwrap() = None;
+                continue;
+            }
+
+            // Collection is blocking, so it's kept off the HTTPd's threads.
+            let gather = {
+                let exporter = exporter.clone();
+                web::block(move || exporter.gather())
+            };
+
+            let metric_families = match gather.await {
+                Ok(metric_families) => metric_families,
+                Err(BlockingError::Error(e)) => {
+                    error!("Failed to collect metrics for stream: {}", e);
+                    continue;
+                },
+                Err(BlockingError::Canceled) => {
+                    error!("Failed to collect metrics for stream: canceled");
+                    continue;
+                },
+            };
+
+            match event(&metric_families, unix_time()) {
+                Ok(event) => self.publish(event),
+                Err(e)    => error!("Failed to encode snapshot: {}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use prometheus::{
+        IntGaugeVec,
+        Opts,
+        Registry,
+    };
+
+    fn event_bytes(s: &str) -> Bytes {
+        Bytes::from(s.to_owned())
+    }
+
+    #[test]
+    fn event_ok() {
+        let registry = Registry::new();
+        let opts = Opts::new("jail_id", "jail ID");
+        let jail_id = IntGaugeVec::new(opts, &["name"]).unwrap();
+        jail_id.with_label_values(&["www"]).set(3);
+        registry.register(Box::new(jail_id)).unwrap();
+
+        let event = event(&registry.gather(), 1234).unwrap();
+
+        assert_eq!(
+            event,
+            event_bytes(concat!(
+                "event: snapshot\n",
+                r#"data: {"timestamp":1234,"jails":{"www":{"id":3}}}"#,
+                "\n\n",
+            )),
+        );
+    }
+
+    #[actix_rt::test]
+    async fn subscribe_starts_with_latest() {
+        let broadcaster = Broadcaster::new();
+        broadcaster.publish(event_bytes("old"));
+        broadcaster.publish(event_bytes("latest"));
+
+        let mut events = Box::pin(broadcaster.subscribe());
+        broadcaster.publish(event_bytes("next"));
+
+        let event = events.next().await.unwrap().unwrap();
+        assert_eq!(event, event_bytes("latest"));
+
+        let event = events.next().await.unwrap().unwrap();
+        assert_eq!(event, event_bytes("next"));
+    }
+
+    #[actix_rt::test]
+    async fn subscribe_drops_oldest() {
+        let broadcaster = Broadcaster::new();
+        let mut events = Box::pin(broadcaster.subscribe());
+
+        // Publishing never waits for the client, which misses the oldest
+        // events once it falls too far behind.
+        let total = CHANNEL_CAPACITY + 4;
+        for i in 0..total {
+            broadcaster.publish(event_bytes(&i.to_string()));
+        }
+
+        let mut received = Vec::new();
+        drop(broadcaster);
+        while let Some(event) = events.next().await {
+            received.push(event.unwrap());
+        }
+
+        // The client carries on from the newest events still buffered.
+        let expected: Vec<Bytes> = (total - received.len()..total)
+            .map(|i| event_bytes(&i.to_string()))
+            .collect();
+
+        assert!(!received.is_empty());
+        assert!(received.len() <= CHANNEL_CAPACITY);
+        assert_eq!(received, expected);
+    }
+}
@@ -5,30 +5,303 @@
 //
 #![forbid(unsafe_code)]
 #![deny(missing_docs)]
-use crate::errors::ExporterError;
+use crate::jails::{
+    jail_gauges,
+    jail_ids,
+};
+use jail_exporter::ExporterError;
 use askama::Template;
 use log::{
     debug,
 };
+use prometheus::proto::MetricFamily;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::time::Duration;
+
+// Columns of the jail table that it can be sorted by.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(in crate::httpd) enum SortKey {
+    Name,
+    Jid,
+    Cpu,
+    Memory,
+    Processes,
+    OpenFiles,
+}
+
+impl SortKey {
+    // Every column, in the order they're displayed.
+    const ALL: [SortKey; 6] = [
+        SortKey::Name,
+        SortKey::Jid,
+        SortKey::Cpu,
+        SortKey::Memory,
+        SortKey::Processes,
+        SortKey::OpenFiles,
+    ];
+
+    // Returns the sort key with the given query parameter value.
+    fn from_param(param: &str) -> Option<Self> {
+        Self::ALL.iter().cloned().find(|key| key.param() == param)
+    }
+
+    // Returns the value of the sort query parameter for this key.
+    fn param(self) -> &'static str {
+        match self {
+            SortKey::Name      => "name",
+            SortKey::Jid       => "jid",
+            SortKey::Cpu       => "cpu",
+            SortKey::Memory    => "memory",
+            SortKey::Processes => "processes",
+            SortKey::OpenFiles => "files",
+        }
+    }
+
+    // Returns the column heading for this key.
+    fn label(self) -> &'static str {
+        match self {
+            SortKey::Name      => "Name",
+            SortKey::Jid       => "JID",
+            SortKey::Cpu       => "CPU %",
+            SortKey::Memory    => "Memory",
+            SortKey::Processes => "Processes",
+            SortKey::OpenFiles => "Open Files",
+        }
+    }
+}
+
+// The order the jail table is sorted in. Sorting is done on the server so
+// the page works without JavaScript.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(in crate::httpd) struct Sort {
+    descending: bool,
+    key:        SortKey,
+}
+
+impl Default for Sort {
+    fn default() -> Self {
+        Self {
+            descending: false,
+            key:        SortKey::Name,
+        }
+    }
+}
+
+impl Sort {
+    // Returns the sort requested by the sort and order query parameters.
+    // Unknown values fall back to sorting by name, as a stale bookmark
+    // shouldn't break the page.
+    pub fn from_params(sort: Option<&String>, order: Option<&String>)
+    -> Self {
+        let key = sort
+            .and_then(|sort| SortKey::from_param(sort))
+            .unwrap_or(SortKey::Name);
+
+        let descending = match order {
+            Some(order) => order == "desc",
+            None        => false,
+        };
+
+        Self {
+            descending,
+            key,
+        }
+    }
+
+    // Compares two jails according to this sort. Ties are broken by name so
+    // the order is stable between requests.
+    fn compare(self, a: &Jail, b: &Jail) -> Ordering {
+        let ordering = match self.key {
+            SortKey::Name      => Ordering::Equal,
+            SortKey::Jid       => a.jid.cmp(&b.jid),
+            SortKey::Cpu       => a.cpu.cmp(&b.cpu),
+            SortKey::Memory    => a.memory.cmp(&b.memory),
+            SortKey::Processes => a.processes.cmp(&b.processes),
+            SortKey::OpenFiles => a.open_files.cmp(&b.open_files),
+        }
+        .then_with(|| a.name.cmp(&b.name));
+
+        if self.descending {
+            ordering.reverse()
+        }
+        else {
+            ordering
+        }
+    }
+}
+
+// A heading of the jail table, linking to the table sorted by its column.
+struct Column {
+    href:      String,
+    indicator: &'static str,
+    label:     &'static str,
+}
+
+impl Column {
+    // Returns the heading for the given key. Following the link of the
+    // column currently sorted by reverses the order.
+    fn new(key: SortKey, sort: Sort) -> Self {
+        let current = key == sort.key;
+        let descending = current && !sort.descending;
+
+        let indicator = match (current, sort.descending) {
+            (false, _)    => "",
+            (true, false) => " \u{25b2}",
+            (true, true)  => " \u{25bc}",
+        };
+
+        Self {
+            href:  format!(
+                "?sort={}&order={}",
+                key.param(),
+                if descending { "desc" } else { "asc" },
+            ),
+            indicator,
+            label: key.label(),
+        }
+    }
+}
+
+// A row of the jail table.
+#[derive(Debug, PartialEq)]
+struct Jail {
+    cpu:        i64,
+    jid:        i64,
+    memory:     i64,
+    name:       String,
+    open_files: i64,
+    processes:  i64,
+}
+
+impl Jail {
+    // Returns the memory use in human readable units.
+    fn memory(&self) -> String {
+        format_bytes(self.memory)
+    }
+}
+
+// Returns the jails found in the gathered metrics, sorted as requested.
+fn jails(metric_families: &[MetricFamily], sort: Sort) -> Vec<Jail> {
+    let cpu        = jail_gauges(metric_families, "jail_pcpu_used");
+    let memory     = jail_gauges(metric_families, "jail_memoryuse_bytes");
+    let open_files = jail_gauges(metric_families, "jail_openfiles");
+    let processes  = jail_gauges(metric_families, "jail_maxproc");
+
+    let value = |values: &HashMap<&str, i64>, name| {
+        values.get(name).cloned().unwrap_or_default()
+    };
+
+    let mut jails: Vec<Jail> = jail_ids(metric_families)
+        .into_iter()
+        .map(|(name, jid)| {
+            Jail {
+                cpu:        value(&cpu, name),
+                jid,
+                memory:     value(&memory, name),
+                name:       name.to_owned(),
+                open_files: value(&open_files, name),
+                processes:  value(&processes, name),
+            }
+        })
+        .collect();
+
+    jails.sort_by(|a, b| sort.compare(a, b));
+
+    jails
+}
+
+// Returns the number of bytes in human readable binary units.
+fn format_bytes(bytes: i64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+// Returns the duration as days, hours, minutes and seconds, leaving out
+// leading units that are zero.
+fn format_uptime(uptime: Duration) -> String {
+    let seconds = uptime.as_secs();
+
+    let units = [
+        (seconds / 86400, "d"),
+        (seconds / 3600 % 24, "h"),
+        (seconds / 60 % 60, "m"),
+        (seconds % 60, "s"),
+    ];
+
+    let first = units
+        .iter()
+        .position(|(value, _)| *value > 0)
+        .unwrap_or(units.len() - 1);
+
+    units[first..]
+        .iter()
+        .map(|(value, unit)| format!("{}{}", value, unit))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Returns the duration of the last scrape in milliseconds.
+fn format_scrape_duration(duration: Option<Duration>) -> String {
+    match duration {
+        Some(duration) => {
+            let millis = duration.as_secs_f64() * 1000.0;
+            format!("{:.1} ms", millis)
+        },
+        None => "no scrape yet".to_owned(),
+    }
+}
 
 // Template for the index served at /. Useful for people connecting to the
-// exporter via their browser.
-// Escaping is disabled since we're passing a path and don't want the / to be
-// escaped.
+// exporter via their browser. Everything is HTML escaped, as jail names are
+// chosen by whoever runs the jail.
 #[derive(Template)]
-#[template(path = "index.html", escape = "none")]
+#[template(path = "index.html")]
 struct IndexTemplate<'a> {
-    telemetry_path: &'a str,
+    columns:         Vec<Column>,
+    jails:           Vec<Jail>,
+    scrape_duration: String,
+    telemetry_path:  &'a str,
+    uptime:          String,
+    version:         &'a str,
+}
+
+// The state of the exporter shown on the index page.
+pub(in crate::httpd) struct Status<'a> {
+    pub metric_families: &'a [MetricFamily],
+    pub scrape_duration: Option<Duration>,
+    pub uptime:          Duration,
 }
 
 // Renders the index page template.
 pub(in crate::httpd)
-fn render_index_page(telemetry_path: &str)
+fn render_index_page(telemetry_path: &str, status: &Status, sort: Sort)
 -> Result<String, ExporterError> {
     debug!("Rendering index template");
 
     let index_template = IndexTemplate {
-        telemetry_path: &telemetry_path,
+        columns:         SortKey::ALL
+            .iter()
+            .map(|key| Column::new(*key, sort))
+            .collect(),
+        jails:           jails(status.metric_families, sort),
+        scrape_duration: format_scrape_duration(status.scrape_duration),
+        telemetry_path:  &telemetry_path,
+        uptime:          format_uptime(status.uptime),
+        version:         env!("CARGO_PKG_VERSION"),
     };
 
     let rendered = index_template.render()?;
@@ -39,27 +312,136 @@ fn render_index_page(telemetry_path: &str)
 #[cfg(test)]
 mod tests {
     use super::*;
-    use indoc::indoc;
     use pretty_assertions::assert_eq;
+    use prometheus::{
+        IntGaugeVec,
+        Opts,
+        Registry,
+    };
+
+    // Returns metric families for the given jails, as (name, jid, pcpu).
+    fn metric_families(jails: &[(&str, i64, i64)]) -> Vec<MetricFamily> {
+        let registry = Registry::new();
+
+        let gauge = |name: &str| {
+            let opts = Opts::new(name, "help");
+            let gauge = IntGaugeVec::new(opts, &["name"]).unwrap();
+            registry.register(Box::new(gauge.clone())).unwrap();
+            gauge
+        };
+
+        let jail_id = gauge("jail_id");
+        let pcpu    = gauge("jail_pcpu_used");
+
+        for (name, jid, cpu) in jails {
+            jail_id.with_label_values(&[name]).set(*jid);
+            pcpu.with_label_values(&[name]).set(*cpu);
+        }
+
+        registry.gather()
+    }
+
+    fn sort(sort: &str, order: &str) -> Sort {
+        Sort::from_params(Some(&sort.into()), Some(&order.into()))
+    }
+
+    fn status(metric_families: &[MetricFamily]) -> Status<'_> {
+        Status {
+            metric_families,
+            scrape_duration: Some(Duration::from_micros(12_345)),
+            uptime:          Duration::from_secs(90_061),
+        }
+    }
 
     #[test]
     fn render_index_page_ok() {
-        let path = "/a1b2c3";
-        let rendered = render_index_page(&path).unwrap();
-        let ok = indoc!(
-            r#"
-            <!DOCTYPE html>
-            <html lang="en">
-                <head>
-                    <meta charset="UTF-8">
-                    <title>Jail Exporter</title>
-                </head>
-                <body>
-                    <h1>Jail Exporter</h1>
-                    <p><a href="/a1b2c3">Metrics</a></p>
-                </body>
-            </html>"#
-        );
-        assert_eq!(rendered, ok);
+        let metric_families = metric_families(&[("www", 3, 25)]);
+        let status = status(&metric_families);
+
+        let rendered = render_index_page("/a1b2c3", &status, Sort::default())
+            .unwrap();
+
+        assert!(rendered.contains(r#"<a href="&#x2f;a1b2c3">Metrics</a>"#));
+        assert!(rendered.contains("<dd>1d 1h 1m 1s</dd>"));
+        assert!(rendered.contains("<dd>12.3 ms</dd>"));
+        assert!(rendered.contains(env!("CARGO_PKG_VERSION")));
+        assert!(rendered.contains("<td>www</td>"));
+        assert!(rendered.contains("<td>25</td>"));
+    }
+
+    #[test]
+    fn render_index_page_escapes_jail_names() {
+        let metric_families = metric_families(&[("<b>jail</b>", 1, 0)]);
+        let status = status(&metric_families);
+
+        let rendered = render_index_page("/metrics", &status, Sort::default())
+            .unwrap();
+
+        assert!(!rendered.contains("<b>jail</b>"));
+        assert!(rendered.contains("&lt;b&gt;jail&lt;&#x2f;b&gt;"));
+    }
+
+    #[test]
+    fn render_index_page_no_jails() {
+        let status = status(&[]);
+
+        let rendered = render_index_page("/metrics", &status, Sort::default())
+            .unwrap();
+
+        assert!(rendered.contains("No jails were running"));
+        assert!(!rendered.contains("<table>"));
+    }
+
+    #[test]
+    fn jails_sorted() {
+        let metric_families = metric_families(&[
+            ("a", 3, 10),
+            ("b", 1, 30),
+            ("c", 2, 10),
+        ]);
+
+        let names = |sort| -> Vec<String> {
+            jails(&metric_families, sort)
+                .into_iter()
+                .map(|jail| jail.name)
+                .collect()
+        };
+
+        assert_eq!(names(Sort::default()), vec!["a", "b", "c"]);
+        assert_eq!(names(sort("jid", "asc")), vec!["b", "c", "a"]);
+        assert_eq!(names(sort("cpu", "desc")), vec!["b", "c", "a"]);
+    }
+
+    #[test]
+    fn sort_from_params_unknown() {
+        assert_eq!(sort("bogus", "sideways"), Sort::default());
+    }
+
+    #[test]
+    fn column_toggles_order() {
+        let sort = sort("cpu", "asc");
+
+        let cpu = Column::new(SortKey::Cpu, sort);
+        assert_eq!(cpu.href, "?sort=cpu&order=desc");
+        assert_eq!(cpu.indicator, " \u{25b2}");
+
+        let memory = Column::new(SortKey::Memory, sort);
+        assert_eq!(memory.href, "?sort=memory&order=asc");
+        assert_eq!(memory.indicator, "");
+    }
+
+    #[test]
+    fn format_bytes_ok() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+
+    #[test]
+    fn format_uptime_ok() {
+        assert_eq!(format_uptime(Duration::from_secs(0)), "0s");
+        assert_eq!(format_uptime(Duration::from_secs(61)), "1m 1s");
+        assert_eq!(format_uptime(Duration::from_secs(86400)), "1d 0h 0m 0s");
     }
 }
@@ -0,0 +1,168 @@
+//
+// jail_exporter
+//
+// This module deals with the scrape timeout Prometheus sends with each
+// scrape.
+//
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use actix_web::HttpRequest;
+use log::debug;
+use prometheus::core::Collector;
+use prometheus::proto::MetricFamily;
+use prometheus::IntGauge;
+use std::time::{
+    Duration,
+    Instant,
+};
+
+// Header in which Prometheus sends the scrape timeout, in seconds.
+const SCRAPE_TIMEOUT_HEADER: &str = "x-prometheus-scrape-timeout-seconds";
+
+// What to answer a scrape with when collection is abandoned.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimeoutAction {
+    // The jails collected in time, along with jail_exporter_scrape_timed_out.
+    Partial,
+    // A 503 Service Unavailable.
+    Unavailable,
+}
+
+// Used to work out when collection for a scrape must be abandoned.
+#[derive(Clone, Copy, Debug)]
+pub struct ScrapeTimeout {
+    pub action: TimeoutAction,
+    pub offset: Duration,
+}
+
+impl Default for ScrapeTimeout {
+    fn default() -> Self {
+        Self {
+            action: TimeoutAction::Partial,
+            offset: Duration::from_millis(500),
+        }
+    }
+}
+
+// Parses the scrape timeout header value.
+fn parse_timeout(value: &str) -> Option<Duration> {
+    match value.trim().parse::<f64>() {
+        Ok(seconds) if seconds.is_finite() && seconds > 0.0 => {
+            Some(Duration::from_secs_f64(seconds))
+        },
+        _ => None,
+    }
+}
+
+impl ScrapeTimeout {
+    // Returns the time by which collection must finish for a scrape
+    // started at now with the given timeout, leaving offset for the
+    // response to reach Prometheus. Offsets that would leave no time at all
+    // are ignored.
+    fn deadline_after(&self, now: Instant, timeout: Duration) -> Instant {
+        match timeout.checked_sub(self.offset) {
+            Some(remaining) if remaining > Duration::from_secs(0) => {
+                now + remaining
+            },
+            _ => now + timeout,
+        }
+    }
+
+    // Returns the deadline for collecting the metrics requested, or None if
+    // the request didn't come with a valid scrape timeout.
+    pub fn deadline(&self, req: &HttpRequest) -> Option<Instant> {
+        let value = req.headers().get(SCRAPE_TIMEOUT_HEADER)?;
+
+        let timeout = match value.to_str().ok().and_then(parse_timeout) {
+            Some(timeout) => timeout,
+            None          => {
+                debug!("Ignoring invalid scrape timeout: {:?}", value);
+                return None;
+            },
+        };
+
+        Some(self.deadline_after(Instant::now(), timeout))
+    }
+}
+
+// Returns the jail_exporter_scrape_timed_out metric family, marking output
+// that is missing jails.
+pub fn timed_out_marker() -> Vec<MetricFamily> {
+    let gauge = IntGauge::new(
+        "jail_exporter_scrape_timed_out",
+        "Whether collection was abandoned before the scrape timed out, \
+         leaving out the jails that weren't collected in time.",
+    ).unwrap();
+
+    gauge.set(1);
+    gauge.collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn parse_timeout_ok() {
+        assert_eq!(parse_timeout("10"), Some(Duration::from_secs(10)));
+        assert_eq!(parse_timeout("0.25"), Some(Duration::from_millis(250)));
+    }
+
+    #[test]
+    fn parse_timeout_invalid() {
+        assert_eq!(parse_timeout(""), None);
+        assert_eq!(parse_timeout("0"), None);
+        assert_eq!(parse_timeout("-1"), None);
+        assert_eq!(parse_timeout("inf"), None);
+        assert_eq!(parse_timeout("NaN"), None);
+        assert_eq!(parse_timeout("10s"), None);
+    }
+
+    #[test]
+    fn deadline_after_offset() {
+        let scrape_timeout = ScrapeTimeout::default();
+        let now = Instant::now();
+
+        let deadline = scrape_timeout.deadline_after(
+            now,
+            Duration::from_secs(10),
+        );
+        assert_eq!(deadline - now, Duration::from_millis(9500));
+
+        // There's no time left after the offset, so it's ignored.
+        let deadline = scrape_timeout.deadline_after(
+            now,
+            Duration::from_millis(400),
+        );
+        assert_eq!(deadline - now, Duration::from_millis(400));
+    }
+
+    #[test]
+    fn deadline_from_header() {
+        let scrape_timeout = ScrapeTimeout::default();
+
+        let req = TestRequest::default()
+            .header(SCRAPE_TIMEOUT_HEADER, "10")
+            .to_http_request();
+        assert!(scrape_timeout.deadline(&req).is_some());
+
+        let req = TestRequest::default()
+            .header(SCRAPE_TIMEOUT_HEADER, "soon")
+            .to_http_request();
+        assert!(scrape_timeout.deadline(&req).is_none());
+
+        let req = TestRequest::default().to_http_request();
+        assert!(scrape_timeout.deadline(&req).is_none());
+    }
+
+    #[test]
+    fn timed_out_marker_ok() {
+        let marker = timed_out_marker();
+        let output = jail_exporter::encode(&marker).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.ends_with("\njail_exporter_scrape_timed_out 1\n"));
+    }
+}
@@ -0,0 +1,782 @@
+//
+// jail_exporter
+//
+// This module deals with serving the HTTPd over TLS.
+//
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use actix_rt::signal::unix::{
+    signal,
+    SignalKind,
+};
+use actix_rt::time;
+use futures::future::{
+    select,
+    Either,
+};
+use jail_exporter::ExporterError;
+use log::{
+    debug,
+    error,
+    info,
+    warn,
+};
+use prometheus::{
+    IntCounterVec,
+    Opts,
+    Registry,
+};
+use rustls::internal::pemfile::{
+    certs,
+    pkcs8_private_keys,
+    rsa_private_keys,
+};
+use rustls::sign::{
+    self,
+    CertifiedKey,
+};
+use rustls::{
+    AllowAnyAuthenticatedClient,
+    Certificate,
+    ClientCertVerified,
+    ClientCertVerifier,
+    DistinguishedNames,
+    NoClientAuth,
+    ProtocolVersion,
+    ResolvesServerCert,
+    RootCertStore,
+    ServerConfig,
+    SignatureScheme,
+    TLSError,
+};
+use serde::Deserialize;
+use std::fs;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::sync::{
+    Arc,
+    Mutex,
+    RwLock,
+};
+use std::time::{
+    Duration,
+    SystemTime,
+};
+use x509_parser::extensions::GeneralName;
+
+// How often the certificate and key are checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
+// Modification times of the certificate and key files.
+type Modified = (Option<SystemTime>, Option<SystemTime>);
+
+// Returns a TlsError naming the file that failed to load.
+fn tls_error<E: std::fmt::Display>(path: &Path, e: E) -> ExporterError {
+    ExporterError::TlsError(format!("{}: {}", path.display(), e))
+}
+
+// Returns the modification time of the given file, if it can be found.
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+// Loads the certificate chain and private key from the given PEM files. The
+// key may be either PKCS#8 or RSA.
+fn load(cert_path: &Path, key_path: &Path)
+-> Result<CertifiedKey, ExporterError> {
+    debug!("Loading TLS certificate from {}", cert_path.display());
+    let pem = fs::read(cert_path).map_err(|e| tls_error(cert_path, e))?;
+
+    let chain = certs(&mut pem.as_slice())
+        .map_err(|_| tls_error(cert_path, "invalid certificate"))?;
+
+    if chain.is_empty() {
+        return Err(tls_error(cert_path, "no certificates found"));
+    }
+
+    debug!("Loading TLS key from {}", key_path.display());
+    let pem = fs::read(key_path).map_err(|e| tls_error(key_path, e))?;
+
+    let mut keys = pkcs8_private_keys(&mut pem.as_slice())
+        .map_err(|_| tls_error(key_path, "invalid private key"))?;
+
+    if keys.is_empty() {
+        keys = rsa_private_keys(&mut pem.as_slice())
+            .map_err(|_| tls_error(key_path, "invalid private key"))?;
+    }
+
+    let key = keys
+        .first()
+        .ok_or_else(|| tls_error(key_path, "no private key found"))?;
+
+    let key = sign::any_supported_type(key)
+        .map_err(|_| tls_error(key_path, "unsupported private key"))?;
+
+    Ok(CertifiedKey::new(chain, Arc::new(key)))
+}
+
+// Serves the certificate loaded from disk to every connection. The
+// certificate can be swapped while the server is running, so new connections
+// pick up a renewed certificate without the listener being restarted.
+pub struct CertResolver {
+    cert_path:     PathBuf,
+    certified_key: RwLock<CertifiedKey>,
+    key_path:      PathBuf,
+    modified:      Mutex<Modified>,
+}
+
+impl CertResolver {
+    // Returns a new CertResolver with the certificate and key loaded from the
+    // given paths.
+    pub fn new(cert_path: &Path, key_path: &Path)
+    -> Result<Self, ExporterError> {
+        let resolver = Self {
+            cert_path:     cert_path.into(),
+            certified_key: RwLock::new(load(cert_path, key_path)?),
+            key_path:      key_path.into(),
+            modified:      Mutex::new(Default::default()),
+        };
+
+        *resolver.modified.lock().unwrap() = resolver.modified();
+
+        Ok(resolver)
+    }
+
+    // Returns the current modification times of the certificate and key.
+    fn modified(&self) -> Modified {
+        (modified_time(&self.cert_path), modified_time(&self.key_path))
+    }
+
+    // Reloads the certificate and key from disk. If either fails to load, the
+    // current certificate continues to be served.
+    pub fn reload(&self) -> Result<(), ExporterError> {
+        let certified_key = load(&self.cert_path, &self.key_path)?;
+        *self.certified_key.write().unwrap() = certified_key;
+
+        info!("Loaded TLS certificate from {}", self.cert_path.display());
+
+        Ok(())
+    }
+
+    // Reloads the certificate and key if either file has changed since they
+    // were last checked. Returns whether a reload was attempted.
+    pub fn reload_if_changed(&self) -> Result<bool, ExporterError> {
+        let modified = self.modified();
+
+        {
+            let mut last = self.modified.lock().unwrap();
+
+            // Recording the change before reloading means a bad certificate
+            // is only reported once, rather than on every check.
+            if *last == modified {
+                return Ok(false);
+            }
+
+            *last = modified;
+        }
+
+        debug!("TLS certificate or key changed on disk");
+        self.reload()?;
+
+        Ok(true)
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(
+        &self,
+        _server_name: Option<webpki::DNSNameRef>,
+        _sigschemes: &[SignatureScheme],
+    ) -> Option<CertifiedKey> {
+        Some(self.certified_key.read().unwrap().clone())
+    }
+}
+
+// Returns the subject of the given DER certificate, along with the names it
+// may be allowed by: the subject's common names and the DNS names of its
+// subject alternative names.
+fn client_names(der: &[u8]) -> Option<(String, Vec<String>)> {
+    let (_, cert) = x509_parser::parse_x509_certificate(der).ok()?;
+    let subject = cert.subject();
+
+    let mut names: Vec<String> = subject
+        .iter_common_name()
+        .filter_map(|cn| cn.as_str().ok())
+        .map(|cn| cn.to_owned())
+        .collect();
+
+    if let Some((_, san)) = cert.tbs_certificate.subject_alternative_name() {
+        for name in &san.general_names {
+            if let GeneralName::DNSName(name) = name {
+                names.push((*name).to_owned());
+            }
+        }
+    }
+
+    Some((subject.to_string(), names))
+}
+
+// Requires clients to present a certificate signed by one of the configured
+// CAs, and optionally restricts the subject names that are accepted.
+// Rejected certificates are logged and counted, labelled by the reason they
+// were rejected.
+pub struct ClientVerifier {
+    allowed_names: Vec<String>,
+    inner:         Arc<dyn ClientCertVerifier>,
+    rejections:    IntCounterVec,
+}
+
+impl ClientVerifier {
+    // Returns a new ClientVerifier trusting the CAs in the given PEM file.
+    // Rejections are counted in a metric registered with the given registry.
+    pub fn new(ca_path: &Path, allowed_names: Vec<String>, registry: &Registry)
+    -> Result<Self, ExporterError> {
+        debug!("Loading TLS client CA from {}", ca_path.display());
+        let pem = fs::read(ca_path).map_err(|e| tls_error(ca_path, e))?;
+
+        let cas = certs(&mut pem.as_slice())
+            .map_err(|_| tls_error(ca_path, "invalid certificate"))?;
+
+        if cas.is_empty() {
+            return Err(tls_error(ca_path, "no certificates found"));
+        }
+
+        let mut roots = RootCertStore::empty();
+        for ca in &cas {
+            roots.add(ca).map_err(|e| tls_error(ca_path, e))?;
+        }
+
+        let opts = Opts::new(
+            "jail_exporter_tls_client_rejections_total",
+            "Number of TLS client certificates rejected, by reason.",
+        );
+        let rejections = IntCounterVec::new(opts, &["reason"])?;
+        registry.register(Box::new(rejections.clone()))?;
+
+        Ok(Self {
+            allowed_names,
+            inner: AllowAnyAuthenticatedClient::new(roots),
+            rejections,
+        })
+    }
+
+    // Returns whether any of the given names is in the allow-list. An empty
+    // allow-list accepts any name.
+    fn is_allowed(&self, names: &[String]) -> bool {
+        self.allowed_names.is_empty() || names.iter().any(|name| {
+            self.allowed_names
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(name))
+        })
+    }
+
+    // Logs and counts a rejected client certificate.
+    fn reject(&self, subject: &str, reason: &str, e: &TLSError) {
+        warn!("Rejected TLS client certificate {}: {}", subject, e);
+
+        self.rejections.with_label_values(&[reason]).inc();
+    }
+}
+
+impl ClientCertVerifier for ClientVerifier {
+    fn offer_client_auth(&self) -> bool {
+        true
+    }
+
+    fn client_auth_mandatory(&self) -> bool {
+        true
+    }
+
+    fn client_auth_root_subjects(&self) -> DistinguishedNames {
+        self.inner.client_auth_root_subjects()
+    }
+
+    fn verify_client_cert(&self, presented_certs: &[Certificate])
+    -> Result<ClientCertVerified, TLSError> {
+        let (subject, names) = presented_certs
+            .first()
+            .and_then(|cert| client_names(&cert.0))
+            .unwrap_or_else(|| ("<unknown>".to_owned(), Vec::new()));
+
+        if let Err(e) = self.inner.verify_client_cert(presented_certs) {
+            self.reject(&subject, "untrusted", &e);
+            return Err(e);
+        }
+
+        if !self.is_allowed(&names) {
+            let e = TLSError::General("subject is not allowed".into());
+            self.reject(&subject, "subject", &e);
+            return Err(e);
+        }
+
+        Ok(ClientCertVerified::assertion())
+    }
+}
+
+// TLS protocol versions, named as in the exporter-toolkit. Only the versions
+// our TLS library offers are supported, so TLS10 and TLS11 are rejected.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, PartialOrd)]
+pub enum TlsVersion {
+    #[serde(rename = "TLS12")]
+    Tls12,
+    #[serde(rename = "TLS13")]
+    Tls13,
+}
+
+impl TlsVersion {
+    // Every supported version, in order of preference.
+    const ALL: [Self; 2] = [TlsVersion::Tls13, TlsVersion::Tls12];
+
+    // Returns the rustls protocol version.
+    fn protocol(self) -> ProtocolVersion {
+        match self {
+            TlsVersion::Tls12 => ProtocolVersion::TLSv1_2,
+            TlsVersion::Tls13 => ProtocolVersion::TLSv1_3,
+        }
+    }
+}
+
+// Used for the TLS configuration builder
+pub struct TlsConfig {
+    cert:                 PathBuf,
+    client_allowed_names: Vec<String>,
+    client_ca:            Option<PathBuf>,
+    key:                  PathBuf,
+    max_version:          Option<TlsVersion>,
+    min_version:          Option<TlsVersion>,
+}
+
+impl TlsConfig {
+    // Returns a new TlsConfig serving the given certificate and key.
+    pub fn new(cert: PathBuf, key: PathBuf) -> Self {
+        debug!("New TlsConfig with certificate {}", cert.display());
+
+        Self {
+            cert,
+            client_allowed_names: Vec::new(),
+            client_ca:            None,
+            key,
+            max_version:          None,
+            min_version:          None,
+        }
+    }
+
+    // Sets the names a client certificate must have one of as a common name
+    // or DNS subject alternative name. Only used with a client CA.
+    pub fn client_allowed_names(mut self, names: Vec<String>) -> Self {
+        debug!("Setting TLS client_allowed_names to: {:?}", names);
+
+        self.client_allowed_names = names;
+        self
+    }
+
+    // Sets the CAs that client certificates must be signed by. Clients must
+    // present a certificate once this is set.
+    pub fn client_ca(mut self, client_ca: PathBuf) -> Self {
+        debug!("Setting TLS client_ca to: {}", client_ca.display());
+
+        self.client_ca = Some(client_ca);
+        self
+    }
+
+    // Sets the highest TLS version to negotiate.
+    pub fn max_version(mut self, version: TlsVersion) -> Self {
+        debug!("Setting TLS max_version to: {:?}", version);
+
+        self.max_version = Some(version);
+        self
+    }
+
+    // Sets the lowest TLS version to negotiate.
+    pub fn min_version(mut self, version: TlsVersion) -> Self {
+        debug!("Setting TLS min_version to: {:?}", version);
+
+        self.min_version = Some(version);
+        self
+    }
+
+    // Returns the protocol versions allowed by min_version and max_version,
+    // in order of preference.
+    fn versions(&self) -> Vec<ProtocolVersion> {
+        TlsVersion::ALL.iter()
+            .filter(|version| {
+                self.min_version.iter().all(|min| *version >= min)
+                    && self.max_version.iter().all(|max| *version <= max)
+            })
+            .map(|version| version.protocol())
+            .collect()
+    }
+
+    // Loads the certificates, returning the resolver used to reload the
+    // server certificate and a rustls ServerConfig using it.
+    pub fn load(&self, registry: &Registry)
+    -> Result<(Arc<CertResolver>, ServerConfig), ExporterError> {
+        let resolver = Arc::new(CertResolver::new(&self.cert, &self.key)?);
+
+        let mut config = match &self.client_ca {
+            Some(client_ca) => {
+                let verifier = ClientVerifier::new(
+                    client_ca,
+                    self.client_allowed_names.clone(),
+                    registry,
+                )?;
+
+                ServerConfig::new(Arc::new(verifier))
+            },
+            None => ServerConfig::new(NoClientAuth::new()),
+        };
+
+        config.cert_resolver = resolver.clone();
+        config.versions = self.versions();
+
+        if config.versions.is_empty() {
+            let e = "TLS min_version is higher than max_version".to_owned();
+            return Err(ExporterError::WebConfigError(e));
+        }
+
+        Ok((resolver, config))
+    }
+}
+
+// Reloads the certificate on SIGHUP, or when the certificate or key changes
+// on disk. Reload errors are logged and the previous certificate is kept.
+pub async fn watch(resolver: Arc<CertResolver>) {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e)     => {
+            error!("Failed to listen for SIGHUP: {}", e);
+            return;
+        },
+    };
+
+    let mut interval = time::interval(RELOAD_INTERVAL);
+
+    loop {
+        let result = match select(
+            Box::pin(hangup.recv()),
+            Box::pin(interval.tick()),
+        ).await {
+            Either::Left(_) => {
+                info!("SIGHUP received, reloading TLS certificate");
+                resolver.reload()
+            },
+            Either::Right(_) => resolver.reload_if_changed().map(|_| ()),
+        };
+
+        if let Err(e) = result {
+            error!("Failed to reload TLS certificate: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::httpd::Server;
+    use nix::sys::stat::utimes;
+    use nix::sys::time::{
+        TimeVal,
+        TimeValLike,
+    };
+    use pretty_assertions::assert_eq;
+    use rustls::{
+        ClientConfig,
+        ClientSession,
+        StreamOwned,
+    };
+    use std::io::{
+        Read,
+        Write,
+    };
+    use std::net::{
+        TcpListener,
+        TcpStream,
+    };
+    use std::thread;
+    use tempfile::TempDir;
+
+    // Writes a freshly generated self-signed certificate for localhost and
+    // its key to the given paths, returning the DER encoded certificate.
+    fn write_self_signed(cert_path: &Path, key_path: &Path) -> Vec<u8> {
+        let names = vec!["localhost".to_owned()];
+        let cert = rcgen::generate_simple_self_signed(names).unwrap();
+
+        // Each serialization is signed afresh, so the DER is read back from
+        // the PEM rather than serialized separately.
+        let pem = cert.serialize_pem().unwrap();
+        fs::write(cert_path, &pem).unwrap();
+        fs::write(key_path, cert.serialize_private_key_pem()).unwrap();
+
+        certs(&mut pem.as_bytes()).unwrap().remove(0).0
+    }
+
+    // Sets the modification time of the given files to the given number of
+    // seconds since the epoch. Back to back writes may otherwise share a
+    // modification time on filesystems with coarse timestamps.
+    fn set_modified(paths: &[&Path], seconds: i64) {
+        let time = TimeVal::seconds(seconds);
+
+        for path in paths {
+            utimes(*path, &time, &time).unwrap();
+        }
+    }
+
+    // Generates a CA certificate, writing it to the given path.
+    fn write_ca(path: &Path) -> rcgen::Certificate {
+        let mut params = rcgen::CertificateParams::new(Vec::new());
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+
+        let ca = rcgen::Certificate::from_params(params).unwrap();
+        fs::write(path, ca.serialize_pem().unwrap()).unwrap();
+
+        ca
+    }
+
+    // Returns a client certificate with the given common name and DNS subject
+    // alternative names, signed by the given CA.
+    // rcgen encodes an empty extension without any names, which webpki
+    // rejects, so at least one name should be given.
+    fn client_cert(ca: &rcgen::Certificate, cn: &str, sans: &[&str])
+    -> Vec<Certificate> {
+        let sans: Vec<String> = sans
+            .iter()
+            .map(|san| (*san).to_owned())
+            .collect();
+
+        let mut params = rcgen::CertificateParams::new(sans);
+        params.distinguished_name.push(rcgen::DnType::CommonName, cn);
+
+        let cert = rcgen::Certificate::from_params(params).unwrap();
+        let der = cert.serialize_der_with_signer(ca).unwrap();
+
+        vec![Certificate(der)]
+    }
+
+    // Returns a ClientVerifier trusting a new CA, along with the CA.
+    fn verifier(allowed_names: &[&str])
+    -> (ClientVerifier, rcgen::Certificate) {
+        let dir = TempDir::new().unwrap();
+        let ca_path = dir.path().join("ca.pem");
+        let ca = write_ca(&ca_path);
+
+        let allowed_names = allowed_names
+            .iter()
+            .map(|name| (*name).to_owned())
+            .collect();
+
+        let verifier = ClientVerifier::new(
+            &ca_path,
+            allowed_names,
+            &Registry::new(),
+        ).unwrap();
+
+        (verifier, ca)
+    }
+
+    // Returns the number of rejections counted for the given reason.
+    fn rejections(verifier: &ClientVerifier, reason: &str) -> i64 {
+        verifier.rejections.with_label_values(&[reason]).get()
+    }
+
+    // Returns the DER encoded certificate currently served by the resolver.
+    fn served_cert(resolver: &CertResolver) -> Vec<u8> {
+        let certified_key = resolver.resolve(None, &[]).unwrap();
+        certified_key.cert[0].0.clone()
+    }
+
+    #[test]
+    fn client_names_ok() {
+        let dir = TempDir::new().unwrap();
+        let ca = write_ca(&dir.path().join("ca.pem"));
+        let cert = client_cert(&ca, "prometheus", &["prom.example.com"]);
+
+        let (subject, names) = client_names(&cert[0].0).unwrap();
+
+        assert_eq!(subject, "CN=prometheus");
+        assert_eq!(names, vec!["prometheus", "prom.example.com"]);
+    }
+
+    #[test]
+    fn verify_client_cert_ok() {
+        let (verifier, ca) = verifier(&[]);
+        let cert = client_cert(&ca, "prometheus", &["prom.example.com"]);
+
+        assert!(verifier.verify_client_cert(&cert).is_ok());
+    }
+
+    #[test]
+    fn verify_client_cert_allowed_cn() {
+        let (verifier, ca) = verifier(&["Prometheus"]);
+        let cert = client_cert(&ca, "prometheus", &["prom.example.com"]);
+
+        assert!(verifier.verify_client_cert(&cert).is_ok());
+    }
+
+    #[test]
+    fn verify_client_cert_allowed_san() {
+        let (verifier, ca) = verifier(&["prom.example.com"]);
+        let cert = client_cert(&ca, "prometheus", &["prom.example.com"]);
+
+        assert!(verifier.verify_client_cert(&cert).is_ok());
+    }
+
+    #[test]
+    fn verify_client_cert_subject_not_allowed() {
+        let (verifier, ca) = verifier(&["prom.example.com"]);
+        let cert = client_cert(&ca, "grafana", &["grafana.example.com"]);
+
+        assert!(verifier.verify_client_cert(&cert).is_err());
+        assert_eq!(rejections(&verifier, "subject"), 1);
+        assert_eq!(rejections(&verifier, "untrusted"), 0);
+    }
+
+    #[test]
+    fn verify_client_cert_untrusted() {
+        let (_, other_ca) = verifier(&[]);
+        let (verifier, _) = verifier(&[]);
+        let cert = client_cert(&other_ca, "prometheus", &["prometheus"]);
+
+        assert!(verifier.verify_client_cert(&cert).is_err());
+        assert_eq!(rejections(&verifier, "untrusted"), 1);
+    }
+
+    #[test]
+    fn load_versions_default() {
+        let dir = TempDir::new().unwrap();
+        let cert_path = dir.path().join("cert.pem");
+        let key_path = dir.path().join("key.pem");
+        write_self_signed(&cert_path, &key_path);
+
+        let tls = TlsConfig::new(cert_path, key_path);
+        let (_, config) = tls.load(&Registry::new()).unwrap();
+
+        assert_eq!(config.versions, vec![
+            ProtocolVersion::TLSv1_3,
+            ProtocolVersion::TLSv1_2,
+        ]);
+    }
+
+    #[test]
+    fn load_versions_limited() {
+        let dir = TempDir::new().unwrap();
+        let cert_path = dir.path().join("cert.pem");
+        let key_path = dir.path().join("key.pem");
+        write_self_signed(&cert_path, &key_path);
+
+        let tls = TlsConfig::new(cert_path.clone(), key_path.clone())
+            .min_version(TlsVersion::Tls13);
+        let (_, config) = tls.load(&Registry::new()).unwrap();
+        assert_eq!(config.versions, vec![ProtocolVersion::TLSv1_3]);
+
+        let tls = TlsConfig::new(cert_path.clone(), key_path.clone())
+            .max_version(TlsVersion::Tls12);
+        let (_, config) = tls.load(&Registry::new()).unwrap();
+        assert_eq!(config.versions, vec![ProtocolVersion::TLSv1_2]);
+
+        let tls = TlsConfig::new(cert_path, key_path)
+            .min_version(TlsVersion::Tls13)
+            .max_version(TlsVersion::Tls12);
+        assert!(tls.load(&Registry::new()).is_err());
+    }
+
+    #[test]
+    fn load_bad_certificate() {
+        let dir = TempDir::new().unwrap();
+        let cert_path = dir.path().join("cert.pem");
+        let key_path = dir.path().join("key.pem");
+        write_self_signed(&cert_path, &key_path);
+        fs::write(&cert_path, "not a certificate").unwrap();
+
+        assert!(CertResolver::new(&cert_path, &key_path).is_err());
+    }
+
+    #[test]
+    fn load_missing_key() {
+        let dir = TempDir::new().unwrap();
+        let cert_path = dir.path().join("cert.pem");
+        let key_path = dir.path().join("key.pem");
+        write_self_signed(&cert_path, &key_path);
+        fs::remove_file(&key_path).unwrap();
+
+        assert!(CertResolver::new(&cert_path, &key_path).is_err());
+    }
+
+    #[test]
+    fn reload_if_changed_ok() {
+        let dir = TempDir::new().unwrap();
+        let cert_path = dir.path().join("cert.pem");
+        let key_path = dir.path().join("key.pem");
+        let first = write_self_signed(&cert_path, &key_path);
+        set_modified(&[&cert_path, &key_path], 1_000_000_000);
+
+        let resolver = CertResolver::new(&cert_path, &key_path).unwrap();
+        assert_eq!(served_cert(&resolver), first);
+        assert!(!resolver.reload_if_changed().unwrap());
+
+        let second = write_self_signed(&cert_path, &key_path);
+        set_modified(&[&cert_path, &key_path], 1_000_000_001);
+        assert!(resolver.reload_if_changed().unwrap());
+        assert_eq!(served_cert(&resolver), second);
+    }
+
+    #[test]
+    fn reload_keeps_certificate_on_error() {
+        let dir = TempDir::new().unwrap();
+        let cert_path = dir.path().join("cert.pem");
+        let key_path = dir.path().join("key.pem");
+        let first = write_self_signed(&cert_path, &key_path);
+
+        let resolver = CertResolver::new(&cert_path, &key_path).unwrap();
+        fs::write(&key_path, "not a key").unwrap();
+
+        assert!(resolver.reload().is_err());
+        assert_eq!(served_cert(&resolver), first);
+    }
+
+    #[test]
+    fn server_tls_ok() {
+        let dir = TempDir::new().unwrap();
+        let cert_path = dir.path().join("cert.pem");
+        let key_path = dir.path().join("key.pem");
+        let cert = write_self_signed(&cert_path, &key_path);
+
+        // Find a free port for the server.
+        let address = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap()
+        };
+
+        let server = Server::new()
+            .bind_addresses(vec![address.to_string()])
+            .tls(TlsConfig::new(cert_path, key_path));
+
+        thread::spawn(move || {
+            let mut system = actix_rt::System::new("test");
+            system.block_on(server.run())
+        });
+
+        let mut config = ClientConfig::new();
+        config.root_store.add(&Certificate(cert)).unwrap();
+        let dns_name = webpki::DNSNameRef::try_from_ascii_str("localhost")
+            .unwrap();
+        let session = ClientSession::new(&Arc::new(config), dns_name);
+
+        // Wait for the server to start listening.
+        let stream = (0..50)
+            .find_map(|_| {
+                thread::sleep(Duration::from_millis(100));
+                TcpStream::connect(address).ok()
+            })
+            .unwrap();
+
+        let mut stream = StreamOwned::new(session, stream);
+        stream.write_all(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response);
+
+        assert!(response.starts_with("HTTP/1.0 200 OK"));
+    }
+}
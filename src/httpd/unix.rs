@@ -0,0 +1,224 @@
+//
+// jail_exporter
+//
+// This module deals with listening on Unix domain sockets.
+//
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use jail_exporter::ExporterError;
+use log::{
+    debug,
+    info,
+};
+use nix::unistd::{
+    chown,
+    Gid,
+};
+use std::fmt::Display;
+use std::fs::{
+    self,
+    Permissions,
+};
+use std::io;
+use std::os::unix::fs::{
+    FileTypeExt,
+    PermissionsExt,
+};
+use std::os::unix::net::{
+    UnixListener,
+    UnixStream,
+};
+use std::path::Path;
+
+// Returns the socket path if the given listen address is a unix: address.
+pub fn socket_path(address: &str) -> Option<&Path> {
+    let mut parts = address.splitn(2, ':');
+
+    match (parts.next(), parts.next()) {
+        (Some("unix"), Some(path)) => Some(Path::new(path)),
+        _                          => None,
+    }
+}
+
+// Returns a BindAddress error naming the socket path.
+fn bind_error<E: Display>(path: &Path, e: E) -> ExporterError {
+    ExporterError::BindAddress(format!("unix:{}: {}", path.display(), e))
+}
+
+// Removes a socket file left behind by a previous run that didn't shut down
+// cleanly. Anything that isn't a socket, or a socket that another process is
+// still accepting connections on, is left alone and reported as an error.
+fn remove_stale_socket(path: &Path) -> Result<(), ExporterError> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(bind_error(path, e)),
+    };
+
+    if !metadata.file_type().is_socket() {
+        return Err(bind_error(path, "file exists and is not a socket"));
+    }
+
+    match UnixStream::connect(path) {
+        Ok(_) => Err(bind_error(path, "socket is in use")),
+        Err(ref e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+            info!("Removing stale socket {}", path.display());
+
+            fs::remove_file(path).map_err(|e| bind_error(path, e))
+        },
+        Err(e) => Err(bind_error(path, e)),
+    }
+}
+
+// Applies the given mode and group to the socket file.
+fn set_permissions(path: &Path, mode: Option<u32>, group: Option<u32>)
+-> Result<(), ExporterError> {
+    if let Some(mode) = mode {
+        debug!("Setting mode {:o} on {}", mode, path.display());
+
+        fs::set_permissions(path, Permissions::from_mode(mode))?;
+    }
+
+    if let Some(group) = group {
+        debug!("Setting group {} on {}", group, path.display());
+
+        chown(path, None, Some(Gid::from_raw(group)))?;
+    }
+
+    Ok(())
+}
+
+// Binds a listener on a socket in a private directory next to the given
+// path, applying the given mode and group there before moving the socket into
+// place. Clients can't reach the socket until it has been moved, so none can
+// connect with the permissions it was created with.
+fn bind_private(path: &Path, mode: Option<u32>, group: Option<u32>)
+-> Result<UnixListener, ExporterError> {
+    let parent = match path.parent() {
+        Some(parent) if parent != Path::new("") => parent,
+        _                                       => Path::new("."),
+    };
+
+    // Only we can enter the directory, which is removed when dropped.
+    let dir = tempfile::Builder::new()
+        .prefix(".jail_exporter")
+        .tempdir_in(parent)?;
+    let private_path = dir.path().join("socket");
+
+    let listener = UnixListener::bind(&private_path)?;
+    set_permissions(&private_path, mode, group)?;
+    fs::rename(&private_path, path)?;
+
+    Ok(listener)
+}
+
+// Binds a listener on the given socket path, applying the given mode and
+// group to the socket file. All errors are returned as BindAddress errors
+// naming the socket.
+pub fn bind(path: &Path, mode: Option<u32>, group: Option<u32>)
+-> Result<UnixListener, ExporterError> {
+    remove_stale_socket(path)?;
+
+    debug!("Binding Unix socket {}", path.display());
+    bind_private(path, mode, group).map_err(|e| bind_error(path, e))
+}
+
+// Removes our socket file on shutdown.
+pub fn remove_socket(path: &Path) {
+    debug!("Removing socket {}", path.display());
+
+    if let Err(e) = fs::remove_file(path) {
+        debug!("Failed to remove socket {}: {}", path.display(), e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tempfile::TempDir;
+
+    #[test]
+    fn socket_path_tcp() {
+        assert_eq!(socket_path("127.0.0.1:9452"), None);
+    }
+
+    #[test]
+    fn socket_path_unix() {
+        let path = socket_path("unix:/var/run/jail_exporter.sock");
+        assert_eq!(path, Some(Path::new("/var/run/jail_exporter.sock")));
+    }
+
+    #[test]
+    fn bind_sets_mode() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("test.sock");
+
+        let _listener = bind(&path, Some(0o660), None).unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, 0o660);
+    }
+
+    #[test]
+    fn bind_moves_socket_into_place() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("test.sock");
+
+        let _listener = bind(&path, Some(0o600), None).unwrap();
+
+        // The private directory the socket was bound in is gone.
+        let entries: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(entries, vec!["test.sock"]);
+
+        assert!(UnixStream::connect(&path).is_ok());
+    }
+
+    #[test]
+    fn bind_removes_stale_socket() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("test.sock");
+
+        // Dropping the listener leaves the socket file behind with nothing
+        // accepting connections on it.
+        drop(UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+
+        assert!(bind(&path, None, None).is_ok());
+    }
+
+    #[test]
+    fn bind_socket_in_use() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("test.sock");
+
+        let _listener = UnixListener::bind(&path).unwrap();
+
+        assert!(bind(&path, None, None).is_err());
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn bind_not_a_socket() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("test.sock");
+        fs::write(&path, "data").unwrap();
+
+        assert!(bind(&path, None, None).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "data");
+    }
+
+    #[test]
+    fn remove_socket_ok() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("test.sock");
+
+        let _listener = bind(&path, None, None).unwrap();
+        remove_socket(&path);
+
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,369 @@
+//
+// jail_exporter
+//
+// This module deals with the web configuration file, which uses the same
+// format as the Prometheus exporter-toolkit.
+//
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use super::auth::Users;
+use super::tls::{
+    TlsConfig,
+    TlsVersion,
+};
+use jail_exporter::ExporterError;
+use log::{
+    debug,
+    warn,
+};
+use serde::Deserialize;
+use serde_yaml::Value;
+use std::fs;
+use std::path::{
+    Path,
+    PathBuf,
+};
+
+// Client authentication policies. Only the exporter-toolkit policies that
+// can be enforced with our TLS library are supported. When no policy is
+// given, NoClientCert is used.
+#[derive(Debug, Deserialize, PartialEq)]
+enum ClientAuthType {
+    NoClientCert,
+    RequireAndVerifyClientCert,
+}
+
+// The tls_server_config section of the file.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct TlsServerConfig {
+    cert_file:           PathBuf,
+    key_file:            PathBuf,
+    #[serde(default)]
+    client_allowed_sans: Vec<String>,
+    client_auth_type:    Option<ClientAuthType>,
+    client_ca_file:      Option<PathBuf>,
+    max_version:         Option<TlsVersion>,
+    min_version:         Option<TlsVersion>,
+
+    // exporter-toolkit settings that our TLS library doesn't offer. These
+    // are accepted so that existing files work, but are ignored.
+    cipher_suites:               Option<Value>,
+    curve_preferences:           Option<Value>,
+    prefer_server_cipher_suites: Option<Value>,
+}
+
+// The web configuration file. Unknown keys are rejected, as with the
+// exporter-toolkit, so typos don't silently disable TLS or authentication.
+// Keys that the exporter-toolkit supports but we don't are ignored with a
+// warning.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct WebConfig {
+    #[serde(default)]
+    basic_auth_users:   Users,
+    http_server_config: Option<Value>,
+    tls_server_config:  Option<TlsServerConfig>,
+}
+
+// Returns a WebConfigError naming the file.
+fn config_error<E: std::fmt::Display>(path: &Path, e: E) -> ExporterError {
+    ExporterError::WebConfigError(format!("{}: {}", path.display(), e))
+}
+
+impl WebConfig {
+    // Reads the web configuration from the given file. Relative paths within
+    // the file are relative to the directory containing it.
+    pub fn from_file(path: &Path) -> Result<Self, ExporterError> {
+        debug!("Reading web config from {}", path.display());
+
+        let contents = fs::read_to_string(path)
+            .map_err(|e| config_error(path, e))?;
+
+        let mut config = Self::from_str(&contents)
+            .map_err(|e| config_error(path, e))?;
+
+        if let Some(tls) = &mut config.tls_server_config {
+            let dir = path.parent().unwrap_or_else(|| Path::new("/"));
+
+            tls.cert_file = dir.join(&tls.cert_file);
+            tls.key_file  = dir.join(&tls.key_file);
+            tls.client_ca_file = tls.client_ca_file
+                .as_ref()
+                .map(|client_ca_file| dir.join(client_ca_file));
+        }
+
+        Ok(config)
+    }
+
+    // Parses the web configuration, checking that the client authentication
+    // settings are consistent.
+    fn from_str(contents: &str) -> Result<Self, String> {
+        // An empty file is valid, and configures nothing.
+        if contents.trim().is_empty() {
+            return Ok(Self::default());
+        }
+
+        let config: Self = serde_yaml::from_str(contents)
+            .map_err(|e| e.to_string())?;
+
+        if let Some(tls) = &config.tls_server_config {
+            let verify = tls.client_auth_type
+                == Some(ClientAuthType::RequireAndVerifyClientCert);
+
+            if verify && tls.client_ca_file.is_none() {
+                return Err("client_ca_file is required for \
+                            RequireAndVerifyClientCert".to_owned());
+            }
+
+            if !verify && tls.client_ca_file.is_some() {
+                return Err("client_ca_file requires client_auth_type \
+                            RequireAndVerifyClientCert".to_owned());
+            }
+
+            if !verify && !tls.client_allowed_sans.is_empty() {
+                return Err("client_allowed_sans requires client_auth_type \
+                            RequireAndVerifyClientCert".to_owned());
+            }
+
+            if let (Some(min), Some(max)) = (tls.min_version, tls.max_version) {
+                if min > max {
+                    return Err("min_version is higher than \
+                                max_version".to_owned());
+                }
+            }
+        }
+
+        for key in config.ignored_keys() {
+            warn!("Ignoring unsupported web config setting: {}", key);
+        }
+
+        Ok(config)
+    }
+
+    // Returns the exporter-toolkit settings given in the file that we don't
+    // support.
+    fn ignored_keys(&self) -> Vec<&'static str> {
+        let mut keys = Vec::new();
+
+        if self.http_server_config.is_some() {
+            keys.push("http_server_config");
+        }
+
+        if let Some(tls) = &self.tls_server_config {
+            let settings = [
+                ("tls_server_config.cipher_suites", &tls.cipher_suites),
+                (
+                    "tls_server_config.curve_preferences",
+                    &tls.curve_preferences,
+                ),
+                (
+                    "tls_server_config.prefer_server_cipher_suites",
+                    &tls.prefer_server_cipher_suites,
+                ),
+            ];
+
+            for (key, value) in settings.iter() {
+                if value.is_some() {
+                    keys.push(*key);
+                }
+            }
+        }
+
+        keys
+    }
+
+    // Returns the users allowed to authenticate with basic auth.
+    pub fn basic_auth_users(&self) -> &Users {
+        &self.basic_auth_users
+    }
+
+    // Returns the TLS configuration, if the file configures TLS.
+    pub fn tls(&self) -> Option<TlsConfig> {
+        let config = self.tls_server_config.as_ref()?;
+
+        let mut tls = TlsConfig::new(
+            config.cert_file.clone(),
+            config.key_file.clone(),
+        );
+
+        if let Some(client_ca_file) = &config.client_ca_file {
+            tls = tls
+                .client_ca(client_ca_file.clone())
+                .client_allowed_names(config.client_allowed_sans.clone());
+        }
+
+        if let Some(max_version) = config.max_version {
+            tls = tls.max_version(max_version);
+        }
+
+        if let Some(min_version) = config.min_version {
+            tls = tls.min_version(min_version);
+        }
+
+        Some(tls)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+    use tempfile::TempDir;
+
+    #[test]
+    fn from_str_empty() {
+        let config = WebConfig::from_str("\n").unwrap();
+        assert_eq!(config, WebConfig::default());
+    }
+
+    #[test]
+    fn from_str_basic_auth_users() {
+        let config = WebConfig::from_str(indoc!(r#"
+            basic_auth_users:
+              prometheus: $2y$10$abc
+        "#)).unwrap();
+
+        let hash = config.basic_auth_users().get("prometheus");
+        assert_eq!(hash, Some(&"$2y$10$abc".to_owned()));
+        assert!(config.tls().is_none());
+    }
+
+    #[test]
+    fn from_str_tls_server_config() {
+        let config = WebConfig::from_str(indoc!(r#"
+            tls_server_config:
+              cert_file: /etc/ssl/cert.pem
+              key_file: /etc/ssl/key.pem
+              client_auth_type: RequireAndVerifyClientCert
+              client_ca_file: /etc/ssl/ca.pem
+              client_allowed_sans:
+                - prometheus.example.com
+        "#)).unwrap();
+
+        let tls = config.tls_server_config.unwrap();
+        assert_eq!(tls.cert_file, Path::new("/etc/ssl/cert.pem"));
+        assert_eq!(tls.client_ca_file, Some("/etc/ssl/ca.pem".into()));
+        assert_eq!(tls.client_allowed_sans, vec!["prometheus.example.com"]);
+    }
+
+    #[test]
+    fn from_str_unknown_key() {
+        let res = WebConfig::from_str("basic_auth_user: {}\n");
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn from_str_ignored_keys() {
+        let config = WebConfig::from_str(indoc!(r#"
+            http_server_config:
+              http2: false
+            tls_server_config:
+              cert_file: cert.pem
+              key_file: key.pem
+              min_version: TLS12
+              cipher_suites:
+                - TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256
+        "#)).unwrap();
+
+        assert!(config.tls().is_some());
+        assert_eq!(config.ignored_keys(), vec![
+            "http_server_config",
+            "tls_server_config.cipher_suites",
+        ]);
+    }
+
+    #[test]
+    fn from_str_tls_versions() {
+        let config = WebConfig::from_str(indoc!(r#"
+            tls_server_config:
+              cert_file: cert.pem
+              key_file: key.pem
+              min_version: TLS12
+              max_version: TLS13
+        "#)).unwrap();
+
+        let tls = config.tls_server_config.unwrap();
+        assert_eq!(tls.min_version, Some(TlsVersion::Tls12));
+        assert_eq!(tls.max_version, Some(TlsVersion::Tls13));
+    }
+
+    #[test]
+    fn from_str_unsupported_tls_version() {
+        let res = WebConfig::from_str(indoc!(r#"
+            tls_server_config:
+              cert_file: cert.pem
+              key_file: key.pem
+              min_version: TLS10
+        "#));
+
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn from_str_min_version_above_max_version() {
+        let res = WebConfig::from_str(indoc!(r#"
+            tls_server_config:
+              cert_file: cert.pem
+              key_file: key.pem
+              min_version: TLS13
+              max_version: TLS12
+        "#));
+
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn from_str_unsupported_client_auth_type() {
+        let res = WebConfig::from_str(indoc!(r#"
+            tls_server_config:
+              cert_file: cert.pem
+              key_file: key.pem
+              client_auth_type: RequestClientCert
+        "#));
+
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn from_str_verify_without_client_ca() {
+        let res = WebConfig::from_str(indoc!(r#"
+            tls_server_config:
+              cert_file: cert.pem
+              key_file: key.pem
+              client_auth_type: RequireAndVerifyClientCert
+        "#));
+
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn from_str_client_ca_without_verify() {
+        let res = WebConfig::from_str(indoc!(r#"
+            tls_server_config:
+              cert_file: cert.pem
+              key_file: key.pem
+              client_ca_file: ca.pem
+        "#));
+
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn from_file_relative_paths() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("web.yml");
+        fs::write(&path, indoc!(r#"
+            tls_server_config:
+              cert_file: cert.pem
+              key_file: /etc/ssl/key.pem
+        "#)).unwrap();
+
+        let config = WebConfig::from_file(&path).unwrap();
+        let tls = config.tls_server_config.unwrap();
+
+        assert_eq!(tls.cert_file, dir.path().join("cert.pem"));
+        assert_eq!(tls.key_file, Path::new("/etc/ssl/key.pem"));
+    }
+}
@@ -0,0 +1,339 @@
+// InfluxDB line protocol encoding and push
+#![forbid(unsafe_code)]
+#![forbid(missing_docs)]
+use crate::jails::jail_ids;
+use crate::signal::run_every;
+use actix_web::http::header::{
+    AUTHORIZATION,
+    CONTENT_TYPE,
+};
+use awc::Client;
+use jail_exporter::{
+    Exporter,
+    ExporterError,
+};
+use log::{
+    debug,
+    info,
+};
+use mime::TEXT_PLAIN_UTF_8;
+use prometheus::proto::{
+    MetricFamily,
+    MetricType,
+};
+use std::time::{
+    Duration,
+    SystemTime,
+    UNIX_EPOCH,
+};
+
+// Timeout for each request made to InfluxDB.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Used for the Influx builder
+pub struct Influx {
+    interval: Duration,
+    token:    Option<String>,
+    url:      String,
+}
+
+impl Influx {
+    // Returns a new Influx writing to the given /api/v2/write URL. The URL is
+    // expected to carry the org and bucket query parameters.
+    pub fn new(url: &str) -> Self {
+        debug!("New Influx writing to {}", url);
+
+        Self {
+            interval: Duration::from_secs(15),
+            token:    None,
+            url:      url.into(),
+        }
+    }
+
+    // Sets how often metrics are written.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        debug!("Setting Influx interval to: {:?}", interval);
+
+        self.interval = interval;
+        self
+    }
+
+    // Sets the API token sent with each write.
+    pub fn token(mut self, token: String) -> Self {
+        debug!("Setting Influx token");
+
+        self.token = Some(token);
+        self
+    }
+
+    // Collects metrics with the given exporter and writes them to InfluxDB.
+    async fn write(&self, exporter: &Exporter) -> Result<(), ExporterError> {
+        let metric_families = exporter.gather()?;
+
+        self.write_metrics(&metric_families).await
+    }
+
+    // Writes the given metrics to InfluxDB.
+    async fn write_metrics(&self, metric_families: &[MetricFamily])
+    -> Result<(), ExporterError> {
+        debug!("Writing metrics to {}", self.url);
+
+        let metrics = encode(metric_families, timestamp());
+
+        let mut request = Client::new()
+            .post(&self.url)
+            .header(CONTENT_TYPE, TEXT_PLAIN_UTF_8)
+            .timeout(REQUEST_TIMEOUT);
+
+        if let Some(token) = &self.token {
+            request = request.header(AUTHORIZATION, format!("Token {}", token));
+        }
+
+        let response = request
+            .send_body(metrics)
+            .await
+            .map_err(|e| ExporterError::PushError(e.to_string()))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(ExporterError::PushError(status.to_string()));
+        }
+
+        Ok(())
+    }
+
+    // Writes metrics every interval until we receive SIGINT or SIGTERM.
+    pub async fn run(self, exporter: &Exporter) -> Result<(), ExporterError> {
+        info!("Writing metrics to {} every {:?}", self.url, self.interval);
+
+        run_every(
+            self.interval,
+            "write metrics to InfluxDB",
+            || self.write(exporter),
+        ).await
+    }
+}
+
+// Returns the current time in nanoseconds, the default precision of the line
+// protocol.
+pub fn timestamp() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default()
+}
+
+// Escapes a measurement name. Commas and spaces would otherwise end the
+// measurement.
+fn escape_measurement(measurement: &str) -> String {
+    escape(measurement, &[',', ' '])
+}
+
+// Escapes a tag key or value. Commas, equals signs and spaces would otherwise
+// end the key or value.
+fn escape_tag(tag: &str) -> String {
+    escape(tag, &[',', '=', ' '])
+}
+
+// Prefixes each of the given special characters with a backslash.
+fn escape(s: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        if special.contains(&c) {
+            escaped.push('\\');
+        }
+
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+// Encodes gathered metric families in the InfluxDB line protocol.
+// Each metric family becomes a measurement, with the metric's labels as tags
+// and its value as an integer "value" field. Metrics for a jail are also
+// tagged with the jail's jid, taken from the jail_id metric.
+pub fn encode(metric_families: &[MetricFamily], timestamp: u128) -> Vec<u8> {
+    let jids = jail_ids(metric_families);
+
+    let mut output = String::new();
+
+    for family in metric_families {
+        for metric in family.get_metric() {
+            let value = match family.get_field_type() {
+                MetricType::COUNTER => metric.get_counter().get_value(),
+                MetricType::GAUGE   => metric.get_gauge().get_value(),
+                _                   => continue,
+            };
+
+            // Empty tag values aren't allowed by the line protocol.
+            let mut tags: Vec<(String, String)> = metric
+                .get_label()
+                .iter()
+                .filter(|pair| !pair.get_value().is_empty())
+                .map(|pair| {
+                    (pair.get_name().to_owned(), pair.get_value().to_owned())
+                })
+                .collect();
+
+            let jid = tags
+                .iter()
+                .find(|(key, _)| key == "name")
+                .and_then(|(_, name)| jids.get(name.as_str()));
+
+            if let Some(jid) = jid {
+                tags.push(("jid".into(), jid.to_string()));
+            }
+
+            // InfluxDB prefers tags sorted by key.
+            tags.sort();
+
+            output.push_str(&escape_measurement(family.get_name()));
+
+            for (key, value) in tags {
+                output.push(',');
+                output.push_str(&escape_tag(&key));
+                output.push('=');
+                output.push_str(&escape_tag(&value));
+            }
+
+            // All of our metrics are integers, which need an i suffix so
+            // they aren't stored as floats.
+            output.push_str(&format!(
+                " value={}i {}\n",
+                value as i64,
+                timestamp,
+            ));
+        }
+    }
+
+    output.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::serve;
+    use pretty_assertions::assert_eq;
+    use prometheus::{
+        IntGauge,
+        IntGaugeVec,
+        Opts,
+        Registry,
+    };
+
+    #[test]
+    fn encode_ok() {
+        let registry = Registry::new();
+
+        let jail_num = IntGauge::new("jail_num", "number of jails").unwrap();
+        jail_num.set(1);
+        registry.register(Box::new(jail_num)).unwrap();
+
+        let opts = Opts::new("jail_id", "jail ID");
+        let jail_id = IntGaugeVec::new(opts, &["name"]).unwrap();
+        jail_id.with_label_values(&["www"]).set(7);
+        registry.register(Box::new(jail_id)).unwrap();
+
+        let opts = Opts::new("jail_memoryuse_bytes", "memory use");
+        let memoryuse = IntGaugeVec::new(opts, &["name"]).unwrap();
+        memoryuse.with_label_values(&["www"]).set(1024);
+        registry.register(Box::new(memoryuse)).unwrap();
+
+        let output = encode(&registry.gather(), 1000);
+        let output = String::from_utf8(output).unwrap();
+
+        let ok = concat!(
+            "jail_id,jid=7,name=www value=7i 1000\n",
+            "jail_memoryuse_bytes,jid=7,name=www value=1024i 1000\n",
+            "jail_num value=1i 1000\n",
+        );
+
+        assert_eq!(output, ok);
+    }
+
+    #[test]
+    fn encode_escapes_tags() {
+        let registry = Registry::new();
+
+        let opts = Opts::new("jail_memoryuse_bytes", "memory use");
+        let memoryuse = IntGaugeVec::new(opts, &["name"]).unwrap();
+        memoryuse.with_label_values(&["a b,c=d"]).set(-5);
+        registry.register(Box::new(memoryuse)).unwrap();
+
+        let output = encode(&registry.gather(), 1000);
+        let output = String::from_utf8(output).unwrap();
+
+        assert_eq!(
+            output,
+            "jail_memoryuse_bytes,name=a\\ b\\,c\\=d value=-5i 1000\n",
+        );
+    }
+
+    #[test]
+    fn encode_skips_empty_tags() {
+        let registry = Registry::new();
+
+        let opts = Opts::new("jail_exporter_build_info", "build info");
+        let build_info = IntGaugeVec::new(opts, &["version"]).unwrap();
+        build_info.with_label_values(&[""]).set(1);
+        registry.register(Box::new(build_info)).unwrap();
+
+        let output = encode(&registry.gather(), 1000);
+        let output = String::from_utf8(output).unwrap();
+
+        assert_eq!(output, "jail_exporter_build_info value=1i 1000\n");
+    }
+
+    #[test]
+    fn escape_measurement_ok() {
+        let measurement = escape_measurement("a b,c=d");
+        assert_eq!(measurement, "a\\ b\\,c=d");
+    }
+
+    #[test]
+    fn escape_tag_ok() {
+        let tag = escape_tag("parent.child=a b,c");
+        assert_eq!(tag, "parent.child\\=a\\ b\\,c");
+    }
+
+    // Returns a registry holding only jail_num, set to 2.
+    fn registry() -> Registry {
+        let registry = Registry::new();
+
+        let jail_num = IntGauge::new("jail_num", "number of jails").unwrap();
+        jail_num.set(2);
+        registry.register(Box::new(jail_num)).unwrap();
+
+        registry
+    }
+
+    #[actix_rt::test]
+    async fn write_ok() {
+        let (url, rx) = serve("204 No Content");
+        let url = format!("{}/api/v2/write?org=jails&bucket=jails", url);
+        let influx = Influx::new(&url).token("secret".into());
+
+        influx.write_metrics(&registry().gather()).await.unwrap();
+
+        let request = rx.recv().unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/api/v2/write?org=jails&bucket=jails");
+        assert_eq!(request.header("authorization"), Some("Token secret"));
+
+        let body = String::from_utf8(request.body).unwrap();
+        assert!(body.starts_with("jail_num value=2i "));
+        assert_eq!(body.lines().count(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn write_error_status() {
+        let (url, _rx) = serve("401 Unauthorized");
+        let influx = Influx::new(&url);
+
+        let res = influx.write_metrics(&registry().gather()).await;
+
+        assert!(res.is_err());
+    }
+}
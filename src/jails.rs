@@ -0,0 +1,254 @@
+// Helpers for finding jails within gathered metrics
+#![forbid(unsafe_code)]
+#![forbid(missing_docs)]
+use jail_exporter::{
+    jail_name,
+    retain_series,
+};
+use prometheus::proto::{
+    MetricFamily,
+    MetricType,
+};
+use std::collections::{
+    BTreeMap,
+    HashMap,
+};
+
+// Returns the value of the named gauge for each jail, keyed on jail name.
+pub fn jail_gauges<'a>(metric_families: &'a [MetricFamily], gauge: &str)
+-> HashMap<&'a str, i64> {
+    metric_families
+        .iter()
+        .filter(|family| family.get_name() == gauge)
+        .flat_map(|family| family.get_metric())
+        .filter_map(|metric| {
+            let name = jail_name(metric)?;
+
+            Some((name, metric.get_gauge().get_value() as i64))
+        })
+        .collect()
+}
+
+// Returns the jid of each jail, keyed on jail name, as found in the jail_id
+// metric.
+pub fn jail_ids(metric_families: &[MetricFamily]) -> HashMap<&str, i64> {
+    jail_gauges(metric_families, "jail_id")
+}
+
+// Returns the value of every series belonging to a jail, keyed on jail name
+// and then on the metric name without its jail_ prefix.
+pub fn jail_values(metric_families: &[MetricFamily])
+-> BTreeMap<&str, BTreeMap<&str, i64>> {
+    let mut jails: BTreeMap<&str, BTreeMap<&str, i64>> = BTreeMap::new();
+
+    for family in metric_families {
+        let metric = family.get_name().trim_start_matches("jail_");
+
+        for series in family.get_metric() {
+            let name = match jail_name(series) {
+                Some(name) => name,
+                None       => continue,
+            };
+
+            let value = match family.get_field_type() {
+                MetricType::COUNTER => series.get_counter().get_value(),
+                _                   => series.get_gauge().get_value(),
+            };
+
+            jails.entry(name).or_default().insert(metric, value as i64);
+        }
+    }
+
+    jails
+}
+
+// Restricts the metric families to the series of the named jail, keeping
+// series that don't belong to any jail, such as the exporter's own metrics.
+pub fn retain_jail(metric_families: &mut Vec<MetricFamily>, name: &str) {
+    retain_series(metric_families, |jail| {
+        match jail {
+            Some(jail) => jail == name,
+            None       => true,
+        }
+    });
+}
+
+// Returns the shard, out of the given number of shards, that the named jail
+// belongs to. This uses 64 bit FNV-1a rather than the standard library's
+// hasher, whose output may change between Rust releases, as the assignment
+// must be stable for as long as the scrape configuration is.
+pub fn jail_shard(name: &str, shards: u64) -> u64 {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    let hash = name.bytes().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+    });
+
+    hash % shards
+}
+
+// Restricts the metric families to the series of jails in the given shard.
+// Series that don't belong to any jail are kept on shard 0 only.
+pub fn retain_shard(
+    metric_families: &mut Vec<MetricFamily>,
+    shard: u64,
+    shards: u64,
+) {
+    retain_series(metric_families, |jail| {
+        match jail {
+            Some(jail) => jail_shard(jail, shards) == shard,
+            None       => shard == 0,
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use prometheus::{
+        IntGauge,
+        IntGaugeVec,
+        Opts,
+        Registry,
+    };
+
+    // Returns a registry with jail_id and jail_memoryuse_bytes series for
+    // jails a and b, along with the global jail_num.
+    fn registry() -> Registry {
+        let registry = Registry::new();
+
+        let opts = Opts::new("jail_id", "jail ID");
+        let jail_id = IntGaugeVec::new(opts, &["name"]).unwrap();
+        jail_id.with_label_values(&["a"]).set(1);
+        jail_id.with_label_values(&["b"]).set(7);
+        registry.register(Box::new(jail_id)).unwrap();
+
+        let opts = Opts::new("jail_memoryuse_bytes", "memory use");
+        let memoryuse = IntGaugeVec::new(opts, &["name"]).unwrap();
+        memoryuse.with_label_values(&["b"]).set(1024);
+        registry.register(Box::new(memoryuse)).unwrap();
+
+        let jail_num = IntGauge::new("jail_num", "number of jails").unwrap();
+        jail_num.set(2);
+        registry.register(Box::new(jail_num)).unwrap();
+
+        registry
+    }
+
+    #[test]
+    fn jail_values_ok() {
+        let metric_families = registry().gather();
+        let values = jail_values(&metric_families);
+
+        let names: Vec<&str> = values.keys().cloned().collect();
+        assert_eq!(names, vec!["a", "b"]);
+
+        assert_eq!(values["a"].get("id"), Some(&1));
+        assert_eq!(values["a"].get("memoryuse_bytes"), None);
+        assert_eq!(values["b"].get("memoryuse_bytes"), Some(&1024));
+    }
+
+    #[test]
+    fn retain_jail_ok() {
+        let mut metric_families = registry().gather();
+        retain_jail(&mut metric_families, "a");
+
+        let names: Vec<&str> = metric_families
+            .iter()
+            .map(|family| family.get_name())
+            .collect();
+
+        // jail_memoryuse_bytes only had a series for b.
+        assert_eq!(names, vec!["jail_id", "jail_num"]);
+
+        let jids = jail_ids(&metric_families);
+        assert_eq!(jids.get("a"), Some(&1));
+        assert_eq!(jids.len(), 1);
+    }
+
+    #[test]
+    fn jail_shard_stable() {
+        // These must never change, or jails would move between shards when
+        // the exporter is upgraded. The first is the published FNV-1a test
+        // vector for "a".
+        assert_eq!(jail_shard("a", u64::MAX), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(jail_shard("www", 1000), 228);
+        assert_eq!(jail_shard("db01", 7), 3);
+    }
+
+    #[test]
+    fn retain_shard_union() {
+        let registry = Registry::new();
+
+        let opts = Opts::new("jail_id", "jail ID");
+        let jail_id = IntGaugeVec::new(opts, &["name"]).unwrap();
+        for jid in 1..=50 {
+            let name = format!("jail{}", jid);
+            jail_id.with_label_values(&[&name]).set(jid);
+        }
+        registry.register(Box::new(jail_id)).unwrap();
+
+        let jail_num = IntGauge::new("jail_num", "number of jails").unwrap();
+        jail_num.set(50);
+        registry.register(Box::new(jail_num)).unwrap();
+
+        // Returns the sample lines of the encoded output, without the HELP
+        // and TYPE comments, which are repeated on each shard.
+        let samples = |metric_families: &[MetricFamily]| -> Vec<String> {
+            let output = jail_exporter::encode(metric_families).unwrap();
+
+            String::from_utf8(output)
+                .unwrap()
+                .lines()
+                .filter(|line| !line.starts_with('#'))
+                .map(|line| line.to_owned())
+                .collect()
+        };
+
+        let shards = 4;
+        let mut union = Vec::new();
+
+        for shard in 0..shards {
+            let mut metric_families = registry.gather();
+            retain_shard(&mut metric_families, shard, shards);
+
+            let samples = samples(&metric_families);
+            assert!(!samples.is_empty(), "shard {} is empty", shard);
+
+            // Only shard 0 carries the global metrics.
+            let has_jail_num = samples
+                .iter()
+                .any(|line| line.starts_with("jail_num"));
+            assert_eq!(has_jail_num, shard == 0);
+
+            union.extend(samples);
+        }
+
+        let mut unsharded = samples(&registry.gather());
+
+        // Sorting also lets us see that no sample is on more than one shard.
+        union.sort();
+        unsharded.sort();
+        assert_eq!(union, unsharded);
+    }
+
+    #[test]
+    fn jail_ids_ok() {
+        let registry = Registry::new();
+
+        let opts = Opts::new("jail_id", "jail ID");
+        let jail_id = IntGaugeVec::new(opts, &["name"]).unwrap();
+        jail_id.with_label_values(&["a"]).set(1);
+        jail_id.with_label_values(&["b"]).set(7);
+        registry.register(Box::new(jail_id)).unwrap();
+
+        let metric_families = registry.gather();
+        let jids = jail_ids(&metric_families);
+
+        assert_eq!(jids.get("a"), Some(&1));
+        assert_eq!(jids.get("b"), Some(&7));
+        assert_eq!(jids.len(), 2);
+    }
+}
@@ -13,14 +13,39 @@ use prometheus::{
     Registry,
     TextEncoder,
 };
-use std::collections::HashMap;
+use prometheus::proto::{
+    Metric,
+    MetricFamily,
+};
+use serde::Serialize;
+use std::collections::{
+    HashMap,
+    VecDeque,
+};
+use std::fmt;
+use std::str::FromStr;
+use std::cmp;
 use std::sync::{
     Arc,
     Mutex,
+    MutexGuard,
+    TryLockError,
+};
+use std::thread;
+use std::time::{
+    Duration,
+    Instant,
+    SystemTime,
+    UNIX_EPOCH,
 };
 
 mod errors;
-use errors::ExporterError;
+pub use errors::ExporterError;
+mod history;
+use history::{
+    History,
+    HistoryEntry,
+};
 mod macros;
 
 /// Metrics that use bookkeeping
@@ -31,19 +56,362 @@ enum BookKept {
 
 /// Book keeping for the jail counters.
 type CounterBookKeeper = HashMap<String, i64>;
+
+/// The amount each jail counter increased by during a collection, keyed by
+/// metric name and jail name.
+pub type CounterIncreases = HashMap<(String, String), i64>;
 type Rusage = HashMap<rctl::Resource, usize>;
 
 /// Vector of String representing jails that have disappeared since the last
 /// scrape.
 type DeadJails = Vec<String>;
 
-/// Vector of String representing jails that we have seen during the current
-/// scrape.
-type SeenJails = Vec<String>;
+/// The jid of each jail seen during a scrape, keyed by jail name.
+type JailIds = HashMap<String, i64>;
+
+/// The jails seen by previous collections.
+#[derive(Debug, Default)]
+struct KnownJails {
+    /// The last jid seen for each jail that has time series.
+    jids: JailIds,
+
+    /// Whether a collection has completed, so that `jids` covers every jail
+    /// that was running.
+    complete: bool,
+}
+
+/// The number of jail events kept before the oldest are dropped.
+const EVENTS_CAPACITY: usize = 1000;
+
+/// How often a collection with a deadline checks whether the collection in
+/// progress has finished.
+const COLLECTION_LOCK_POLL: Duration = Duration::from_millis(10);
+
+/// Return the current time in seconds since the Unix epoch.
+///
+/// This is the clock used to timestamp jail events and the history, for
+/// callers that need to timestamp their own output consistently with them.
+///
+/// # Example
+///
+/// ```
+/// let timestamp = jail_exporter::unix_time();
+/// assert!(timestamp > 0);
+/// ```
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Return the value of the jail name label of the given metric, if it has
+/// one.
+///
+/// # Example
+///
+/// ```
+/// # let exporter = jail_exporter::Exporter::new();
+/// let metric_families = exporter.gather().unwrap();
+///
+/// for family in &metric_families {
+///     for metric in family.get_metric() {
+///         if let Some(name) = jail_exporter::jail_name(metric) {
+///             println!("{} has a series for {}", family.get_name(), name);
+///         }
+///     }
+/// }
+/// ```
+pub fn jail_name(metric: &Metric) -> Option<&str> {
+    metric
+        .get_label()
+        .iter()
+        .find(|label| label.get_name() == "name")
+        .map(|label| label.get_value())
+}
+
+/// Restrict the metric families to the series that `keep` returns `true`
+/// for, given the name of the jail each series belongs to, if any. Families
+/// left without any series are removed.
+///
+/// # Example
+///
+/// ```
+/// # let exporter = jail_exporter::Exporter::new();
+/// let mut metric_families = exporter.gather().unwrap();
+///
+/// // Keep the series of the www jail, along with the global metrics.
+/// jail_exporter::retain_series(&mut metric_families, |jail| {
+///     jail.is_none() || jail == Some("www")
+/// });
+/// ```
+pub fn retain_series<F>(metric_families: &mut Vec<MetricFamily>, keep: F)
+where
+    F: Fn(Option<&str>) -> bool,
+{
+    for family in metric_families.iter_mut() {
+        let metrics = family
+            .take_metric()
+            .into_iter()
+            .filter(|metric| keep(jail_name(metric)))
+            .collect();
+
+        family.set_metric(metrics);
+    }
+
+    metric_families.retain(|family| !family.get_metric().is_empty());
+}
 
 /// Vector of u8 representing gathered metrics.
 type ExportedMetrics = Vec<u8>;
 
+/// Groups of related rctl resources, allowing outputs to restrict their
+/// output to part of the metrics.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ResourceGroup {
+    /// CPU and wallclock time.
+    Cpu,
+    /// File descriptors, pseudoterminals and core dumps.
+    Files,
+    /// Filesystem reads and writes.
+    Io,
+    /// SysV message queues, semaphores and shared memory.
+    Ipc,
+    /// Memory, address space and swap.
+    Memory,
+    /// Processes and threads.
+    Processes,
+}
+
+/// The resource group of each time series that comes from rctl, keyed on
+/// metric name.
+type ResourceGroups = HashMap<&'static str, ResourceGroup>;
+
+impl From<rctl::Resource> for ResourceGroup {
+    fn from(resource: rctl::Resource) -> Self {
+        match resource {
+            rctl::Resource::CpuTime         => ResourceGroup::Cpu,
+            rctl::Resource::PercentCpu      => ResourceGroup::Cpu,
+            rctl::Resource::Wallclock       => ResourceGroup::Cpu,
+            rctl::Resource::CoreDumpSize    => ResourceGroup::Files,
+            rctl::Resource::OpenFiles       => ResourceGroup::Files,
+            rctl::Resource::PseudoTerminals => ResourceGroup::Files,
+            rctl::Resource::ReadBps         => ResourceGroup::Io,
+            rctl::Resource::ReadIops        => ResourceGroup::Io,
+            rctl::Resource::WriteBps        => ResourceGroup::Io,
+            rctl::Resource::WriteIops       => ResourceGroup::Io,
+            rctl::Resource::MsgqQueued      => ResourceGroup::Ipc,
+            rctl::Resource::MsgqSize        => ResourceGroup::Ipc,
+            rctl::Resource::NMsgq           => ResourceGroup::Ipc,
+            rctl::Resource::Nsem            => ResourceGroup::Ipc,
+            rctl::Resource::NSemop          => ResourceGroup::Ipc,
+            rctl::Resource::NShm            => ResourceGroup::Ipc,
+            rctl::Resource::ShmSize         => ResourceGroup::Ipc,
+            rctl::Resource::DataSize        => ResourceGroup::Memory,
+            rctl::Resource::MemoryLocked    => ResourceGroup::Memory,
+            rctl::Resource::MemoryUse       => ResourceGroup::Memory,
+            rctl::Resource::StackSize       => ResourceGroup::Memory,
+            rctl::Resource::SwapUse         => ResourceGroup::Memory,
+            rctl::Resource::VMemoryUse      => ResourceGroup::Memory,
+            rctl::Resource::MaxProcesses    => ResourceGroup::Processes,
+            rctl::Resource::NThreads        => ResourceGroup::Processes,
+        }
+    }
+}
+
+impl ResourceGroup {
+    /// Return the name of the group.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ResourceGroup::Cpu       => "cpu",
+            ResourceGroup::Files     => "files",
+            ResourceGroup::Io        => "io",
+            ResourceGroup::Ipc       => "ipc",
+            ResourceGroup::Memory    => "memory",
+            ResourceGroup::Processes => "processes",
+        }
+    }
+}
+
+impl fmt::Display for ResourceGroup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ResourceGroup {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cpu"       => Ok(ResourceGroup::Cpu),
+            "files"     => Ok(ResourceGroup::Files),
+            "io"        => Ok(ResourceGroup::Io),
+            "ipc"       => Ok(ResourceGroup::Ipc),
+            "memory"    => Ok(ResourceGroup::Memory),
+            "processes" => Ok(ResourceGroup::Processes),
+            _           => Err(format!("unknown resource group: {}", s)),
+        }
+    }
+}
+
+/// The kinds of change in the running jails seen between two collections.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JailEventKind {
+    /// A jail appeared with a name and jid we hadn't seen.
+    Started,
+    /// A jail disappeared.
+    Stopped,
+    /// A jail kept its name but has a new jid, so it was stopped and started
+    /// again between collections.
+    Restarted,
+    /// A jail kept its jid but has a new name.
+    Renamed,
+}
+
+impl JailEventKind {
+    /// Every kind of jail event.
+    const ALL: [Self; 4] = [
+        Self::Started,
+        Self::Stopped,
+        Self::Restarted,
+        Self::Renamed,
+    ];
+
+    /// Returns the name of the event kind, as used in the `type` label of
+    /// `jail_events_total`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use jail_exporter::JailEventKind;
+    ///
+    /// assert_eq!(JailEventKind::Restarted.as_str(), "restarted");
+    /// ```
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Started   => "started",
+            Self::Stopped   => "stopped",
+            Self::Restarted => "restarted",
+            Self::Renamed   => "renamed",
+        }
+    }
+}
+
+impl fmt::Display for JailEventKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// A change in the running jails, found by comparing consecutive
+/// collections.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct JailEvent {
+    /// Seconds since the Unix epoch of the collection that saw the change.
+    pub timestamp: u64,
+
+    /// The kind of change.
+    #[serde(rename = "type")]
+    pub kind: JailEventKind,
+
+    /// The name of the jail. For renames, this is the new name.
+    pub name: String,
+
+    /// The jid of the jail. For stops, this is the jid it last had.
+    pub jid: i64,
+
+    /// The name the jail had before it was renamed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_name: Option<String>,
+
+    /// The jid the jail had before it was restarted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_jid: Option<i64>,
+}
+
+/// Returns the events explaining the differences between the jails seen by
+/// two collections, ordered by jail name.
+fn jail_events(old: &JailIds, new: &JailIds, timestamp: u64)
+-> Vec<JailEvent> {
+    let event = |kind, name: &str, jid: i64| {
+        JailEvent {
+            timestamp,
+            kind,
+            name: name.to_owned(),
+            jid,
+            previous_name: None,
+            previous_jid: None,
+        }
+    };
+
+    let mut events = Vec::new();
+
+    for (name, &jid) in new {
+        match old.get(name) {
+            Some(&old_jid) if old_jid == jid => {},
+            Some(&old_jid) => {
+                events.push(JailEvent {
+                    previous_jid: Some(old_jid),
+                    ..event(JailEventKind::Restarted, name, jid)
+                });
+            },
+            None => {
+                // A jid that moved from a jail which has gone to one we
+                // hadn't seen is a rename.
+                let renamed = old
+                    .iter()
+                    .find(|(old_name, &old_jid)| {
+                        old_jid == jid && !new.contains_key(*old_name)
+                    });
+
+                let new_event = match renamed {
+                    Some((old_name, _)) => {
+                        JailEvent {
+                            previous_name: Some(old_name.to_owned()),
+                            ..event(JailEventKind::Renamed, name, jid)
+                        }
+                    },
+                    None => event(JailEventKind::Started, name, jid),
+                };
+
+                events.push(new_event);
+            },
+        }
+    }
+
+    for (name, &jid) in old {
+        if new.contains_key(name) {
+            continue;
+        }
+
+        // Renamed jails were already dealt with above.
+        let renamed = new
+            .iter()
+            .any(|(new_name, &new_jid)| {
+                new_jid == jid && !old.contains_key(new_name)
+            });
+
+        if !renamed {
+            events.push(event(JailEventKind::Stopped, name, jid));
+        }
+    }
+
+    events.sort_by(|a, b| a.name.cmp(&b.name));
+
+    events
+}
+
+/// Returns the jails of the old collection that the new one didn't see.
+fn dead_jails(old: &JailIds, new: &JailIds) -> DeadJails {
+    old
+        .keys()
+        .filter(|name| !new.contains_key(*name))
+        .map(|name| name.to_owned())
+        .collect()
+}
+
 /// Exporter structure containing the time series that are being tracked.
 #[derive(Clone)]
 pub struct Exporter {
@@ -80,12 +448,41 @@ pub struct Exporter {
 
     // Metrics this library generates
     build_info: IntGaugeVec,
+    events_total: IntCounterVec,
     jail_id: IntGaugeVec,
     jail_total: IntGauge,
 
     // Counter bookkeeping
     cputime_seconds_total_old: Arc<Mutex<CounterBookKeeper>>,
     wallclock_seconds_total_old: Arc<Mutex<CounterBookKeeper>>,
+
+    // The jails seen by previous collections.
+    jails_old: Arc<Mutex<KnownJails>>,
+
+    // Jail events seen by recent collections, oldest first.
+    events: Arc<Mutex<VecDeque<JailEvent>>>,
+
+    // Resource usage of each jail over recent collections.
+    history: Arc<Mutex<History>>,
+
+    // Counter increases computed during the current collection
+    counter_increases: Arc<Mutex<CounterIncreases>>,
+
+    // Serialises collections, so that clones of the exporter sharing the
+    // registry never observe each others partially collected metrics.
+    collection_lock: Arc<Mutex<()>>,
+
+    // The duration of the most recent collection.
+    last_collection_duration: Arc<Mutex<Option<Duration>>>,
+
+    // When the most recent collection started.
+    last_collection_started: Arc<Mutex<Option<Instant>>>,
+
+    // The error from the most recent collection, if it failed.
+    last_collection_error: Arc<Mutex<Option<String>>>,
+
+    // The resource group of each time series that comes from rctl.
+    resource_groups: Arc<ResourceGroups>,
 }
 
 impl Default for Exporter {
@@ -98,179 +495,232 @@ impl Default for Exporter {
         // Convenience variable
         let labels: &[&str] = &["name"];
 
+        // Filled in as each rctl time series is registered.
+        let mut resource_groups = ResourceGroups::new();
+
         let metrics = Self {
             registry: registry.clone(),
 
-            coredumpsize_bytes: register_int_gauge_vec!(
+            coredumpsize_bytes: register_resource_gauge_vec!(
                 registry,
+                resource_groups,
+                rctl::Resource::CoreDumpSize,
                 "jail_coredumpsize_bytes",
                 "core dump size, in bytes",
                 labels
             ).unwrap(),
 
-            cputime_seconds_total: register_int_counter_vec!(
+            cputime_seconds_total: register_resource_counter_vec!(
                 registry,
+                resource_groups,
+                rctl::Resource::CpuTime,
                 "jail_cputime_seconds_total",
                 "CPU time, in seconds",
                 labels
             ).unwrap(),
 
-            datasize_bytes: register_int_gauge_vec!(
+            datasize_bytes: register_resource_gauge_vec!(
                 registry,
+                resource_groups,
+                rctl::Resource::DataSize,
                 "jail_datasize_bytes",
                 "data size, in bytes",
                 labels
             ).unwrap(),
 
-            maxproc: register_int_gauge_vec!(
+            maxproc: register_resource_gauge_vec!(
                 registry,
+                resource_groups,
+                rctl::Resource::MaxProcesses,
                 "jail_maxproc",
                 "number of processes",
                 labels
             ).unwrap(),
 
-            memorylocked_bytes: register_int_gauge_vec!(
+            memorylocked_bytes: register_resource_gauge_vec!(
                 registry,
+                resource_groups,
+                rctl::Resource::MemoryLocked,
                 "jail_memorylocked_bytes",
                 "locked memory, in bytes",
                 labels
             ).unwrap(),
 
-            memoryuse_bytes: register_int_gauge_vec!(
+            memoryuse_bytes: register_resource_gauge_vec!(
                 registry,
+                resource_groups,
+                rctl::Resource::MemoryUse,
                 "jail_memoryuse_bytes",
                 "resident set size, in bytes",
                 labels
             ).unwrap(),
 
-            msgqqueued: register_int_gauge_vec!(
+            msgqqueued: register_resource_gauge_vec!(
                 registry,
+                resource_groups,
+                rctl::Resource::MsgqQueued,
                 "jail_msgqqueued",
                 "number of queued SysV messages",
                 labels
             ).unwrap(),
 
-            msgqsize_bytes: register_int_gauge_vec!(
+            msgqsize_bytes: register_resource_gauge_vec!(
                 registry,
+                resource_groups,
+                rctl::Resource::MsgqSize,
                 "jail_msgqsize_bytes",
                 "SysV message queue size, in bytes",
                 labels
             ).unwrap(),
 
-            nmsgq: register_int_gauge_vec!(
+            nmsgq: register_resource_gauge_vec!(
                 registry,
+                resource_groups,
+                rctl::Resource::NMsgq,
                 "jail_nmsgq",
                 "number of SysV message queues",
                 labels
             ).unwrap(),
 
-            nsem: register_int_gauge_vec!(
+            nsem: register_resource_gauge_vec!(
                 registry,
+                resource_groups,
+                rctl::Resource::Nsem,
                 "jail_nsem",
                 "number of SysV semaphores",
                 labels
             ).unwrap(),
 
-            nsemop: register_int_gauge_vec!(
+            nsemop: register_resource_gauge_vec!(
                 registry,
+                resource_groups,
+                rctl::Resource::NSemop,
                 "jail_nsemop",
                 "number of SysV semaphores modified in a single semop(2) call",
                 labels
             ).unwrap(),
 
-            nshm: register_int_gauge_vec!(
+            nshm: register_resource_gauge_vec!(
                 registry,
+                resource_groups,
+                rctl::Resource::NShm,
                 "jail_nshm",
                 "number of SysV shared memory segments",
                 labels
             ).unwrap(),
 
-            nthr: register_int_gauge_vec!(
+            nthr: register_resource_gauge_vec!(
                 registry,
+                resource_groups,
+                rctl::Resource::NThreads,
                 "jail_nthr",
                 "number of threads",
                 labels
             ).unwrap(),
 
-            openfiles: register_int_gauge_vec!(
+            openfiles: register_resource_gauge_vec!(
                 registry,
+                resource_groups,
+                rctl::Resource::OpenFiles,
                 "jail_openfiles",
                 "file descriptor table size",
                 labels
             ).unwrap(),
 
-            pcpu_used: register_int_gauge_vec!(
+            pcpu_used: register_resource_gauge_vec!(
                 registry,
+                resource_groups,
+                rctl::Resource::PercentCpu,
                 "jail_pcpu_used",
                 "%CPU, in percents of a single CPU core",
                 labels
             ).unwrap(),
 
-            pseudoterminals: register_int_gauge_vec!(
+            pseudoterminals: register_resource_gauge_vec!(
                 registry,
+                resource_groups,
+                rctl::Resource::PseudoTerminals,
                 "jail_pseudoterminals",
                 "number of PTYs",
                 labels
             ).unwrap(),
 
-            readbps: register_int_gauge_vec!(
+            readbps: register_resource_gauge_vec!(
                 registry,
+                resource_groups,
+                rctl::Resource::ReadBps,
                 "jail_readbps",
                 "filesystem reads, in bytes per second",
                 labels
             ).unwrap(),
 
-            readiops: register_int_gauge_vec!(
+            readiops: register_resource_gauge_vec!(
                 registry,
+                resource_groups,
+                rctl::Resource::ReadIops,
                 "jail_readiops",
                 "filesystem reads, in operations per second",
                 labels
             ).unwrap(),
 
-            shmsize_bytes: register_int_gauge_vec!(
+            shmsize_bytes: register_resource_gauge_vec!(
                 registry,
+                resource_groups,
+                rctl::Resource::ShmSize,
                 "jail_shmsize_bytes",
                 "SysV shared memory size, in bytes",
                 labels
             ).unwrap(),
 
-            stacksize_bytes: register_int_gauge_vec!(
+            stacksize_bytes: register_resource_gauge_vec!(
                 registry,
+                resource_groups,
+                rctl::Resource::StackSize,
                 "jail_stacksize_bytes",
                 "stack size, in bytes",
                 labels
             ).unwrap(),
 
-            swapuse_bytes: register_int_gauge_vec!(
+            swapuse_bytes: register_resource_gauge_vec!(
                 registry,
+                resource_groups,
+                rctl::Resource::SwapUse,
                 "jail_swapuse_bytes",
                 "swap space that may be reserved or used, in bytes",
                 labels
             ).unwrap(),
 
-            vmemoryuse_bytes: register_int_gauge_vec!(
+            vmemoryuse_bytes: register_resource_gauge_vec!(
                 registry,
+                resource_groups,
+                rctl::Resource::VMemoryUse,
                 "jail_vmemoryuse_bytes",
                 "address space limit, in bytes",
                 labels
             ).unwrap(),
 
-            wallclock_seconds_total: register_int_counter_vec!(
+            wallclock_seconds_total: register_resource_counter_vec!(
                 registry,
+                resource_groups,
+                rctl::Resource::Wallclock,
                 "jail_wallclock_seconds_total",
                 "wallclock time, in seconds",
                 labels
             ).unwrap(),
 
-            writebps: register_int_gauge_vec!(
+            writebps: register_resource_gauge_vec!(
                 registry,
+                resource_groups,
+                rctl::Resource::WriteBps,
                 "jail_writebps",
                 "filesystem writes, in bytes per second",
                 labels
             ).unwrap(),
 
-            writeiops: register_int_gauge_vec!(
+            writeiops: register_resource_gauge_vec!(
                 registry,
+                resource_groups,
+                rctl::Resource::WriteIops,
                 "jail_writeiops",
                 "filesystem writes, in operations per second",
                 labels
@@ -285,6 +735,13 @@ impl Default for Exporter {
                 &["version"]
             ).unwrap(),
 
+            events_total: register_int_counter_vec!(
+                registry,
+                "jail_events_total",
+                "Number of jail events seen between collections, by type.",
+                &["type"]
+            ).unwrap(),
+
             jail_id: register_int_gauge_vec!(
                 registry,
                 "jail_id",
@@ -305,20 +762,62 @@ impl Default for Exporter {
             wallclock_seconds_total_old: Arc::new(Mutex::new(
                     CounterBookKeeper::new()
                     )),
+            counter_increases: Arc::new(Mutex::new(
+                    CounterIncreases::new()
+                    )),
+
+            jails_old: Arc::new(Mutex::new(KnownJails::default())),
+            events: Arc::new(Mutex::new(VecDeque::new())),
+            history: Arc::new(Mutex::new(History::default())),
+
+            collection_lock: Arc::new(Mutex::new(())),
+
+            last_collection_duration: Arc::new(Mutex::new(None)),
+            last_collection_started: Arc::new(Mutex::new(None)),
+            last_collection_error: Arc::new(Mutex::new(None)),
+
+            resource_groups: Arc::new(resource_groups),
         };
 
         let build_info_labels = [env!("CARGO_PKG_VERSION")];
         metrics.build_info.with_label_values(&build_info_labels).set(1);
 
+        // Export every event type from the start, so rates work for the
+        // first event of each type.
+        for kind in JailEventKind::ALL.iter() {
+            metrics.events_total.with_label_values(&[kind.as_str()]);
+        }
+
         metrics
     }
 }
 
+/// Encode gathered metrics in the Prometheus text format.
+///
+/// # Example
+///
+/// ```
+/// # let exporter = jail_exporter::Exporter::new();
+/// let metric_families = exporter.gather().unwrap();
+/// let output = jail_exporter::encode(&metric_families);
+/// ```
+pub fn encode(metric_families: &[MetricFamily])
+-> Result<ExportedMetrics, ExporterError> {
+    // Collect them in a buffer
+    let mut buffer = vec![];
+    let encoder = TextEncoder::new();
+    encoder.encode(metric_families, &mut buffer)?;
+
+    Ok(buffer)
+}
+
 /// Exporter implementation
 impl Exporter {
     /// Return a new Exporter instance.
     ///
     /// This will create the initial time series and return a metrics struct.
+    /// Clones of the returned exporter share its time series and counter
+    /// bookkeeping, so they may be handed to several outputs.
     ///
     /// # Example
     ///
@@ -341,33 +840,409 @@ impl Exporter {
     /// let output = exporter.export();
     /// ```
     pub fn export(&self) -> Result<ExportedMetrics, ExporterError> {
-        // Collect metrics
-        self.get_jail_metrics()?;
+        // Collect and gather metrics
+        let metric_families = self.gather()?;
+
+        // Return the exported metrics
+        encode(&metric_families)
+    }
+
+    /// Return the registry holding the exporter's time series.
+    ///
+    /// Outputs may register metrics about themselves here, so that they are
+    /// gathered along with the jail metrics.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # let exporter = jail_exporter::Exporter::new();
+    /// let counter = prometheus::IntCounter::new("example", "help").unwrap();
+    /// exporter.registry().register(Box::new(counter)).unwrap();
+    /// ```
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// Return the resource group of the named time series.
+    ///
+    /// Time series that don't come from rctl, such as `jail_num`, don't
+    /// belong to any group.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use jail_exporter::ResourceGroup;
+    ///
+    /// # let exporter = jail_exporter::Exporter::new();
+    /// let group = exporter.resource_group("jail_memoryuse_bytes");
+    /// assert_eq!(group, Some(ResourceGroup::Memory));
+    /// assert_eq!(exporter.resource_group("jail_num"), None);
+    /// ```
+    pub fn resource_group(&self, metric: &str) -> Option<ResourceGroup> {
+        self.resource_groups.get(metric).copied()
+    }
+
+    /// Return the duration of the most recent collection.
+    ///
+    /// This is `None` if no collection has happened yet.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # let exporter = jail_exporter::Exporter::new();
+    /// exporter.gather().unwrap();
+    /// assert!(exporter.last_collection_duration().is_some());
+    /// ```
+    pub fn last_collection_duration(&self) -> Option<Duration> {
+        *self.last_collection_duration.lock().unwrap()
+    }
+
+    /// Return when the most recent collection started.
+    ///
+    /// This is `None` if no collection has happened yet. It is shared by
+    /// clones of the exporter, so it reflects collections made by any output.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # let exporter = jail_exporter::Exporter::new();
+    /// exporter.gather().unwrap();
+    /// let started = exporter.last_collection_started().unwrap();
+    /// println!("collected {:?} ago", started.elapsed());
+    /// ```
+    pub fn last_collection_started(&self) -> Option<Instant> {
+        *self.last_collection_started.lock().unwrap()
+    }
+
+    /// Return the error from the most recent collection, if it failed.
+    ///
+    /// This is `None` if the most recent collection succeeded, or if no
+    /// collection has happened yet. It is shared by clones of the exporter,
+    /// so it reflects collections made by any output.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # let exporter = jail_exporter::Exporter::new();
+    /// if let Some(e) = exporter.last_collection_error() {
+    ///     eprintln!("last collection failed: {}", e);
+    /// }
+    /// ```
+    pub fn last_collection_error(&self) -> Option<String> {
+        self.last_collection_error.lock().unwrap().clone()
+    }
+
+    /// Return the jail events seen by recent collections, oldest first.
+    ///
+    /// Events are found by comparing consecutive collections, so jails that
+    /// start and stop between collections go unnoticed, and nothing is seen
+    /// unless something is collecting. Only the most recent 1000 events are
+    /// kept.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # let exporter = jail_exporter::Exporter::new();
+    /// for event in exporter.events() {
+    ///     println!("{} {} {}", event.timestamp, event.kind, event.name);
+    /// }
+    /// ```
+    pub fn events(&self) -> Vec<JailEvent> {
+        self.events.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Return the maximum number of samples of jail resource usage kept in
+    /// the history.
+    ///
+    /// History is disabled when this is 0, which is the default.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # let exporter = jail_exporter::Exporter::new();
+    /// assert_eq!(exporter.history_size(), 0);
+    /// ```
+    pub fn history_size(&self) -> usize {
+        self.history.lock().unwrap().max_samples()
+    }
+
+    /// Set the maximum number of samples of jail resource usage kept in the
+    /// history.
+    ///
+    /// Each collection records one sample per resource of each running jail.
+    /// Once the limit is reached, the oldest collections are dropped. Setting
+    /// this to 0 disables the history.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # let exporter = jail_exporter::Exporter::new();
+    /// exporter.set_history_size(100_000);
+    /// assert_eq!(exporter.history_size(), 100_000);
+    /// ```
+    pub fn set_history_size(&self, max_samples: usize) {
+        self.history.lock().unwrap().set_max_samples(max_samples);
+    }
+
+    /// Return how long collections are kept in the history.
+    ///
+    /// This is `None` by default, in which case the history is only bounded
+    /// by its size.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # let exporter = jail_exporter::Exporter::new();
+    /// assert_eq!(exporter.history_retention(), None);
+    /// ```
+    pub fn history_retention(&self) -> Option<Duration> {
+        self.history
+            .lock()
+            .unwrap()
+            .retention()
+            .map(Duration::from_secs)
+    }
+
+    /// Set how long collections are kept in the history.
+    ///
+    /// Collections made more than `retention` before the most recent one
+    /// are dropped, however often collections are made. The history size
+    /// still bounds the memory used.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use std::time::Duration;
+    /// # let exporter = jail_exporter::Exporter::new();
+    /// exporter.set_history_retention(Duration::from_secs(3600));
+    /// assert_eq!(
+    ///     exporter.history_retention(),
+    ///     Some(Duration::from_secs(3600)),
+    /// );
+    /// ```
+    pub fn set_history_retention(&self, retention: Duration) {
+        self.history
+            .lock()
+            .unwrap()
+            .set_retention(Some(retention.as_secs()));
+    }
+
+    /// Return the number of collections held in the history.
+    ///
+    /// Every collection made while the history is enabled is recorded,
+    /// whether it was made for a scrape, an output or the HTTPd's history
+    /// sampler.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # let exporter = jail_exporter::Exporter::new();
+    /// assert_eq!(exporter.history_len(), 0);
+    /// ```
+    pub fn history_len(&self) -> usize {
+        self.history.lock().unwrap().len()
+    }
+
+    /// Return the value of the resource for the named jail at each
+    /// collection in the history made at or after `since`, in seconds since
+    /// the Unix epoch.
+    ///
+    /// The values are returned as (timestamp, value) pairs, oldest first.
+    /// This is `None` if the jail wasn't seen by any collection in the
+    /// history.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # let exporter = jail_exporter::Exporter::new();
+    /// let resource = rctl::Resource::MemoryUse;
+    /// if let Some(values) = exporter.history("www", resource, 0) {
+    ///     for (timestamp, value) in values {
+    ///         println!("{} {}", timestamp, value);
+    ///     }
+    /// }
+    /// ```
+    pub fn history(&self, name: &str, resource: rctl::Resource, since: u64)
+    -> Option<Vec<(u64, usize)>> {
+        self.history.lock().unwrap().values(name, resource, since)
+    }
+
+    /// Gather the metrics as of the most recent collection, without
+    /// collecting.
+    ///
+    /// This is useful for displaying the state of the jails without the cost
+    /// of a collection, and without disturbing the counter increases seen by
+    /// outputs that send deltas.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # let exporter = jail_exporter::Exporter::new();
+    /// let metric_families = exporter.snapshot();
+    /// ```
+    pub fn snapshot(&self) -> Vec<MetricFamily> {
+        // Wait for any collection in progress, so we don't see it half done.
+        let _lock = self.collection_lock.lock().unwrap();
+
+        self.registry.gather()
+    }
+
+    /// Collect the rctl metrics and gather them.
+    ///
+    /// This will return the `MetricFamily` structures held by the registry,
+    /// for callers that need to process the metrics before encoding them.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # let exporter = jail_exporter::Exporter::new();
+    /// let metric_families = exporter.gather();
+    /// ```
+    pub fn gather(&self) -> Result<Vec<MetricFamily>, ExporterError> {
+        let (metric_families, _) = self.gather_with_increases()?;
+
+        Ok(metric_families)
+    }
+
+    /// Collect the rctl metrics and gather them, along with the amount each
+    /// jail counter increased by during this collection.
+    ///
+    /// This is useful for outputs that send counter deltas rather than
+    /// totals. The increases are those computed by the exporter's counter
+    /// bookkeeping, so counter resets within a jail are handled in the same
+    /// way as for the Prometheus counters.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # let exporter = jail_exporter::Exporter::new();
+    /// let (metric_families, increases) = exporter
+    ///     .gather_with_increases()
+    ///     .unwrap();
+    /// ```
+    pub fn gather_with_increases(&self)
+    -> Result<(Vec<MetricFamily>, CounterIncreases), ExporterError> {
+        // Held until the metrics have been gathered.
+        let _lock = self.collection_lock.lock().unwrap();
+
+        self.collect(None)?;
+
+        // Take the increases recorded during this collection.
+        let increases = self.counter_increases
+            .lock()
+            .unwrap()
+            .drain()
+            .collect();
 
         // Gather them
-        let metric_families = self.registry.gather();
+        Ok((self.registry.gather(), increases))
+    }
+
+    /// Collect the rctl metrics until the deadline and gather them.
+    ///
+    /// This is useful when the metrics are only wanted for a limited time,
+    /// such as when answering a scrape that will time out. Jails are
+    /// collected one at a time, stopping once the deadline has passed. The
+    /// returned `bool` is `true` if it did, in which case only the series of
+    /// the jails collected in time are returned, along with those that don't
+    /// belong to any jail. The jails that weren't reached may still be
+    /// running, so they aren't forgotten.
+    ///
+    /// Waiting for another collection to finish counts against the deadline.
+    /// If it passes before we can start, including when it has already
+    /// passed, no jails are collected.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use std::time::{Duration, Instant};
+    /// # let exporter = jail_exporter::Exporter::new();
+    /// let deadline = Instant::now() + Duration::from_secs(10);
+    /// let (metric_families, timed_out) = exporter
+    ///     .gather_until(deadline)
+    ///     .unwrap();
+    /// ```
+    pub fn gather_until(&self, deadline: Instant)
+    -> Result<(Vec<MetricFamily>, bool), ExporterError> {
+        // Held until the metrics have been gathered.
+        let lock = self.lock_collection_until(deadline);
 
-        // Collect them in a buffer
-        let mut buffer = vec![];
-        let encoder = TextEncoder::new();
-        encoder.encode(&metric_families, &mut buffer)?;
+        let timed_out = if lock.is_some() {
+            self.collect(Some(deadline))?
+        }
+        else {
+            debug!("Deadline passed waiting for collection");
+            Some(JailIds::new())
+        };
 
-        // Return the exported metrics
-        Ok(buffer)
+        let mut metric_families = self.registry.gather();
+
+        // Only the series of the jails we reached are up to date.
+        if let Some(seen) = &timed_out {
+            retain_series(&mut metric_families, |jail| {
+                match jail {
+                    Some(jail) => seen.contains_key(jail),
+                    None       => true,
+                }
+            });
+        }
+
+        Ok((metric_families, timed_out.is_some()))
+    }
+
+    /// Acquires the collection lock, waiting for the collection in progress
+    /// to finish no later than the deadline. Returns `None` if it didn't.
+    fn lock_collection_until(&self, deadline: Instant)
+    -> Option<MutexGuard<'_, ()>> {
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+
+            match self.collection_lock.try_lock() {
+                Ok(lock)                       => return Some(lock),
+                Err(TryLockError::WouldBlock)  => {},
+                Err(TryLockError::Poisoned(e)) => panic!("{}", e),
+            }
+
+            thread::sleep(cmp::min(COLLECTION_LOCK_POLL, deadline - now));
+        }
+    }
+
+    /// Collects metrics, remembering how long it took and whether we
+    /// succeeded. Returns the jails collected if the deadline passed first.
+    /// The collection lock must be held by the caller.
+    fn collect(&self, deadline: Option<Instant>)
+    -> Result<Option<JailIds>, ExporterError> {
+        let started = Instant::now();
+        *self.last_collection_started.lock().unwrap() = Some(started);
+
+        let collected = self.get_jail_metrics(deadline);
+        let duration = started.elapsed();
+
+        *self.last_collection_duration.lock().unwrap() = Some(duration);
+        *self.last_collection_error.lock().unwrap() = collected
+            .as_ref()
+            .err()
+            .map(|e| e.to_string());
+
+        collected
     }
 
     /// Updates the book for the given metric and returns the amount the value
     /// has increased by.
     fn update_metric_book(&self, name: &str, resource: &BookKept) -> i64 {
-        // Get the Book of Old Values and the current value.
-        let (mut book, value) = match *resource {
+        // Get the Book of Old Values, the current value and the name of the
+        // metric being kept.
+        let (mut book, value, metric) = match *resource {
             BookKept::CpuTime(v) => {
                 let book = self.cputime_seconds_total_old.lock().unwrap();
-                (book, v)
+                (book, v, "jail_cputime_seconds_total")
             },
             BookKept::Wallclock(v) => {
                 let book = self.wallclock_seconds_total_old.lock().unwrap();
-                (book, v)
+                (book, v, "jail_wallclock_seconds_total")
             },
         };
 
@@ -390,6 +1265,12 @@ impl Exporter {
         // Update book keeping.
         book.insert(name.to_owned(), value);
 
+        // Record the increase for outputs sending deltas.
+        self.counter_increases
+            .lock()
+            .unwrap()
+            .insert((metric.to_owned(), name.to_owned()), inc);
+
         // Return computed increase
         inc
     }
@@ -503,50 +1384,113 @@ impl Exporter {
         }
     }
 
-    fn get_jail_metrics(&self) -> Result<(), ExporterError> {
+    // Collects the metrics of each jail. If the deadline passes, we stop and
+    // return the jails collected so far, only reaping those we can tell are
+    // gone, as the rest may still be running.
+    fn get_jail_metrics(&self, deadline: Option<Instant>)
+    -> Result<Option<JailIds>, ExporterError> {
         debug!("get_jail_metrics");
 
-        // Set jail_total to zero before gathering.
-        self.jail_total.set(0);
+        // Count every running jail up front, so jail_total is right even if
+        // the deadline stops us before we reach them all.
+        let jails: Vec<RunningJail> = RunningJail::all().collect();
+        self.jail_total.set(jails.len() as i64);
+
+        // Forget increases from the previous collection.
+        self.counter_increases.lock().unwrap().clear();
+
+        // Get a new map of seen jails, and their jids.
+        let mut jids = JailIds::new();
 
-        // Get a new vec of seen jails.
-        let mut seen = SeenJails::new();
+        // Record the resource usage of each jail for the history.
+        let mut entry = HistoryEntry::new(unix_time());
 
         // Loop over jails.
-        for jail in RunningJail::all() {
+        for jail in jails {
+            if let Some(deadline) = deadline {
+                if Instant::now() >= deadline {
+                    debug!("Deadline passed after {} jails", jids.len());
+                    self.update_jails(&jids, false);
+                    return Ok(Some(jids));
+                }
+            }
+
             let name = jail.name()?;
             let rusage = jail.racct_statistics()?;
 
             debug!("JID: {}, Name: {:?}", jail.jid, name);
 
-            // Add to our vec of seen jails.
-            seen.push(name.to_owned());
+            // Add to our map of seen jails.
+            jids.insert(name.to_owned(), i64::from(jail.jid));
 
             // Process rusage for the named jail, setting time series.
             self.process_rusage(&name, &rusage);
+            entry.insert(&name, &rusage);
 
             self.jail_id.with_label_values(&[&name]).set(i64::from(jail.jid));
-            self.jail_total.set(self.jail_total.get() + 1);
         }
 
-        // Get a list of dead jails based on what we've seen, and reap them.
-        // Performed in two steps due to Mutex locking issues.
-        let dead = self.dead_jails(seen);
-        self.reap(dead);
+        self.update_jails(&jids, true);
+        self.history.lock().unwrap().push(entry);
 
-        Ok(())
+        Ok(None)
     }
 
-    // Loop over jail names from the previous run, as determined by book
-    // keeping, and create a vector of jail names that no longer exist.
-    fn dead_jails(&self, seen: SeenJails) -> DeadJails {
-        let book = self.cputime_seconds_total_old.lock().unwrap();
+    // Compares the jails seen by this collection with those seen before,
+    // recording the differences as events and reaping the jails that have
+    // gone. A collection that didn't finish is only compared with the known
+    // jails sharing a name or jid with those it saw, as the rest may still be
+    // running. No events are recorded until a collection has completed, as
+    // until then we can't tell which jails just started.
+    fn update_jails(&self, jids: &JailIds, complete: bool) {
+        let mut known = self.jails_old.lock().unwrap();
+
+        let old = if complete {
+            known.jids.drain().collect()
+        }
+        else {
+            let related: Vec<String> = known.jids
+                .iter()
+                .filter(|(name, jid)| {
+                    jids.contains_key(*name) || jids.values().any(|j| j == *jid)
+                })
+                .map(|(name, _)| name.to_owned())
+                .collect();
+
+            related
+                .iter()
+                .filter_map(|name| known.jids.remove_entry(name))
+                .collect()
+        };
 
-        book
-            .keys()
-            .filter(|n| !seen.contains(&n))
-            .map(|n| n.to_owned())
-            .collect()
+        if known.complete {
+            self.record_events(jail_events(&old, jids, unix_time()));
+        }
+
+        self.reap(dead_jails(&old, jids));
+
+        known.jids.extend(jids.iter().map(|(name, jid)| (name.clone(), *jid)));
+        known.complete |= complete;
+    }
+
+    // Records the given jail events, dropping the oldest once we hold
+    // EVENTS_CAPACITY of them.
+    fn record_events(&self, new_events: Vec<JailEvent>) {
+        let mut events = self.events.lock().unwrap();
+
+        for event in new_events {
+            debug!("Jail {} {}", event.name, event.kind);
+
+            self.events_total
+                .with_label_values(&[event.kind.as_str()])
+                .inc();
+
+            if events.len() == EVENTS_CAPACITY {
+                events.pop_front();
+            }
+
+            events.push_back(event);
+        }
     }
 
     // Loop over DeadJails removing old labels and killing old book keeping.
@@ -652,24 +1596,179 @@ mod tests {
     }
 
     #[test]
-    fn dead_jails_ok() {
-        let names = ["test_a", "test_b", "test_c"];
+    fn counter_increases_recorded() {
         let mut hash = Rusage::new();
         let exporter = Exporter::new();
+        let key = ("jail_cputime_seconds_total".to_owned(), "test".to_owned());
 
-        // Create some metrics for test_{a,b,c}.
-        for name in names.iter() {
+        hash.insert(rctl::Resource::CpuTime, 1000);
+        exporter.process_rusage("test", &hash);
+        hash.insert(rctl::Resource::CpuTime, 1020);
+        exporter.process_rusage("test", &hash);
+
+        let increases = exporter.counter_increases.lock().unwrap();
+        assert_eq!(increases.get(&key), Some(&20));
+    }
+
+    #[test]
+    fn gather_until_collection_in_progress() {
+        let exporter = Exporter::new();
+
+        // Another collection holds the lock past our deadline.
+        let _lock = exporter.collection_lock.lock().unwrap();
+        let deadline = Instant::now() + Duration::from_millis(50);
+
+        let (metric_families, timed_out) = exporter
+            .gather_until(deadline)
+            .unwrap();
+
+        assert!(timed_out);
+        assert!(Instant::now() >= deadline);
+        assert!(exporter.last_collection_duration().is_none());
+
+        // Only the series that don't belong to any jail are served.
+        assert!(metric_families
+            .iter()
+            .any(|family| family.get_name() == "jail_num"));
+        assert!(metric_families
+            .iter()
+            .flat_map(|family| family.get_metric())
+            .all(|metric| jail_name(metric).is_none()));
+    }
+
+    #[test]
+    fn last_collection_error_ok() {
+        let exporter = Exporter::new();
+        assert_eq!(exporter.last_collection_error(), None);
+
+        exporter.gather().unwrap();
+        assert_eq!(exporter.last_collection_error(), None);
+    }
+
+    #[test]
+    fn resource_group_every_resource() {
+        let exporter = Exporter::new();
+
+        // Set every rctl time series for a jail, so they're all gathered.
+        let resources = [
+            "coredumpsize", "cputime", "datasize", "maxproc", "memorylocked",
+            "memoryuse", "msgqqueued", "msgqsize", "nmsgq", "nsem", "nsemop",
+            "nshm", "nthr", "openfiles", "pcpu", "pseudoterminals", "readbps",
+            "readiops", "shmsize", "stacksize", "swapuse", "vmemoryuse",
+            "wallclock", "writebps", "writeiops",
+        ];
+
+        let mut hash = Rusage::new();
+        for resource in resources.iter() {
+            hash.insert(resource.parse().unwrap(), 1);
+        }
+        exporter.process_rusage("test", &hash);
+
+        let names: Vec<String> = exporter
+            .registry()
+            .gather()
+            .iter()
+            .map(|family| family.get_name().to_owned())
+            .filter(|name| {
+                ![
+                    "jail_events_total",
+                    "jail_exporter_build_info",
+                    "jail_id",
+                    "jail_num",
+                ].contains(&name.as_str())
+            })
+            .collect();
+
+        assert_eq!(names.len(), exporter.resource_groups.len());
+
+        for name in names {
+            let group = exporter.resource_group(&name);
+            assert!(group.is_some(), "{} has no resource group", name);
+        }
+    }
+
+    #[test]
+    fn resource_group_from_resource() {
+        let exporter = Exporter::new();
+
+        let group = exporter.resource_group("jail_wallclock_seconds_total");
+        assert_eq!(group, Some(ResourceGroup::Cpu));
+
+        let group = ResourceGroup::from(rctl::Resource::OpenFiles);
+        assert_eq!(group, ResourceGroup::Files);
+    }
+
+    #[test]
+    fn resource_group_from_str() {
+        let exporter = Exporter::new();
+
+        for group in exporter.resource_groups.values() {
+            assert_eq!(group.as_str().parse::<ResourceGroup>(), Ok(*group));
+        }
+
+        assert!("disk".parse::<ResourceGroup>().is_err());
+    }
+
+    #[test]
+    fn gather_until_ok() {
+        let exporter = Exporter::new();
+        let deadline = Instant::now() + Duration::from_secs(60);
+
+        let (_, timed_out) = exporter.gather_until(deadline).unwrap();
+        assert!(!timed_out);
+    }
+
+    #[test]
+    fn retain_series_ok() {
+        let mut hash = Rusage::new();
+        let exporter = Exporter::new();
+
+        for name in ["test_a", "test_b"].iter() {
             hash.insert(rctl::Resource::CpuTime, 1000);
             exporter.process_rusage(&name, &hash);
+            exporter.jail_id.with_label_values(&[name]).set(1);
         }
+        hash.clear();
+        hash.insert(rctl::Resource::MemoryUse, 1024);
+        exporter.process_rusage("test_b", &hash);
+
+        let mut metric_families = exporter.registry().gather();
+        retain_series(&mut metric_families, |jail| jail != Some("test_b"));
+
+        let series: Vec<(&str, Option<&str>)> = metric_families
+            .iter()
+            .flat_map(|family| {
+                family.get_metric().iter().map(move |metric| {
+                    let name = metric
+                        .get_label()
+                        .iter()
+                        .find(|label| label.get_name() == "name")
+                        .map(|label| label.get_value());
+
+                    (family.get_name(), name)
+                })
+            })
+            .filter(|(family, _)| *family != "jail_events_total")
+            .collect();
+
+        // jail_memoryuse_bytes only had a series for test_b.
+        assert_eq!(series, vec![
+            ("jail_cputime_seconds_total", Some("test_a")),
+            ("jail_exporter_build_info", None),
+            ("jail_id", Some("test_a")),
+            ("jail_num", None),
+        ]);
+    }
 
-        // Now, create a seen array containing only a and c.
-        let mut seen = SeenJails::new();
-        seen.push("test_a".into());
-        seen.push("test_c".into());
+    #[test]
+    fn dead_jails_ok() {
+        let old = jail_ids(&[("test_a", 1), ("test_b", 2), ("test_c", 3)]);
+
+        // Now, create a seen map containing only a and c.
+        let seen = jail_ids(&[("test_a", 1), ("test_c", 3)]);
 
         // Workout which jails are dead, it should be b.
-        let dead = exporter.dead_jails(seen);
+        let dead = dead_jails(&old, &seen);
         let ok: DeadJails = vec![
             "test_b".into(),
         ];
@@ -677,6 +1776,155 @@ mod tests {
         assert_eq!(ok, dead);
     }
 
+    // Returns JailIds for the given (name, jid) pairs.
+    fn jail_ids(jails: &[(&str, i64)]) -> JailIds {
+        jails
+            .iter()
+            .map(|(name, jid)| (name.to_string(), *jid))
+            .collect()
+    }
+
+    #[test]
+    fn jail_events_ok() {
+        let old = jail_ids(&[("db", 1), ("mail", 2), ("web", 3), ("www", 4)]);
+        let new = jail_ids(&[("cache", 5), ("db", 1), ("web", 6), ("www2", 4)]);
+
+        let event = |kind, name: &str, jid| {
+            JailEvent {
+                timestamp:     10,
+                kind,
+                name:          name.into(),
+                jid,
+                previous_name: None,
+                previous_jid:  None,
+            }
+        };
+
+        let ok = vec![
+            event(JailEventKind::Started, "cache", 5),
+            event(JailEventKind::Stopped, "mail", 2),
+            JailEvent {
+                previous_jid: Some(3),
+                ..event(JailEventKind::Restarted, "web", 6)
+            },
+            JailEvent {
+                previous_name: Some("www".into()),
+                ..event(JailEventKind::Renamed, "www2", 4)
+            },
+        ];
+
+        assert_eq!(jail_events(&old, &new, 10), ok);
+    }
+
+    // Returns the kind and name of each recorded event.
+    fn event_kinds(exporter: &Exporter) -> Vec<(JailEventKind, String)> {
+        exporter
+            .events()
+            .into_iter()
+            .map(|event| (event.kind, event.name))
+            .collect()
+    }
+
+    #[test]
+    fn update_jails_ok() {
+        let exporter = Exporter::new();
+
+        // The first collection has nothing to compare with.
+        exporter.update_jails(&jail_ids(&[("a", 1)]), true);
+        assert!(exporter.events().is_empty());
+
+        exporter.update_jails(&jail_ids(&[("a", 1), ("b", 2)]), true);
+        exporter.update_jails(&jail_ids(&[("b", 2)]), true);
+
+        assert_eq!(event_kinds(&exporter), vec![
+            (JailEventKind::Started, "b".into()),
+            (JailEventKind::Stopped, "a".into()),
+        ]);
+
+        let started = exporter.events_total.with_label_values(&["started"]);
+        assert_eq!(started.get(), 1);
+    }
+
+    #[test]
+    fn update_jails_incomplete() {
+        let mut hash = Rusage::new();
+        let exporter = Exporter::new();
+
+        hash.insert(rctl::Resource::CpuTime, 1000);
+
+        for name in ["a", "b", "c"].iter() {
+            exporter.process_rusage(&name, &hash);
+        }
+
+        exporter.update_jails(&jail_ids(&[("a", 1), ("b", 2), ("c", 3)]), true);
+
+        // An unfinished collection sees a restarted, b renamed and d started.
+        // It can't tell whether c is still running.
+        exporter.process_rusage("b2", &hash);
+        let seen = jail_ids(&[("a", 4), ("b2", 2), ("d", 5)]);
+        exporter.update_jails(&seen, false);
+
+        assert_eq!(event_kinds(&exporter), vec![
+            (JailEventKind::Restarted, "a".into()),
+            (JailEventKind::Renamed, "b2".into()),
+            (JailEventKind::Started, "d".into()),
+        ]);
+
+        // b is gone, but c is kept.
+        let book = exporter.cputime_seconds_total_old.lock().unwrap();
+        let mut names: Vec<&String> = book.keys().collect();
+        names.sort();
+        assert_eq!(names, vec!["a", "b2", "c"]);
+        drop(book);
+
+        // The next collection finishes, and finds that c has stopped.
+        exporter.update_jails(&seen, true);
+
+        assert_eq!(event_kinds(&exporter).last(), Some(&(
+            JailEventKind::Stopped,
+            "c".into(),
+        )));
+        assert!(!exporter.cputime_seconds_total_old.lock().unwrap()
+            .contains_key("c"));
+    }
+
+    #[test]
+    fn update_jails_incomplete_first() {
+        let mut hash = Rusage::new();
+        let exporter = Exporter::new();
+
+        hash.insert(rctl::Resource::CpuTime, 1000);
+        exporter.process_rusage("a", &hash);
+
+        // Nothing is recorded until a collection completes, but jails seen by
+        // an unfinished one are still reaped once they've gone.
+        exporter.update_jails(&jail_ids(&[("a", 1)]), false);
+        exporter.update_jails(&jail_ids(&[("b", 2)]), true);
+        assert!(exporter.events().is_empty());
+
+        let series = exporter.cputime_seconds_total.with_label_values(&["a"]);
+        assert_eq!(series.get(), 0);
+    }
+
+    #[test]
+    fn record_events_evicts_oldest() {
+        let exporter = Exporter::new();
+        exporter.update_jails(&JailIds::new(), true);
+
+        // Each collection starts jail a with a new jid.
+        for jid in 0..EVENTS_CAPACITY + 10 {
+            exporter.update_jails(&jail_ids(&[("a", jid as i64)]), true);
+        }
+
+        let events = exporter.events();
+        assert_eq!(events.len(), EVENTS_CAPACITY);
+
+        // The start and the first 9 restarts were dropped.
+        assert_eq!(events[0].kind, JailEventKind::Restarted);
+        assert_eq!(events[0].jid, 10);
+        assert_eq!(events[EVENTS_CAPACITY - 1].jid, EVENTS_CAPACITY as i64 + 9);
+    }
+
     #[test]
     fn reap_ok() {
         let names = ["test_a", "test_b", "test_c"];
@@ -689,10 +1937,9 @@ mod tests {
             exporter.process_rusage(&name, &hash);
         }
 
-        // Now, create a seen array containing only a and c.
-        let mut seen = SeenJails::new();
-        seen.push("test_a".into());
-        seen.push("test_c".into());
+        // Now, create a seen map containing only a and c.
+        let old = jail_ids(&[("test_a", 1), ("test_b", 2), ("test_c", 3)]);
+        let seen = jail_ids(&[("test_a", 1), ("test_c", 3)]);
 
         let dead_jail = "test_b";
         let series = exporter
@@ -702,7 +1949,7 @@ mod tests {
         assert_eq!(series.get(), 1000);
 
         // Workout which jails are dead, it should be b.
-        let dead = exporter.dead_jails(seen);
+        let dead = dead_jails(&old, &seen);
         exporter.reap(dead);
 
         // We need a new handle on this. Using the old one will present the old
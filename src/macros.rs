@@ -39,3 +39,39 @@ macro_rules! register_int_gauge_vec {
         __generic_vec!($REGISTRY, IntGaugeVec, opts, $LABEL_NAMES)
     }};
 }
+
+// Register an IntCounterVec of an rctl resource with the given registry,
+// recording the resource group of the time series in the given map.
+#[macro_export]
+#[doc(hidden)]
+macro_rules! register_resource_counter_vec {
+    (
+        $REGISTRY:ident,
+        $GROUPS:ident,
+        $RESOURCE:expr,
+        $NAME:expr,
+        $HELP:expr,
+        $LABEL_NAMES:expr
+    ) => {{
+        $GROUPS.insert($NAME, $crate::ResourceGroup::from($RESOURCE));
+        register_int_counter_vec!($REGISTRY, $NAME, $HELP, $LABEL_NAMES)
+    }};
+}
+
+// Register an IntGaugeVec of an rctl resource with the given registry,
+// recording the resource group of the time series in the given map.
+#[macro_export]
+#[doc(hidden)]
+macro_rules! register_resource_gauge_vec {
+    (
+        $REGISTRY:ident,
+        $GROUPS:ident,
+        $RESOURCE:expr,
+        $NAME:expr,
+        $HELP:expr,
+        $LABEL_NAMES:expr
+    ) => {{
+        $GROUPS.insert($NAME, $crate::ResourceGroup::from($RESOURCE));
+        register_int_gauge_vec!($REGISTRY, $NAME, $HELP, $LABEL_NAMES)
+    }};
+}
@@ -5,18 +5,42 @@
 //!
 #![forbid(unsafe_code)]
 #![deny(missing_docs)]
+use jail_exporter::{
+    Exporter,
+    ExporterError,
+};
 use log::debug;
+use std::path::Path;
+use std::time::Duration;
 use users::{
+    Groups,
     Users,
     UsersCache,
 };
 
 mod cli;
-mod errors;
-use errors::ExporterError;
 mod file;
-use file::FileExporter;
+use file::{
+    FileExporter,
+    Format,
+};
+mod graphite;
+use graphite::Graphite;
 mod httpd;
+mod influx;
+use influx::Influx;
+mod jails;
+mod otlp;
+use otlp::Otlp;
+mod push;
+use push::Pusher;
+mod remote_write;
+use remote_write::RemoteWriter;
+mod signal;
+mod statsd;
+use statsd::Statsd;
+#[cfg(test)]
+mod test_server;
 
 // Checks for the availability of RACCT/RCTL in the kernel.
 fn is_racct_rctl_available() -> Result<(), ExporterError> {
@@ -57,12 +81,45 @@ fn is_running_as_root<U: Users>(users: &mut U) -> Result<(), ExporterError> {
     }
 }
 
+// Resolves a user name or numeric UID to a UID.
+fn get_uid<U: Users>(users: &mut U, owner: &str)
+-> Result<u32, ExporterError> {
+    debug!("Resolving UID for {}", owner);
+
+    if let Ok(uid) = owner.parse::<u32>() {
+        return Ok(uid);
+    }
+
+    match users.get_user_by_name(owner) {
+        Some(user) => Ok(user.uid()),
+        None       => Err(ExporterError::UnknownUser(owner.to_owned())),
+    }
+}
+
+// Resolves a group name or numeric GID to a GID.
+fn get_gid<G: Groups>(groups: &mut G, group: &str)
+-> Result<u32, ExporterError> {
+    debug!("Resolving GID for {}", group);
+
+    if let Ok(gid) = group.parse::<u32>() {
+        return Ok(gid);
+    }
+
+    match groups.get_group_by_name(group) {
+        Some(group) => Ok(group.gid()),
+        None        => Err(ExporterError::UnknownGroup(group.to_owned())),
+    }
+}
+
 #[actix_rt::main]
 async fn main() -> Result<(), ExporterError> {
     env_logger::init();
 
+    // Used for the root check and resolving file ownership.
+    let mut users = UsersCache::new();
+
     // Check that we're running as root.
-    is_running_as_root(&mut UsersCache::new())?;
+    is_running_as_root(&mut users)?;
 
     // Check if RACCT/RCTL is available and if it's not, exit.
     is_racct_rctl_available()?;
@@ -70,23 +127,248 @@ async fn main() -> Result<(), ExporterError> {
     // Parse the commandline arguments.
     let matches = cli::parse_args();
 
-    // If an output file was specified, we do that. We will never launch the
-    // HTTPd when we're passed an OUTPUT_FILE_PATH.
-    if let Some(output_path) = matches.value_of("OUTPUT_FILE_PATH") {
+    // A single exporter is shared by all outputs, so they agree on counter
+    // values.
+    let exporter = Exporter::new();
+
+    // If an output file or directory was specified, we do that. We will never
+    // launch the HTTPd when we're passed an OUTPUT_FILE_PATH or
+    // OUTPUT_DIRECTORY, unless we're also given an OUTPUT_INTERVAL.
+    let output_path = matches.value_of("OUTPUT_FILE_PATH");
+    let output_dir  = matches.value_of("OUTPUT_DIRECTORY");
+
+    let file_exporter = if let Some(output_path) = output_path {
         debug!("output.file-path: {}", output_path);
 
-        let exporter = FileExporter::new(output_path);
+        Some(FileExporter::new(output_path))
+    }
+    else if let Some(output_dir) = output_dir {
+        debug!("output.directory: {}", output_dir);
+
+        Some(FileExporter::new_directory(output_dir))
+    }
+    else {
+        None
+    };
+
+    if let Some(file_exporter) = file_exporter {
+        let fsync = cli::is_enabled(&matches, "OUTPUT_FSYNC");
+        debug!("output.fsync: {}", fsync);
+
+        let mut file_exporter = file_exporter.fsync(fsync);
+
+        // The format was validated by the CLI, so only these values exist.
+        if let Some(format) = matches.value_of("OUTPUT_FORMAT") {
+            debug!("output.format: {}", format);
+
+            let format = match format {
+                "influx" => Format::Influx,
+                _        => Format::Prometheus,
+            };
+
+            file_exporter = file_exporter.format(format);
+        }
+
+        // The mode was validated by the CLI, so this should always parse.
+        if let Some(mode) = matches.value_of("OUTPUT_FILE_MODE") {
+            debug!("output.file-mode: {}", mode);
+
+            file_exporter = file_exporter.mode(u32::from_str_radix(mode, 8)?);
+        }
+
+        if let Some(owner) = matches.value_of("OUTPUT_FILE_OWNER") {
+            debug!("output.file-owner: {}", owner);
+
+            file_exporter = file_exporter.owner(get_uid(&mut users, owner)?);
+        }
+
+        if let Some(group) = matches.value_of("OUTPUT_FILE_GROUP") {
+            debug!("output.file-group: {}", group);
+
+            file_exporter = file_exporter.group(get_gid(&mut users, group)?);
+        }
+
+        // Without an interval, we write the metrics once and exit.
+        let interval = match matches.value_of("OUTPUT_INTERVAL") {
+            Some(interval) => interval.parse::<u64>()?,
+            None           => return file_exporter.export(&exporter),
+        };
+        debug!("output.interval: {}", interval);
+
+        let interval = Duration::from_secs(interval);
+        actix_rt::spawn(file_exporter.export_interval(
+            exporter.clone(),
+            interval,
+        ));
+    }
+
+    // If a Pushgateway URL was specified, we push metrics to it until we're
+    // asked to shut down. We never launch the HTTPd in this case.
+    if let Some(push_url) = matches.value_of("PUSH_URL") {
+        debug!("push.url: {}", push_url);
+
+        let delete_on_shutdown = cli::is_enabled(
+            &matches,
+            "PUSH_DELETE_ON_SHUTDOWN",
+        );
+        debug!("push.delete-on-shutdown: {}", delete_on_shutdown);
+
+        let mut pusher = Pusher::new(push_url)
+            .delete_on_shutdown(delete_on_shutdown);
+
+        if let Some(instance) = matches.value_of("PUSH_INSTANCE") {
+            debug!("push.instance: {}", instance);
+
+            pusher = pusher.instance(instance.to_owned());
+        }
+
+        // The interval was validated by the CLI, so this should always parse.
+        if let Some(interval) = matches.value_of("PUSH_INTERVAL") {
+            debug!("push.interval: {}", interval);
+
+            let interval = Duration::from_secs(interval.parse::<u64>()?);
+            pusher = pusher.interval(interval);
+        }
+
+        if let Some(job) = matches.value_of("PUSH_JOB") {
+            debug!("push.job: {}", job);
+
+            pusher = pusher.job(job.to_owned());
+        }
+
+        return pusher.run(&exporter).await;
+    }
+
+    // If a remote write URL was specified, we send metrics to it until we're
+    // asked to shut down. As with the Pushgateway, we never launch the HTTPd.
+    if let Some(remote_write_url) = matches.value_of("REMOTE_WRITE_URL") {
+        debug!("remote-write.url: {}", remote_write_url);
+
+        let mut writer = RemoteWriter::new(remote_write_url);
+
+        // The interval was validated by the CLI, so this should always parse.
+        if let Some(interval) = matches.value_of("REMOTE_WRITE_INTERVAL") {
+            debug!("remote-write.interval: {}", interval);
+
+            let interval = Duration::from_secs(interval.parse::<u64>()?);
+            writer = writer.interval(interval);
+        }
+
+        // The queue size was validated by the CLI, so this should always
+        // parse.
+        if let Some(queue_size) = matches.value_of("REMOTE_WRITE_QUEUE_SIZE") {
+            debug!("remote-write.queue-size: {}", queue_size);
+
+            writer = writer.queue_size(queue_size.parse::<usize>()?);
+        }
+
+        return writer.run(&exporter).await;
+    }
+
+    // If a StatsD address was specified, we send metrics to it until we're
+    // asked to shut down. Again, the HTTPd is not launched.
+    if let Some(statsd_address) = matches.value_of("STATSD_ADDRESS") {
+        debug!("statsd.address: {}", statsd_address);
+
+        let mut statsd = Statsd::new(statsd_address);
+
+        // The interval was validated by the CLI, so this should always parse.
+        if let Some(interval) = matches.value_of("STATSD_INTERVAL") {
+            debug!("statsd.interval: {}", interval);
+
+            let interval = Duration::from_secs(interval.parse::<u64>()?);
+            statsd = statsd.interval(interval);
+        }
+
+        if let Some(tags) = matches.values_of("STATSD_TAG") {
+            let tags: Vec<String> = tags.map(|tag| tag.to_owned()).collect();
+            debug!("statsd.tag: {:?}", tags);
+
+            statsd = statsd.tags(tags);
+        }
+
+        return statsd.run(&exporter).await;
+    }
+
+    // If an InfluxDB URL was specified, we write metrics to it until we're
+    // asked to shut down. The HTTPd is not launched.
+    if let Some(influx_url) = matches.value_of("INFLUX_URL") {
+        debug!("influx.url: {}", influx_url);
+
+        let mut influx = Influx::new(influx_url);
+
+        // The interval was validated by the CLI, so this should always parse.
+        if let Some(interval) = matches.value_of("INFLUX_INTERVAL") {
+            debug!("influx.interval: {}", interval);
+
+            let interval = Duration::from_secs(interval.parse::<u64>()?);
+            influx = influx.interval(interval);
+        }
+
+        if let Some(token) = matches.value_of("INFLUX_TOKEN") {
+            influx = influx.token(token.to_owned());
+        }
+
+        return influx.run(&exporter).await;
+    }
+
+    // If an OTLP collector URL was specified, we export metrics to it until
+    // we're asked to shut down. The HTTPd is not launched.
+    if let Some(otlp_url) = matches.value_of("OTLP_URL") {
+        debug!("otlp.url: {}", otlp_url);
+
+        let mut otlp = Otlp::new(otlp_url);
+
+        // The interval was validated by the CLI, so this should always parse.
+        if let Some(interval) = matches.value_of("OTLP_INTERVAL") {
+            debug!("otlp.interval: {}", interval);
+
+            let interval = Duration::from_secs(interval.parse::<u64>()?);
+            otlp = otlp.interval(interval);
+        }
+
+        return otlp.run(&exporter).await;
+    }
+
+    // If a Carbon address was specified, we send metrics to it until we're
+    // asked to shut down. The HTTPd is not launched.
+    if let Some(graphite_address) = matches.value_of("GRAPHITE_ADDRESS") {
+        debug!("graphite.address: {}", graphite_address);
+
+        let mut graphite = Graphite::new(graphite_address);
+
+        if let Some(host) = matches.value_of("GRAPHITE_HOST") {
+            debug!("graphite.host: {}", host);
+
+            graphite = graphite.host(host.to_owned());
+        }
+
+        // The interval was validated by the CLI, so this should always parse.
+        if let Some(interval) = matches.value_of("GRAPHITE_INTERVAL") {
+            debug!("graphite.interval: {}", interval);
+
+            let interval = Duration::from_secs(interval.parse::<u64>()?);
+            graphite = graphite.interval(interval);
+        }
+
+        if let Some(prefix) = matches.value_of("GRAPHITE_PREFIX") {
+            debug!("graphite.prefix: {}", prefix);
 
-        return exporter.export();
+            graphite = graphite.prefix(prefix.to_owned());
+        }
+
+        return graphite.run(&exporter).await;
     }
 
-    // Get the bind_address for the httpd::Server below.
+    // Get the bind_addresses for the httpd::Server below.
     // We shouldn't hit the error conditions here after the validation of the
     // CLI arguments passed.
-    let bind_address = matches.value_of("WEB_LISTEN_ADDRESS").ok_or(
-        ExporterError::ArgNotSet("web.listen-address".to_owned())
-    )?.to_owned();
-    debug!("web.listen-address: {}", bind_address);
+    let bind_addresses: Vec<String> = matches
+        .values_of("WEB_LISTEN_ADDRESS")
+        .ok_or(ExporterError::ArgNotSet("web.listen-address".to_owned()))?
+        .map(|address| address.to_owned())
+        .collect();
+    debug!("web.listen-address: {:?}", bind_addresses);
 
     // Get the WEB_TELEMETRY_PATH and turn it into an owned string for moving
     // into the httpd::Server below.
@@ -97,11 +379,133 @@ async fn main() -> Result<(), ExporterError> {
     )?.to_owned();
     debug!("web.telemetry-path: {}", telemetry_path);
 
+    // The size was validated by the CLI, so this should always parse.
+    if let Some(size) = matches.value_of("WEB_HISTORY_SIZE") {
+        debug!("web.history-size: {}", size);
+
+        exporter.set_history_size(size.parse::<usize>()?);
+    }
+
+    // The retention was validated by the CLI, so this should always parse.
+    if let Some(retention) = matches.value_of("WEB_HISTORY_RETENTION") {
+        debug!("web.history-retention: {}", retention);
+
+        let retention = Duration::from_secs(retention.parse::<u64>()?);
+        exporter.set_history_retention(retention);
+    }
+
+    let mut server = httpd::Server::new()
+        .bind_addresses(bind_addresses)
+        .exporter(exporter)
+        .telemetry_path(telemetry_path);
+
+    // The interval was validated by the CLI, so this should always parse.
+    if let Some(interval) = matches.value_of("WEB_HISTORY_INTERVAL") {
+        debug!("web.history-interval: {}", interval);
+
+        let interval = Duration::from_secs(interval.parse::<u64>()?);
+        server = server.history_interval(interval);
+    }
+
+    if let Some(action) = matches.value_of("WEB_SCRAPE_TIMEOUT_ACTION") {
+        debug!("web.scrape-timeout-action: {}", action);
+
+        let action = match action {
+            "unavailable" => httpd::TimeoutAction::Unavailable,
+            _             => httpd::TimeoutAction::Partial,
+        };
+
+        server = server.scrape_timeout_action(action);
+    }
+
+    // The offset was validated by the CLI, so this should always parse.
+    if let Some(offset) = matches.value_of("WEB_SCRAPE_TIMEOUT_OFFSET") {
+        debug!("web.scrape-timeout-offset: {}", offset);
+
+        let offset = Duration::from_secs_f64(offset.parse::<f64>()?);
+        server = server.scrape_timeout_offset(offset);
+    }
+
+    // The interval was validated by the CLI, so this should always parse.
+    if let Some(interval) = matches.value_of("WEB_STREAM_INTERVAL") {
+        debug!("web.stream-interval: {}", interval);
+
+        let interval = Duration::from_secs(interval.parse::<u64>()?);
+        server = server.stream_interval(interval);
+    }
+
+    // The mode was validated by the CLI, so this should always parse.
+    if let Some(mode) = matches.value_of("WEB_SOCKET_MODE") {
+        debug!("web.socket-mode: {}", mode);
+
+        server = server.socket_mode(u32::from_str_radix(mode, 8)?);
+    }
+
+    if let Some(group) = matches.value_of("WEB_SOCKET_GROUP") {
+        debug!("web.socket-group: {}", group);
+
+        server = server.socket_group(get_gid(&mut users, group)?);
+    }
+
+    // Both of these are required together by the CLI.
+    let tls_cert = matches.value_of("WEB_TLS_CERT");
+    let tls_key  = matches.value_of("WEB_TLS_KEY");
+
+    if let (Some(cert), Some(key)) = (tls_cert, tls_key) {
+        debug!("web.tls-cert: {}", cert);
+        debug!("web.tls-key: {}", key);
+
+        let mut tls = httpd::TlsConfig::new(cert.into(), key.into());
+
+        if let Some(client_ca) = matches.value_of("WEB_TLS_CLIENT_CA") {
+            debug!("web.tls-client-ca: {}", client_ca);
+
+            tls = tls.client_ca(client_ca.into());
+        }
+
+        if let Some(names) = matches.values_of("WEB_TLS_CLIENT_ALLOWED_NAME") {
+            let names: Vec<String> = names.map(|n| n.to_owned()).collect();
+            debug!("web.tls-client-allowed-name: {:?}", names);
+
+            tls = tls.client_allowed_names(names);
+        }
+
+        server = server.tls(tls);
+    }
+
+    if let Some(path) = matches.value_of("WEB_CONFIG_FILE") {
+        debug!("web.config.file: {}", path);
+
+        let config = httpd::WebConfig::from_file(Path::new(path))?;
+
+        if let Some(tls) = config.tls() {
+            server = server.tls(tls);
+        }
+
+        server = server.basic_auth(config.basic_auth_users().clone());
+    }
+
+    // The networks were validated by the CLI, so these should always parse.
+    if let Some(cidrs) = matches.values_of("WEB_ALLOW_CIDR") {
+        let cidrs = cidrs
+            .map(|cidr| cidr.parse().map_err(ExporterError::InvalidCidr))
+            .collect::<Result<Vec<httpd::Cidr>, _>>()?;
+        debug!("web.allow-cidr: {:?}", cidrs);
+
+        server = server.allow_cidrs(cidrs);
+    }
+
+    if let Some(proxies) = matches.values_of("WEB_TRUSTED_PROXY") {
+        let proxies = proxies
+            .map(|proxy| proxy.parse().map_err(ExporterError::InvalidCidr))
+            .collect::<Result<Vec<httpd::Cidr>, _>>()?;
+        debug!("web.trusted-proxy: {:?}", proxies);
+
+        server = server.trusted_proxies(proxies);
+    }
+
     // Configure and run the http server.
-    httpd::Server::new()
-        .bind_address(bind_address)
-        .telemetry_path(telemetry_path)
-        .run().await?;
+    server.run().await?;
 
     Ok(())
 }
@@ -111,6 +515,56 @@ mod tests {
     use super::*;
     use pretty_assertions::assert_eq;
 
+    #[test]
+    fn get_gid_name() {
+        use users::mock::{
+            Group,
+            MockUsers,
+        };
+
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_group(Group::new(80, "www"));
+
+        let gid = get_gid(&mut users, "www").unwrap();
+
+        assert_eq!(gid, 80);
+    }
+
+    #[test]
+    fn get_gid_unknown() {
+        use users::mock::MockUsers;
+
+        let mut users = MockUsers::with_current_uid(0);
+        let gid = get_gid(&mut users, "www");
+
+        assert!(gid.is_err());
+    }
+
+    #[test]
+    fn get_uid_name() {
+        use users::mock::{
+            MockUsers,
+            User,
+        };
+
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::new(80, "www", 80));
+
+        let uid = get_uid(&mut users, "www").unwrap();
+
+        assert_eq!(uid, 80);
+    }
+
+    #[test]
+    fn get_uid_numeric() {
+        use users::mock::MockUsers;
+
+        let mut users = MockUsers::with_current_uid(0);
+        let uid = get_uid(&mut users, "10000").unwrap();
+
+        assert_eq!(uid, 10000);
+    }
+
     #[test]
     fn is_running_as_root_ok() {
         use users::mock::{
@@ -0,0 +1,684 @@
+// OpenTelemetry OTLP/HTTP exporter
+#![forbid(unsafe_code)]
+#![forbid(missing_docs)]
+use crate::jails::jail_ids;
+use crate::signal::run_every;
+use actix_web::http::header::CONTENT_TYPE;
+use awc::Client;
+use futures::lock::Mutex;
+use jail_exporter::{
+    jail_name,
+    Exporter,
+    ExporterError,
+};
+use log::{
+    debug,
+    info,
+};
+use prometheus::proto::{
+    MetricFamily,
+    MetricType,
+};
+use prost::{
+    Message,
+    Oneof,
+};
+use std::collections::{
+    BTreeMap,
+    HashMap,
+};
+use std::time::{
+    Duration,
+    SystemTime,
+    UNIX_EPOCH,
+};
+
+// AggregationTemporality value for cumulative sums.
+const AGGREGATION_TEMPORALITY_CUMULATIVE: i32 = 2;
+
+// Timeout for each request made to the collector.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// The messages below are the subset of the OTLP metrics protobuf definitions
+// (opentelemetry-proto v1) that we need to export gauges and sums.
+#[derive(Clone, PartialEq, Message)]
+struct ExportMetricsServiceRequest {
+    #[prost(message, repeated, tag = "1")]
+    resource_metrics: Vec<ResourceMetrics>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct ResourceMetrics {
+    #[prost(message, optional, tag = "1")]
+    resource: Option<Resource>,
+    #[prost(message, repeated, tag = "2")]
+    scope_metrics: Vec<ScopeMetrics>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Resource {
+    #[prost(message, repeated, tag = "1")]
+    attributes: Vec<KeyValue>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct KeyValue {
+    #[prost(string, tag = "1")]
+    key: String,
+    #[prost(message, optional, tag = "2")]
+    value: Option<AnyValue>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct AnyValue {
+    #[prost(oneof = "Value", tags = "1, 3")]
+    value: Option<Value>,
+}
+
+#[derive(Clone, PartialEq, Oneof)]
+enum Value {
+    #[prost(string, tag = "1")]
+    StringValue(String),
+    #[prost(int64, tag = "3")]
+    IntValue(i64),
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct ScopeMetrics {
+    #[prost(message, optional, tag = "1")]
+    scope: Option<InstrumentationScope>,
+    #[prost(message, repeated, tag = "2")]
+    metrics: Vec<Metric>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct InstrumentationScope {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(string, tag = "2")]
+    version: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Metric {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(string, tag = "2")]
+    description: String,
+    #[prost(oneof = "Data", tags = "5, 7")]
+    data: Option<Data>,
+}
+
+#[derive(Clone, PartialEq, Oneof)]
+enum Data {
+    #[prost(message, tag = "5")]
+    Gauge(Gauge),
+    #[prost(message, tag = "7")]
+    Sum(Sum),
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Gauge {
+    #[prost(message, repeated, tag = "1")]
+    data_points: Vec<NumberDataPoint>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Sum {
+    #[prost(message, repeated, tag = "1")]
+    data_points: Vec<NumberDataPoint>,
+    #[prost(int32, tag = "2")]
+    aggregation_temporality: i32,
+    #[prost(bool, tag = "3")]
+    is_monotonic: bool,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct NumberDataPoint {
+    #[prost(message, repeated, tag = "7")]
+    attributes: Vec<KeyValue>,
+    #[prost(fixed64, tag = "2")]
+    start_time_unix_nano: u64,
+    #[prost(fixed64, tag = "3")]
+    time_unix_nano: u64,
+    #[prost(oneof = "NumberValue", tags = "4, 6")]
+    value: Option<NumberValue>,
+}
+
+#[derive(Clone, PartialEq, Oneof)]
+enum NumberValue {
+    #[prost(double, tag = "4")]
+    AsDouble(f64),
+    #[prost(sfixed64, tag = "6")]
+    AsInt(i64),
+}
+
+// Start times of the cumulative sums.
+// The exporter's counter bookkeeping creates a jail's counters when the jail
+// is first seen and removes them when the jail goes away, so the start time of
+// a jail's sums is the time we first saw the jail. A restarted jail has a new
+// jid and racct counters starting from 0 again, so start times are kept per
+// name and jid. Metrics without a jail use the time the exporter started.
+struct StartTimes {
+    exporter: u64,
+    jails:    HashMap<(String, i64), u64>,
+}
+
+impl StartTimes {
+    fn new(now: u64) -> Self {
+        Self {
+            exporter: now,
+            jails:    HashMap::new(),
+        }
+    }
+
+    // Records the start time of newly seen jails, given their jids keyed on
+    // name, and forgets jails that have gone away. A jail that comes back, or
+    // is restarted with a new jid, starts a new sum.
+    fn update(&mut self, jails: &HashMap<&str, i64>, now: u64) {
+        self.jails.retain(|(name, jid), _| {
+            jails.get(name.as_str()) == Some(jid)
+        });
+
+        for (name, jid) in jails {
+            self.jails.entry(((*name).to_owned(), *jid)).or_insert(now);
+        }
+    }
+
+    // Returns the start time of the sums of the jail with the given name and
+    // jid, or of the exporter for metrics without a jail.
+    fn get(&self, jail: Option<(&str, i64)>) -> u64 {
+        jail.and_then(|(name, jid)| self.jails.get(&(name.to_owned(), jid)))
+            .cloned()
+            .unwrap_or(self.exporter)
+    }
+}
+
+// Used for the Otlp builder
+pub struct Otlp {
+    host:     String,
+    interval: Duration,
+    url:      String,
+}
+
+impl Otlp {
+    // Returns a new Otlp exporting to the given collector /v1/metrics URL.
+    pub fn new(url: &str) -> Self {
+        debug!("New Otlp exporting to {}", url);
+
+        let host = hostname::get()
+            .map(|host| host.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| "localhost".into());
+
+        Self {
+            host,
+            interval: Duration::from_secs(15),
+            url:      url.into(),
+        }
+    }
+
+    // Sets how often metrics are exported.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        debug!("Setting Otlp interval to: {:?}", interval);
+
+        self.interval = interval;
+        self
+    }
+
+    // Collects metrics with the given exporter and exports them to the
+    // collector.
+    async fn export(&self, exporter: &Exporter, start_times: &mut StartTimes)
+    -> Result<(), ExporterError> {
+        let metric_families = exporter.gather()?;
+
+        self.export_metrics(&metric_families, start_times).await
+    }
+
+    // Exports the given metrics to the collector.
+    async fn export_metrics(
+        &self,
+        metric_families: &[MetricFamily],
+        start_times: &mut StartTimes,
+    ) -> Result<(), ExporterError> {
+        debug!("Exporting metrics to {}", self.url);
+
+        let now = now();
+
+        start_times.update(&jail_ids(metric_families), now);
+
+        let request = export_request(
+            metric_families,
+            &self.host,
+            start_times,
+            now,
+        );
+
+        let mut body = Vec::with_capacity(request.encoded_len());
+        request
+            .encode(&mut body)
+            .map_err(|e| ExporterError::PushError(e.to_string()))?;
+
+        let response = Client::new()
+            .post(&self.url)
+            .header(CONTENT_TYPE, "application/x-protobuf")
+            .timeout(REQUEST_TIMEOUT)
+            .send_body(body)
+            .await
+            .map_err(|e| ExporterError::PushError(e.to_string()))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(ExporterError::PushError(status.to_string()));
+        }
+
+        Ok(())
+    }
+
+    // Exports metrics every interval until we receive SIGINT or SIGTERM.
+    pub async fn run(self, exporter: &Exporter) -> Result<(), ExporterError> {
+        info!("Exporting metrics to {} every {:?}", self.url, self.interval);
+
+        let otlp = &self;
+        let start_times = &Mutex::new(StartTimes::new(now()));
+
+        run_every(self.interval, "export metrics over OTLP", || async move {
+            let mut start_times = start_times.lock().await;
+            otlp.export(exporter, &mut start_times).await
+        }).await
+    }
+}
+
+// Returns the current time in nanoseconds.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+fn string_attribute(key: &str, value: &str) -> KeyValue {
+    KeyValue {
+        key:   key.into(),
+        value: Some(AnyValue {
+            value: Some(Value::StringValue(value.into())),
+        }),
+    }
+}
+
+fn int_attribute(key: &str, value: i64) -> KeyValue {
+    KeyValue {
+        key:   key.into(),
+        value: Some(AnyValue {
+            value: Some(Value::IntValue(value)),
+        }),
+    }
+}
+
+// Returns ResourceMetrics holding the given metrics under a resource with the
+// given attributes.
+fn resource_metrics(attributes: Vec<KeyValue>, metrics: Vec<Metric>)
+-> ResourceMetrics {
+    let scope = InstrumentationScope {
+        name:    env!("CARGO_PKG_NAME").into(),
+        version: env!("CARGO_PKG_VERSION").into(),
+    };
+
+    ResourceMetrics {
+        resource:      Some(Resource { attributes }),
+        scope_metrics: vec![ScopeMetrics {
+            scope: Some(scope),
+            metrics,
+        }],
+    }
+}
+
+// Converts gathered metric families into an OTLP export request.
+// Each jail becomes a resource with jail.name, jail.id and host.name
+// attributes, holding a gauge or monotonic cumulative sum for each rctl
+// resource. Metrics that don't belong to a jail are held by a resource with
+// only the host.name attribute. Each metric family appears once per resource,
+// with a data point for each of its series there. The jail_id metric is not
+// exported, as it is carried by the jail.id attribute.
+fn export_request(
+    metric_families: &[MetricFamily],
+    host: &str,
+    start_times: &StartTimes,
+    now: u64,
+) -> ExportMetricsServiceRequest {
+    let jids = jail_ids(metric_families);
+
+    let mut host_metrics = Vec::new();
+    let mut jail_metrics: BTreeMap<&str, Vec<Metric>> = BTreeMap::new();
+
+    for family in metric_families {
+        if family.get_name() == "jail_id" {
+            continue;
+        }
+
+        let is_sum = match family.get_field_type() {
+            MetricType::COUNTER => true,
+            MetricType::GAUGE   => false,
+            _                   => continue,
+        };
+
+        // Series of a family that share a resource are data points of a
+        // single metric, keyed on jail name, or None for the host.
+        let mut data_points: BTreeMap<Option<&str>, Vec<NumberDataPoint>> =
+            BTreeMap::new();
+
+        for metric in family.get_metric() {
+            let value = if is_sum {
+                metric.get_counter().get_value()
+            }
+            else {
+                metric.get_gauge().get_value()
+            };
+
+            let jail = jail_name(metric);
+            let jail_id = jail
+                .and_then(|name| jids.get(name).map(|jid| (name, *jid)));
+
+            // Labels other than the jail name become data point attributes.
+            let attributes = metric
+                .get_label()
+                .iter()
+                .filter(|pair| pair.get_name() != "name")
+                .map(|pair| string_attribute(pair.get_name(), pair.get_value()))
+                .collect();
+
+            data_points.entry(jail).or_default().push(NumberDataPoint {
+                attributes,
+                start_time_unix_nano: start_times.get(jail_id),
+                time_unix_nano:       now,
+                value:                Some(NumberValue::AsInt(value as i64)),
+            });
+        }
+
+        for (jail, data_points) in data_points {
+            let data = if is_sum {
+                Data::Sum(Sum {
+                    data_points,
+                    aggregation_temporality: AGGREGATION_TEMPORALITY_CUMULATIVE,
+                    is_monotonic:            true,
+                })
+            }
+            else {
+                Data::Gauge(Gauge {
+                    data_points,
+                })
+            };
+
+            let metric = Metric {
+                name:        family.get_name().into(),
+                description: family.get_help().into(),
+                data:        Some(data),
+            };
+
+            match jail {
+                Some(name) => {
+                    jail_metrics.entry(name).or_default().push(metric);
+                },
+                None => host_metrics.push(metric),
+            }
+        }
+    }
+
+    let mut resource_metrics_list = vec![resource_metrics(
+        vec![string_attribute("host.name", host)],
+        host_metrics,
+    )];
+
+    for (name, metrics) in jail_metrics {
+        let mut attributes = vec![
+            string_attribute("jail.name", name),
+            string_attribute("host.name", host),
+        ];
+
+        if let Some(jid) = jids.get(name) {
+            attributes.insert(1, int_attribute("jail.id", *jid));
+        }
+
+        resource_metrics_list.push(resource_metrics(attributes, metrics));
+    }
+
+    ExportMetricsServiceRequest {
+        resource_metrics: resource_metrics_list,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::serve;
+    use pretty_assertions::assert_eq;
+    use prometheus::{
+        IntCounterVec,
+        IntGauge,
+        IntGaugeVec,
+        Opts,
+        Registry,
+    };
+
+    // Returns metric families for a single jail named "www" with jid 7.
+    fn metric_families() -> Vec<MetricFamily> {
+        let registry = Registry::new();
+
+        let jail_num = IntGauge::new("jail_num", "number of jails").unwrap();
+        jail_num.set(1);
+        registry.register(Box::new(jail_num)).unwrap();
+
+        let opts = Opts::new("jail_id", "jail ID");
+        let jail_id = IntGaugeVec::new(opts, &["name"]).unwrap();
+        jail_id.with_label_values(&["www"]).set(7);
+        registry.register(Box::new(jail_id)).unwrap();
+
+        let opts = Opts::new("jail_cputime_seconds_total", "CPU time");
+        let cputime = IntCounterVec::new(opts, &["name"]).unwrap();
+        cputime.with_label_values(&["www"]).inc_by(20);
+        registry.register(Box::new(cputime)).unwrap();
+
+        let opts = Opts::new("jail_memoryuse_bytes", "memory use");
+        let memoryuse = IntGaugeVec::new(opts, &["name"]).unwrap();
+        memoryuse.with_label_values(&["www"]).set(1024);
+        registry.register(Box::new(memoryuse)).unwrap();
+
+        registry.gather()
+    }
+
+    fn data_point(start: u64, now: u64, value: i64) -> NumberDataPoint {
+        NumberDataPoint {
+            attributes:           vec![],
+            start_time_unix_nano: start,
+            time_unix_nano:       now,
+            value:                Some(NumberValue::AsInt(value)),
+        }
+    }
+
+    #[test]
+    fn export_request_jail_resource() {
+        let mut start_times = StartTimes::new(100);
+        start_times.update(&jails(&[("www", 7)]), 200);
+
+        let request = export_request(
+            &metric_families(),
+            "host1",
+            &start_times,
+            300,
+        );
+
+        assert_eq!(request.resource_metrics.len(), 2);
+
+        let jail = &request.resource_metrics[1];
+        let attributes = &jail.resource.as_ref().unwrap().attributes;
+
+        assert_eq!(attributes, &vec![
+            string_attribute("jail.name", "www"),
+            int_attribute("jail.id", 7),
+            string_attribute("host.name", "host1"),
+        ]);
+
+        let metrics = &jail.scope_metrics[0].metrics;
+        assert_eq!(metrics.len(), 2);
+
+        assert_eq!(metrics[0].name, "jail_cputime_seconds_total");
+        assert_eq!(metrics[0].data, Some(Data::Sum(Sum {
+            data_points:             vec![data_point(200, 300, 20)],
+            aggregation_temporality: AGGREGATION_TEMPORALITY_CUMULATIVE,
+            is_monotonic:            true,
+        })));
+
+        assert_eq!(metrics[1].name, "jail_memoryuse_bytes");
+        assert_eq!(metrics[1].data, Some(Data::Gauge(Gauge {
+            data_points: vec![data_point(200, 300, 1024)],
+        })));
+    }
+
+    #[test]
+    fn export_request_host_resource() {
+        let start_times = StartTimes::new(100);
+
+        let request = export_request(
+            &metric_families(),
+            "host1",
+            &start_times,
+            300,
+        );
+
+        let host = &request.resource_metrics[0];
+        let attributes = &host.resource.as_ref().unwrap().attributes;
+
+        assert_eq!(attributes, &vec![string_attribute("host.name", "host1")]);
+
+        let metrics = &host.scope_metrics[0].metrics;
+        let names: Vec<_> = metrics.iter().map(|m| m.name.as_str()).collect();
+
+        assert_eq!(names, vec!["jail_num"]);
+    }
+
+    // Returns the jids of the given jails, keyed on name.
+    fn jails<'a>(jails: &[(&'a str, i64)]) -> HashMap<&'a str, i64> {
+        jails.iter().cloned().collect()
+    }
+
+    #[test]
+    fn export_request_groups_series() {
+        let registry = Registry::new();
+
+        let opts = Opts::new("jail_events_total", "jail events");
+        let events = IntCounterVec::new(opts, &["type"]).unwrap();
+        events.with_label_values(&["started"]).inc_by(2);
+        events.with_label_values(&["stopped"]).inc_by(1);
+        registry.register(Box::new(events)).unwrap();
+
+        let start_times = StartTimes::new(100);
+
+        let request = export_request(
+            &registry.gather(),
+            "host1",
+            &start_times,
+            300,
+        );
+
+        let metrics = &request.resource_metrics[0].scope_metrics[0].metrics;
+        assert_eq!(metrics.len(), 1);
+
+        let data_point = |kind, value| {
+            NumberDataPoint {
+                attributes: vec![string_attribute("type", kind)],
+                ..data_point(100, 300, value)
+            }
+        };
+
+        assert_eq!(metrics[0].name, "jail_events_total");
+        assert_eq!(metrics[0].data, Some(Data::Sum(Sum {
+            data_points:             vec![
+                data_point("started", 2),
+                data_point("stopped", 1),
+            ],
+            aggregation_temporality: AGGREGATION_TEMPORALITY_CUMULATIVE,
+            is_monotonic:            true,
+        })));
+    }
+
+    #[test]
+    fn start_times_update() {
+        let mut start_times = StartTimes::new(100);
+
+        start_times.update(&jails(&[("a", 1), ("b", 2)]), 200);
+        start_times.update(&jails(&[("a", 1)]), 300);
+        start_times.update(&jails(&[("a", 1), ("b", 3)]), 400);
+
+        assert_eq!(start_times.get(Some(("a", 1))), 200);
+        assert_eq!(start_times.get(Some(("b", 3))), 400);
+        assert_eq!(start_times.get(Some(("b", 2))), 100);
+        assert_eq!(start_times.get(None), 100);
+    }
+
+    #[test]
+    fn start_times_update_restarted() {
+        let mut start_times = StartTimes::new(100);
+
+        // b is restarted between two exports, coming back with a new jid.
+        start_times.update(&jails(&[("a", 1), ("b", 2)]), 200);
+        start_times.update(&jails(&[("a", 1), ("b", 3)]), 300);
+
+        assert_eq!(start_times.get(Some(("a", 1))), 200);
+        assert_eq!(start_times.get(Some(("b", 3))), 300);
+        assert_eq!(start_times.jails.len(), 2);
+    }
+
+    #[actix_rt::test]
+    async fn export_ok() {
+        let (url, rx) = serve("200 OK");
+        let otlp = Otlp::new(&format!("{}/v1/metrics", url));
+        let mut start_times = StartTimes::new(now());
+
+        otlp.export_metrics(&metric_families(), &mut start_times)
+            .await
+            .unwrap();
+
+        let request = rx.recv().unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/v1/metrics");
+        assert_eq!(
+            request.header("content-type"),
+            Some("application/x-protobuf"),
+        );
+
+        let export = ExportMetricsServiceRequest::decode(&request.body[..])
+            .unwrap();
+        assert_eq!(export.resource_metrics.len(), 2);
+
+        let host = &export.resource_metrics[0];
+        let scope = &host.scope_metrics[0];
+
+        assert_eq!(scope.scope.as_ref().unwrap().name, "jail_exporter");
+        assert_eq!(scope.metrics.len(), 1);
+        assert_eq!(scope.metrics[0].name, "jail_num");
+
+        match &scope.metrics[0].data {
+            Some(Data::Gauge(gauge)) => {
+                assert_eq!(
+                    gauge.data_points[0].value,
+                    Some(NumberValue::AsInt(1)),
+                );
+            },
+            _ => panic!("jail_num should be a gauge"),
+        }
+
+        // The jail was seen for the first time by this export.
+        assert_eq!(start_times.jails.len(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn export_error_status() {
+        let (url, _rx) = serve("503 Service Unavailable");
+        let otlp = Otlp::new(&url);
+        let mut start_times = StartTimes::new(now());
+
+        let res = otlp.export_metrics(&metric_families(), &mut start_times)
+            .await;
+
+        assert!(res.is_err());
+    }
+}
@@ -0,0 +1,258 @@
+// Pushgateway exporter
+#![forbid(unsafe_code)]
+#![forbid(missing_docs)]
+use crate::signal::run_every;
+use actix_web::http::header::CONTENT_TYPE;
+use awc::Client;
+use jail_exporter::{
+    Exporter,
+    ExporterError,
+};
+use log::{
+    debug,
+    info,
+};
+use mime::TEXT_PLAIN_UTF_8;
+use percent_encoding::{
+    utf8_percent_encode,
+    AsciiSet,
+    NON_ALPHANUMERIC,
+};
+use std::time::Duration;
+
+// Characters that don't need encoding in a grouping key path segment.
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+// Timeout for each request made to the Pushgateway.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Used for the Pushgateway builder
+pub struct Pusher {
+    delete_on_shutdown: bool,
+    instance:           Option<String>,
+    interval:           Duration,
+    job:                String,
+    url:                String,
+}
+
+impl Pusher {
+    // Returns a new Pusher for the Pushgateway at the given URL.
+    pub fn new(url: &str) -> Self {
+        debug!("New Pusher pushing to {}", url);
+
+        Self {
+            delete_on_shutdown: false,
+            instance:           None,
+            interval:           Duration::from_secs(15),
+            job:                "jail_exporter".into(),
+            url:                url.trim_end_matches('/').into(),
+        }
+    }
+
+    // Sets whether the pushed metrics are deleted from the Pushgateway when
+    // we shut down.
+    pub fn delete_on_shutdown(mut self, delete_on_shutdown: bool) -> Self {
+        debug!("Setting Pusher delete_on_shutdown to: {}", delete_on_shutdown);
+
+        self.delete_on_shutdown = delete_on_shutdown;
+        self
+    }
+
+    // Sets the instance grouping key.
+    pub fn instance(mut self, instance: String) -> Self {
+        debug!("Setting Pusher instance to: {}", instance);
+
+        self.instance = Some(instance);
+        self
+    }
+
+    // Sets how often metrics are pushed.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        debug!("Setting Pusher interval to: {:?}", interval);
+
+        self.interval = interval;
+        self
+    }
+
+    // Sets the job grouping key.
+    pub fn job(mut self, job: String) -> Self {
+        debug!("Setting Pusher job to: {}", job);
+
+        self.job = job;
+        self
+    }
+
+    // Returns the URL of our group on the Pushgateway.
+    fn group_url(&self) -> String {
+        let mut url = format!(
+            "{}/metrics/{}",
+            self.url,
+            grouping_key("job", &self.job),
+        );
+
+        if let Some(instance) = &self.instance {
+            url.push('/');
+            url.push_str(&grouping_key("instance", instance));
+        }
+
+        url
+    }
+
+    // Collects metrics with the given exporter and pushes them, replacing any
+    // metrics previously pushed to our group.
+    async fn push(&self, exporter: &Exporter) -> Result<(), ExporterError> {
+        let url = self.group_url();
+        debug!("Pushing metrics to {}", url);
+
+        let metrics = exporter.export()?;
+
+        let request = Client::new()
+            .put(&url)
+            .header(CONTENT_TYPE, TEXT_PLAIN_UTF_8)
+            .timeout(REQUEST_TIMEOUT)
+            .send_body(metrics);
+
+        check_response(request.await)
+    }
+
+    // Deletes all metrics in our group from the Pushgateway.
+    async fn delete(&self) -> Result<(), ExporterError> {
+        let url = self.group_url();
+        debug!("Deleting metrics from {}", url);
+
+        let request = Client::new()
+            .delete(&url)
+            .timeout(REQUEST_TIMEOUT)
+            .send();
+
+        check_response(request.await)
+    }
+
+    // Pushes metrics every interval until we receive SIGINT or SIGTERM.
+    pub async fn run(self, exporter: &Exporter) -> Result<(), ExporterError> {
+        info!("Pushing metrics to {} every {:?}", self.url, self.interval);
+
+        run_every(self.interval, "push metrics", || self.push(exporter))
+            .await?;
+
+        if self.delete_on_shutdown {
+            self.delete().await?;
+        }
+
+        Ok(())
+    }
+}
+
+// Returns a grouping key path for the given label name and value.
+// Values containing a / can't be represented in a path even when percent
+// encoded, so the Pushgateway accepts these base64 encoded instead. Empty
+// values must also be given in this way.
+fn grouping_key(name: &str, value: &str) -> String {
+    if value.is_empty() || value.contains('/') {
+        let value = base64::encode_config(value, base64::URL_SAFE);
+
+        // An empty value must still be a non-empty path segment.
+        let value = if value.is_empty() { "=".into() } else { value };
+
+        format!("{}@base64/{}", name, value)
+    }
+    else {
+        format!("{}/{}", name, utf8_percent_encode(value, PATH_SEGMENT))
+    }
+}
+
+// Converts the result of a Pushgateway request into our result, treating any
+// non-2xx status as an error.
+fn check_response<B>(
+    response: Result<awc::ClientResponse<B>, awc::error::SendRequestError>,
+) -> Result<(), ExporterError> {
+    let response = response
+        .map_err(|e| ExporterError::PushError(e.to_string()))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        return Err(ExporterError::PushError(status.to_string()));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::serve;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn grouping_key_base64() {
+        let key = grouping_key("instance", "a/b");
+        assert_eq!(key, "instance@base64/YS9i");
+    }
+
+    #[test]
+    fn grouping_key_empty() {
+        let key = grouping_key("instance", "");
+        assert_eq!(key, "instance@base64/=");
+    }
+
+    #[test]
+    fn grouping_key_escaped() {
+        let key = grouping_key("instance", "host:9452 a");
+        assert_eq!(key, "instance/host%3A9452%20a");
+    }
+
+    #[test]
+    fn group_url_ok() {
+        let pusher = Pusher::new("http://127.0.0.1:9091/")
+            .job("jails".into())
+            .instance("host.example.com".into());
+
+        assert_eq!(
+            pusher.group_url(),
+            "http://127.0.0.1:9091/metrics/job/jails/instance/host.example.com",
+        );
+    }
+
+    #[actix_rt::test]
+    async fn push_ok() {
+        let (url, rx) = serve("200 OK");
+        let pusher = Pusher::new(&url).instance("host1".into());
+        let exporter = Exporter::new();
+
+        pusher.push(&exporter).await.unwrap();
+
+        let request = rx.recv().unwrap();
+        assert_eq!(request.method, "PUT");
+        assert_eq!(request.path, "/metrics/job/jail_exporter/instance/host1");
+        let body = String::from_utf8(request.body).unwrap();
+        assert!(body.contains("jail_exporter_build_info{version="));
+    }
+
+    #[actix_rt::test]
+    async fn push_error_status() {
+        let (url, _rx) = serve("500 Internal Server Error");
+        let pusher = Pusher::new(&url);
+        let exporter = Exporter::new();
+
+        let res = pusher.push(&exporter).await;
+
+        assert!(res.is_err());
+    }
+
+    #[actix_rt::test]
+    async fn delete_ok() {
+        let (url, rx) = serve("202 Accepted");
+        let pusher = Pusher::new(&url).job("jails".into());
+
+        pusher.delete().await.unwrap();
+
+        let request = rx.recv().unwrap();
+        assert_eq!(request.method, "DELETE");
+        assert_eq!(request.path, "/metrics/job/jails");
+        assert!(request.body.is_empty());
+    }
+}
@@ -0,0 +1,491 @@
+// Prometheus remote write sender
+#![forbid(unsafe_code)]
+#![forbid(missing_docs)]
+use crate::signal::run_every;
+use actix_rt::time;
+use actix_web::http::header::{
+    CONTENT_ENCODING,
+    CONTENT_TYPE,
+};
+use actix_web::http::StatusCode;
+use awc::Client;
+use futures::lock::Mutex;
+use jail_exporter::{
+    Exporter,
+    ExporterError,
+};
+use log::{
+    debug,
+    error,
+    info,
+    warn,
+};
+use prometheus::proto::{
+    MetricFamily,
+    MetricType,
+};
+use prost::Message;
+use std::collections::VecDeque;
+use std::time::{
+    Duration,
+    SystemTime,
+    UNIX_EPOCH,
+};
+
+// Version of the remote write protocol that we speak.
+const REMOTE_WRITE_VERSION: &str = "0.1.0";
+
+// Timeout for each request made to the remote write endpoint.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Delay before the first retry of a failed write. This doubles with each
+// consecutive failure.
+const RETRY_MIN_DELAY: Duration = Duration::from_secs(1);
+
+// The messages below are the subset of the remote write protobuf definitions
+// (prometheus/prompb) that we need to send samples.
+#[derive(Clone, PartialEq, Message)]
+struct WriteRequest {
+    #[prost(message, repeated, tag = "1")]
+    timeseries: Vec<TimeSeries>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct TimeSeries {
+    #[prost(message, repeated, tag = "1")]
+    labels: Vec<Label>,
+    #[prost(message, repeated, tag = "2")]
+    samples: Vec<Sample>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Label {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(string, tag = "2")]
+    value: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Sample {
+    #[prost(double, tag = "1")]
+    value: f64,
+    #[prost(int64, tag = "2")]
+    timestamp: i64,
+}
+
+// The reason a write to the remote write endpoint failed.
+#[derive(Debug, PartialEq)]
+enum WriteError {
+    // The write may succeed if we try again later, e.g. the endpoint was
+    // unreachable or returned a 5xx.
+    Retryable(String),
+    // The endpoint rejected the write, retrying it won't help.
+    Rejected(String),
+}
+
+// A bounded queue of encoded writes waiting to be sent. When the queue is
+// full, the oldest write is dropped to make room for the newest.
+struct Queue {
+    capacity: usize,
+    writes:   VecDeque<Vec<u8>>,
+}
+
+impl Queue {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            writes: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    // Adds a write to the back of the queue, returning the write that was
+    // dropped to make room, if any.
+    fn push(&mut self, write: Vec<u8>) -> Option<Vec<u8>> {
+        let dropped = if self.writes.len() >= self.capacity {
+            self.writes.pop_front()
+        }
+        else {
+            None
+        };
+
+        self.writes.push_back(write);
+        dropped
+    }
+}
+
+// Exponential backoff between retries of a failed write.
+struct Backoff {
+    delay: Duration,
+}
+
+impl Backoff {
+    fn new() -> Self {
+        Self {
+            delay: RETRY_MIN_DELAY,
+        }
+    }
+
+    // Returns the delay before the next retry, doubling the delay for the
+    // retry after that.
+    fn next_delay(&mut self) -> Duration {
+        let delay = self.delay;
+        self.delay = delay * 2;
+        delay
+    }
+
+    // Called after a successful write.
+    fn reset(&mut self) {
+        self.delay = RETRY_MIN_DELAY;
+    }
+}
+
+// Used for the RemoteWriter builder
+pub struct RemoteWriter {
+    interval:   Duration,
+    queue_size: usize,
+    url:        String,
+}
+
+impl RemoteWriter {
+    // Returns a new RemoteWriter sending to the given remote write URL.
+    pub fn new(url: &str) -> Self {
+        debug!("New RemoteWriter sending to {}", url);
+
+        Self {
+            interval:   Duration::from_secs(15),
+            queue_size: 100,
+            url:        url.into(),
+        }
+    }
+
+    // Sets how often metrics are collected and sent.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        debug!("Setting RemoteWriter interval to: {:?}", interval);
+
+        self.interval = interval;
+        self
+    }
+
+    // Sets how many writes may be waiting to be sent before the oldest are
+    // dropped.
+    pub fn queue_size(mut self, queue_size: usize) -> Self {
+        debug!("Setting RemoteWriter queue_size to: {}", queue_size);
+
+        self.queue_size = queue_size;
+        self
+    }
+
+    // Sends a single encoded write to the remote write endpoint.
+    async fn send(&self, body: Vec<u8>) -> Result<(), WriteError> {
+        debug!("Sending {} bytes to {}", body.len(), self.url);
+
+        let request = Client::new()
+            .post(&self.url)
+            .header(CONTENT_ENCODING, "snappy")
+            .header(CONTENT_TYPE, "application/x-protobuf")
+            .header("X-Prometheus-Remote-Write-Version", REMOTE_WRITE_VERSION)
+            .timeout(REQUEST_TIMEOUT)
+            .send_body(body);
+
+        let response = request
+            .await
+            .map_err(|e| WriteError::Retryable(e.to_string()))?;
+
+        check_status(response.status())
+    }
+
+    // Sends queued writes, oldest first, until the queue is empty or the
+    // endpoint keeps failing. Retries back off exponentially, giving up until
+    // the next interval once the delay would exceed the interval.
+    async fn flush(&self, queue: &mut Queue, backoff: &mut Backoff) {
+        while let Some(write) = queue.writes.front() {
+            match self.send(write.clone()).await {
+                Ok(_) => {
+                    queue.writes.pop_front();
+                    backoff.reset();
+                },
+                Err(WriteError::Rejected(e)) => {
+                    error!("Remote write rejected, dropping write: {}", e);
+                    queue.writes.pop_front();
+                },
+                Err(WriteError::Retryable(e)) => {
+                    let delay = backoff.next_delay();
+                    warn!("Remote write failed: {}", e);
+
+                    if delay >= self.interval {
+                        break;
+                    }
+
+                    debug!("Retrying remote write in {:?}", delay);
+                    time::delay_for(delay).await;
+                },
+            }
+        }
+    }
+
+    // Collects metrics every interval and sends them to the remote write
+    // endpoint until we receive SIGINT or SIGTERM. Collections that can't be
+    // sent yet are queued, so a temporary outage doesn't lose samples.
+    pub async fn run(self, exporter: &Exporter) -> Result<(), ExporterError> {
+        info!(
+            "Sending metrics to {} every {:?}",
+            self.url,
+            self.interval,
+        );
+
+        let writer = &self;
+        let state = &Mutex::new((Backoff::new(), Queue::new(self.queue_size)));
+
+        run_every(self.interval, "collect metrics", || async move {
+            let mut state = state.lock().await;
+            let (backoff, queue) = &mut *state;
+
+            // Earlier collections are still flushed when this one fails.
+            let collected = collect(exporter).map(|write| {
+                if queue.push(write).is_some() {
+                    warn!("Remote write queue full, dropped oldest write");
+                }
+            });
+
+            writer.flush(queue, backoff).await;
+
+            collected
+        }).await
+    }
+}
+
+// Maps the status of a remote write response to our result. As with
+// Prometheus itself, 5xx and 429 responses are retried while other errors
+// cause the write to be dropped.
+fn check_status(status: StatusCode) -> Result<(), WriteError> {
+    if status.is_success() {
+        Ok(())
+    }
+    else if status.is_server_error()
+        || status == StatusCode::TOO_MANY_REQUESTS {
+        Err(WriteError::Retryable(status.to_string()))
+    }
+    else {
+        Err(WriteError::Rejected(status.to_string()))
+    }
+}
+
+// Collects metrics with the given exporter and returns them as an encoded
+// write request, timestamped with the current time.
+fn collect(exporter: &Exporter) -> Result<Vec<u8>, ExporterError> {
+    let metric_families = exporter.gather()?;
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default();
+
+    encode(&write_request(&metric_families, timestamp))
+}
+
+// Converts gathered metric families into a write request, with one time
+// series per metric. Only counters and gauges are converted, as those are the
+// only types the exporter produces.
+fn write_request(metric_families: &[MetricFamily], timestamp: i64)
+-> WriteRequest {
+    let mut timeseries = Vec::new();
+
+    for family in metric_families {
+        for metric in family.get_metric() {
+            let value = match family.get_field_type() {
+                MetricType::COUNTER => metric.get_counter().get_value(),
+                MetricType::GAUGE   => metric.get_gauge().get_value(),
+                _                   => continue,
+            };
+
+            let mut labels: Vec<Label> = metric
+                .get_label()
+                .iter()
+                .map(|pair| Label {
+                    name:  pair.get_name().into(),
+                    value: pair.get_value().into(),
+                })
+                .collect();
+
+            labels.push(Label {
+                name:  "__name__".into(),
+                value: family.get_name().into(),
+            });
+
+            // Receivers expect labels to be sorted by name.
+            labels.sort_by(|a, b| a.name.cmp(&b.name));
+
+            timeseries.push(TimeSeries {
+                labels,
+                samples: vec![Sample { value, timestamp }],
+            });
+        }
+    }
+
+    WriteRequest { timeseries }
+}
+
+// Encodes a write request as snappy compressed protobuf.
+fn encode(write_request: &WriteRequest) -> Result<Vec<u8>, ExporterError> {
+    let mut buf = Vec::with_capacity(write_request.encoded_len());
+
+    write_request
+        .encode(&mut buf)
+        .map_err(|e| ExporterError::RemoteWriteError(e.to_string()))?;
+
+    snap::raw::Encoder::new()
+        .compress_vec(&buf)
+        .map_err(|e| ExporterError::RemoteWriteError(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::serve;
+    use pretty_assertions::assert_eq;
+    use prometheus::{
+        IntCounterVec,
+        IntGauge,
+        Opts,
+        Registry,
+    };
+
+    // Decodes a body sent to the stand-in receiver.
+    fn decode(body: &[u8]) -> WriteRequest {
+        let buf = snap::raw::Decoder::new().decompress_vec(body).unwrap();
+        WriteRequest::decode(&buf[..]).unwrap()
+    }
+
+    fn label(name: &str, value: &str) -> Label {
+        Label {
+            name:  name.into(),
+            value: value.into(),
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_and_resets() {
+        let mut backoff = Backoff::new();
+
+        assert_eq!(backoff.next_delay(), Duration::from_secs(1));
+        assert_eq!(backoff.next_delay(), Duration::from_secs(2));
+        assert_eq!(backoff.next_delay(), Duration::from_secs(4));
+
+        backoff.reset();
+
+        assert_eq!(backoff.next_delay(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn check_status_retryable() {
+        let res = check_status(StatusCode::SERVICE_UNAVAILABLE);
+        let expected = "503 Service Unavailable".to_owned();
+        assert_eq!(res, Err(WriteError::Retryable(expected)));
+
+        let res = check_status(StatusCode::TOO_MANY_REQUESTS);
+        let expected = "429 Too Many Requests".to_owned();
+        assert_eq!(res, Err(WriteError::Retryable(expected)));
+    }
+
+    #[test]
+    fn check_status_rejected() {
+        let res = check_status(StatusCode::BAD_REQUEST);
+        let expected = "400 Bad Request".to_owned();
+        assert_eq!(res, Err(WriteError::Rejected(expected)));
+    }
+
+    #[test]
+    fn queue_drops_oldest() {
+        let mut queue = Queue::new(2);
+
+        assert_eq!(queue.push(vec![1]), None);
+        assert_eq!(queue.push(vec![2]), None);
+        assert_eq!(queue.push(vec![3]), Some(vec![1]));
+
+        let writes: Vec<_> = queue.writes.into_iter().collect();
+        assert_eq!(writes, vec![vec![2], vec![3]]);
+    }
+
+    #[test]
+    fn write_request_ok() {
+        let registry = Registry::new();
+
+        let jail_num = IntGauge::new("jail_num", "number of jails").unwrap();
+        jail_num.set(1);
+        registry.register(Box::new(jail_num)).unwrap();
+
+        let opts = Opts::new("jail_cputime_seconds_total", "CPU time");
+        let cputime = IntCounterVec::new(opts, &["name"]).unwrap();
+        cputime.with_label_values(&["test"]).inc_by(5);
+        registry.register(Box::new(cputime)).unwrap();
+
+        let write_request = write_request(&registry.gather(), 1000);
+
+        let expected = WriteRequest {
+            timeseries: vec![
+                TimeSeries {
+                    labels: vec![
+                        label("__name__", "jail_cputime_seconds_total"),
+                        label("name", "test"),
+                    ],
+                    samples: vec![Sample { value: 5.0, timestamp: 1000 }],
+                },
+                TimeSeries {
+                    labels: vec![label("__name__", "jail_num")],
+                    samples: vec![Sample { value: 1.0, timestamp: 1000 }],
+                },
+            ],
+        };
+
+        assert_eq!(write_request, expected);
+    }
+
+    #[actix_rt::test]
+    async fn send_ok() {
+        let (url, rx) = serve("204 No Content");
+        let writer = RemoteWriter::new(&format!("{}/api/v1/write", url));
+        let exporter = Exporter::new();
+
+        let write = collect(&exporter).unwrap();
+        writer.send(write).await.unwrap();
+
+        let request = rx.recv().unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/api/v1/write");
+        assert_eq!(request.header("content-encoding"), Some("snappy"));
+        assert_eq!(
+            request.header("content-type"),
+            Some("application/x-protobuf"),
+        );
+        assert_eq!(
+            request.header("x-prometheus-remote-write-version"),
+            Some("0.1.0"),
+        );
+
+        let write_request = decode(&request.body);
+        let build_info = write_request
+            .timeseries
+            .iter()
+            .find(|ts| {
+                ts.labels.contains(
+                    &label("__name__", "jail_exporter_build_info"),
+                )
+            })
+            .unwrap();
+
+        assert_eq!(build_info.samples.len(), 1);
+        assert_eq!(build_info.samples[0].value, 1.0);
+    }
+
+    #[actix_rt::test]
+    async fn send_error_status() {
+        let (url, _rx) = serve("503 Service Unavailable");
+        let writer = RemoteWriter::new(&url);
+
+        let res = writer.send(vec![]).await;
+        let expected = "503 Service Unavailable".to_owned();
+
+        assert_eq!(res, Err(WriteError::Retryable(expected)));
+    }
+}
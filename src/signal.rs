@@ -0,0 +1,70 @@
+// Signal handling shared by the long running outputs
+#![forbid(unsafe_code)]
+#![forbid(missing_docs)]
+use actix_rt::signal::{
+    ctrl_c,
+    unix::{
+        signal,
+        SignalKind,
+    },
+};
+use actix_rt::time;
+use futures::future::{
+    select,
+    Either,
+};
+use jail_exporter::ExporterError;
+use log::{
+    error,
+    info,
+};
+use std::future::Future;
+use std::time::Duration;
+
+// Resolves when either SIGINT or SIGTERM is received.
+pub async fn shutdown_signal() -> Result<(), ExporterError> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    let interrupt = ctrl_c();
+
+    let signalled = select(Box::pin(terminate.recv()), Box::pin(interrupt))
+        .await;
+
+    if let Either::Right((result, _)) = signalled {
+        result?;
+    }
+
+    Ok(())
+}
+
+// Calls f every interval until we receive SIGINT or SIGTERM. Errors from f
+// are logged as a failure to do what, rather than returned, so a destination
+// that is temporarily down doesn't stop future attempts.
+pub async fn run_every<F, Fut>(interval: Duration, what: &str, mut f: F)
+-> Result<(), ExporterError>
+where
+    F:   FnMut() -> Fut,
+    Fut: Future<Output = Result<(), ExporterError>>,
+{
+    let mut interval = time::interval(interval);
+    let mut shutdown = Box::pin(shutdown_signal());
+
+    loop {
+        match select(Box::pin(interval.tick()), shutdown).await {
+            Either::Left((_, pending)) => {
+                shutdown = pending;
+
+                if let Err(e) = f().await {
+                    error!("Failed to {}: {}", what, e);
+                }
+            },
+            Either::Right((signal, _)) => {
+                signal?;
+                break;
+            },
+        }
+    }
+
+    info!("Shutting down");
+
+    Ok(())
+}
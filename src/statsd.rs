@@ -0,0 +1,334 @@
+// StatsD exporter
+#![forbid(unsafe_code)]
+#![forbid(missing_docs)]
+use crate::signal::run_every;
+use futures::future::ready;
+use jail_exporter::{
+    CounterIncreases,
+    Exporter,
+    ExporterError,
+};
+use log::{
+    debug,
+    info,
+};
+use prometheus::proto::{
+    MetricFamily,
+    MetricType,
+};
+use std::io;
+use std::net::{
+    SocketAddr,
+    ToSocketAddrs,
+    UdpSocket,
+};
+use std::time::Duration;
+
+// Largest datagram we send. Staying below a typical Ethernet MTU avoids
+// fragmentation, which StatsD servers often don't cope with.
+const MAX_PACKET_SIZE: usize = 1432;
+
+// Used for the StatsD builder
+pub struct Statsd {
+    address:  String,
+    interval: Duration,
+    tags:     Vec<String>,
+}
+
+impl Statsd {
+    // Returns a new Statsd sending to the given HOST:PORT.
+    pub fn new(address: &str) -> Self {
+        debug!("New Statsd sending to {}", address);
+
+        Self {
+            address:  address.into(),
+            interval: Duration::from_secs(15),
+            tags:     Vec::new(),
+        }
+    }
+
+    // Sets how often metrics are sent.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        debug!("Setting Statsd interval to: {:?}", interval);
+
+        self.interval = interval;
+        self
+    }
+
+    // Sets static tags added to every metric sent.
+    pub fn tags(mut self, tags: Vec<String>) -> Self {
+        debug!("Setting Statsd tags to: {:?}", tags);
+
+        self.tags = tags;
+        self
+    }
+
+    // Returns a UDP socket connected to the StatsD server.
+    fn connect(&self) -> Result<UdpSocket, ExporterError> {
+        let address = self.address.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::AddrNotAvailable,
+                format!("no addresses found for {}", self.address),
+            )
+        })?;
+
+        let local: SocketAddr = if address.is_ipv4() {
+            "0.0.0.0:0".parse().unwrap()
+        }
+        else {
+            "[::]:0".parse().unwrap()
+        };
+
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(address)?;
+
+        Ok(socket)
+    }
+
+    // Collects metrics with the given exporter and sends them over the given
+    // socket.
+    fn send(&self, socket: &UdpSocket, exporter: &Exporter)
+    -> Result<(), ExporterError> {
+        let (metric_families, increases) = exporter.gather_with_increases()?;
+
+        self.send_metrics(socket, &metric_families, &increases)
+    }
+
+    // Sends the given metrics over the given socket.
+    fn send_metrics(
+        &self,
+        socket: &UdpSocket,
+        metric_families: &[MetricFamily],
+        increases: &CounterIncreases,
+    ) -> Result<(), ExporterError> {
+        let lines = lines(metric_families, increases, &self.tags);
+
+        for packet in packets(&lines) {
+            socket.send(packet.as_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    // Sends metrics every interval until we receive SIGINT or SIGTERM.
+    pub async fn run(self, exporter: &Exporter) -> Result<(), ExporterError> {
+        info!(
+            "Sending StatsD metrics to {} every {:?}",
+            self.address,
+            self.interval,
+        );
+
+        let socket = self.connect()?;
+
+        run_every(self.interval, "send StatsD metrics", || {
+            ready(self.send(&socket, exporter))
+        }).await
+    }
+}
+
+// Replaces characters that would break the DogStatsD line format in a tag.
+fn sanitise_tag(tag: &str) -> String {
+    tag.chars()
+        .map(|c| match c {
+            ',' | '|' | '#' | '\n' => '_',
+            c                      => c,
+        })
+        .collect()
+}
+
+// Converts gathered metric families into StatsD lines. Gauges are sent as
+// their current value, while counters are sent as the increase seen during
+// the collection. Metric labels, such as the jail name, become DogStatsD tags
+// along with the given static tags.
+fn lines(
+    metric_families: &[MetricFamily],
+    increases: &CounterIncreases,
+    static_tags: &[String],
+) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    for family in metric_families {
+        let name = family.get_name();
+
+        for metric in family.get_metric() {
+            let labels = metric.get_label();
+
+            let (value, kind) = match family.get_field_type() {
+                MetricType::COUNTER => {
+                    let jail = labels
+                        .iter()
+                        .find(|pair| pair.get_name() == "name")
+                        .map(|pair| pair.get_value().to_owned());
+
+                    let increase = jail.and_then(|jail| {
+                        increases.get(&(name.to_owned(), jail))
+                    });
+
+                    match increase {
+                        Some(increase) => (*increase as f64, "c"),
+                        None           => continue,
+                    }
+                },
+                MetricType::GAUGE => (metric.get_gauge().get_value(), "g"),
+                _                 => continue,
+            };
+
+            let tags: Vec<String> = labels
+                .iter()
+                .map(|pair| {
+                    sanitise_tag(
+                        &format!("{}:{}", pair.get_name(), pair.get_value()),
+                    )
+                })
+                .chain(static_tags.iter().map(|tag| sanitise_tag(tag)))
+                .collect();
+
+            let mut line = format!("{}:{}|{}", name, value, kind);
+            if !tags.is_empty() {
+                line.push_str("|#");
+                line.push_str(&tags.join(","));
+            }
+
+            lines.push(line);
+        }
+    }
+
+    lines
+}
+
+// Joins lines into newline separated packets no larger than MAX_PACKET_SIZE.
+// A line that is too large on its own is sent in a packet by itself.
+fn packets(lines: &[String]) -> Vec<String> {
+    let mut packets = Vec::new();
+    let mut packet = String::new();
+
+    for line in lines {
+        let len = packet.len() + line.len() + 1;
+
+        if !packet.is_empty() && len > MAX_PACKET_SIZE {
+            packets.push(packet);
+            packet = String::new();
+        }
+
+        if !packet.is_empty() {
+            packet.push('\n');
+        }
+
+        packet.push_str(line);
+    }
+
+    if !packet.is_empty() {
+        packets.push(packet);
+    }
+
+    packets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use prometheus::{
+        IntCounterVec,
+        IntGaugeVec,
+        Opts,
+        Registry,
+    };
+
+    // Returns metric families with a gauge and counter for the jail "test".
+    fn metric_families() -> Vec<MetricFamily> {
+        let registry = Registry::new();
+
+        let opts = Opts::new("jail_memoryuse_bytes", "memory use");
+        let memoryuse = IntGaugeVec::new(opts, &["name"]).unwrap();
+        memoryuse.with_label_values(&["test"]).set(1024);
+        registry.register(Box::new(memoryuse)).unwrap();
+
+        let opts = Opts::new("jail_cputime_seconds_total", "CPU time");
+        let cputime = IntCounterVec::new(opts, &["name"]).unwrap();
+        cputime.with_label_values(&["test"]).inc_by(1020);
+        registry.register(Box::new(cputime)).unwrap();
+
+        registry.gather()
+    }
+
+    fn increases() -> CounterIncreases {
+        let mut increases = CounterIncreases::new();
+        let key = ("jail_cputime_seconds_total".to_owned(), "test".to_owned());
+        increases.insert(key, 20);
+
+        increases
+    }
+
+    #[test]
+    fn lines_counter_delta() {
+        let lines = lines(&metric_families(), &increases(), &[]);
+
+        let ok = vec![
+            "jail_cputime_seconds_total:20|c|#name:test".to_owned(),
+            "jail_memoryuse_bytes:1024|g|#name:test".to_owned(),
+        ];
+
+        assert_eq!(lines, ok);
+    }
+
+    #[test]
+    fn lines_missing_increase() {
+        let lines = lines(&metric_families(), &CounterIncreases::new(), &[]);
+
+        let ok = vec![
+            "jail_memoryuse_bytes:1024|g|#name:test".to_owned(),
+        ];
+
+        assert_eq!(lines, ok);
+    }
+
+    #[test]
+    fn lines_static_tags() {
+        let tags = vec!["env:prod".to_owned(), "role".to_owned()];
+        let lines = lines(&metric_families(), &increases(), &tags);
+
+        assert_eq!(
+            lines[1],
+            "jail_memoryuse_bytes:1024|g|#name:test,env:prod,role",
+        );
+    }
+
+    #[test]
+    fn packets_split() {
+        let line = "a".repeat(1000);
+        let lines = vec![line.clone(), line.clone(), "b:1|g".to_owned()];
+
+        let packets = packets(&lines);
+
+        assert_eq!(packets, vec![line.clone(), format!("{}\nb:1|g", line)]);
+    }
+
+    #[test]
+    fn sanitise_tag_ok() {
+        let tag = sanitise_tag("name:a,b|c#d");
+        assert_eq!(tag, "name:a_b_c_d");
+    }
+
+    #[test]
+    fn send_ok() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = server.local_addr().unwrap().to_string();
+
+        let statsd = Statsd::new(&address).tags(vec!["env:test".into()]);
+        let socket = statsd.connect().unwrap();
+
+        statsd
+            .send_metrics(&socket, &metric_families(), &increases())
+            .unwrap();
+
+        let mut buf = [0; MAX_PACKET_SIZE];
+        let len = server.recv(&mut buf).unwrap();
+        let packet = String::from_utf8(buf[..len].to_vec()).unwrap();
+
+        assert_eq!(packet, concat!(
+            "jail_cputime_seconds_total:20|c|#name:test,env:test\n",
+            "jail_memoryuse_bytes:1024|g|#name:test,env:test",
+        ));
+    }
+}
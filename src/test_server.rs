@@ -0,0 +1,87 @@
+// A minimal HTTP server standing in for the remote ends of our outputs
+#![forbid(unsafe_code)]
+#![forbid(missing_docs)]
+use std::io::{
+    BufRead,
+    BufReader,
+    Read,
+    Write,
+};
+use std::net::TcpListener;
+use std::sync::mpsc;
+use std::thread;
+
+// A request received by the stand-in server.
+pub struct Request {
+    pub body:    Vec<u8>,
+    pub headers: Vec<(String, String)>,
+    pub method:  String,
+    pub path:    String,
+}
+
+impl Request {
+    // Returns the value of the named header, if it was sent.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+// Starts a stand-in server that accepts a single request, replying with the
+// given status line. Returns the base URL and a receiver for the request.
+pub fn serve(status: &'static str) -> (String, mpsc::Receiver<Request>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let (tx, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let mut parts = line.split_whitespace();
+        let method = parts.next().unwrap().to_owned();
+        let path = parts.next().unwrap().to_owned();
+
+        let mut headers = Vec::new();
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).unwrap();
+
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+
+            let (name, value) = header.split_at(header.find(':').unwrap());
+            headers.push((name.to_owned(), value[1..].trim().to_owned()));
+        }
+
+        let request = Request {
+            body: Vec::new(),
+            headers,
+            method,
+            path,
+        };
+
+        let content_length = request
+            .header("content-length")
+            .map_or(0, |length| length.parse().unwrap());
+
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).unwrap();
+
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            status,
+        );
+        reader.get_mut().write_all(response.as_bytes()).unwrap();
+
+        tx.send(Request { body, ..request }).unwrap();
+    });
+
+    (url, rx)
+}